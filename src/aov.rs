@@ -0,0 +1,203 @@
+//! Arbitrary output variables: images of what the camera rays hit first, and of the
+//! direct and indirect parts of the lighting, written next to the final image to debug
+//! shading or feed a denoiser.
+
+use crate::hittable::HitRecord;
+use crate::integrator::Radiance;
+use crate::ray::Ray;
+use crate::vec3::*;
+
+use std::fmt;
+use std::str::FromStr;
+
+/// One arbitrary output variable
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Aov {
+    /// Distance from the camera to the first surface
+    Depth,
+    /// World space surface normal
+    Normal,
+    /// Base color of the first surface's material
+    Albedo,
+    /// World space position of the first hit
+    Position,
+    /// Identifier of the object hit first, in the order objects were added to the scene
+    ObjectId,
+    /// Identifier of the material hit first, see `Material::id`
+    MaterialId,
+    /// Light that reached the first surface straight from an emitter
+    Direct,
+    /// Light that bounced more than once
+    Indirect,
+}
+
+impl Aov {
+    pub const ALL: [Aov; 8] = [
+        Aov::Depth,
+        Aov::Normal,
+        Aov::Albedo,
+        Aov::Position,
+        Aov::ObjectId,
+        Aov::MaterialId,
+        Aov::Direct,
+        Aov::Indirect,
+    ];
+
+    /// Names of the channels of the variable in an OpenEXR file
+    pub fn channels(self) -> &'static [&'static str] {
+        match self {
+            Aov::Depth => &["Z"],
+            Aov::Normal | Aov::Position => &["X", "Y", "Z"],
+            Aov::Albedo | Aov::Direct | Aov::Indirect => &["R", "G", "B"],
+            Aov::ObjectId | Aov::MaterialId => &["id"],
+        }
+    }
+}
+
+impl FromStr for Aov {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "depth" => Ok(Aov::Depth),
+            "normal" => Ok(Aov::Normal),
+            "albedo" => Ok(Aov::Albedo),
+            "position" => Ok(Aov::Position),
+            "object_id" => Ok(Aov::ObjectId),
+            "material_id" => Ok(Aov::MaterialId),
+            "direct" => Ok(Aov::Direct),
+            "indirect" => Ok(Aov::Indirect),
+            _ => Err(format!(
+                "unknown output variable `{}`, expected `depth`, `normal`, `albedo`, `position`, `object_id`, `material_id`, `direct` or `indirect`",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Aov {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Aov::Depth => write!(f, "depth"),
+            Aov::Normal => write!(f, "normal"),
+            Aov::Albedo => write!(f, "albedo"),
+            Aov::Position => write!(f, "position"),
+            Aov::ObjectId => write!(f, "object_id"),
+            Aov::MaterialId => write!(f, "material_id"),
+            Aov::Direct => write!(f, "direct"),
+            Aov::Indirect => write!(f, "indirect"),
+        }
+    }
+}
+
+/// Output variables seen by one camera sample
+#[derive(Copy, Clone, Debug)]
+pub struct AovSample {
+    /// First surface along the camera ray, if any
+    pub hit: Option<AovHit>,
+    pub radiance: Radiance,
+}
+
+/// Properties of the first surface a camera ray hits
+#[derive(Copy, Clone, Debug)]
+pub struct AovHit {
+    pub depth: f32,
+    pub normal: Vec3,
+    pub albedo: Color,
+    pub position: Point3,
+    pub object_id: u32,
+    pub material_id: u32,
+}
+
+impl AovHit {
+    /// Properties of the surface `ray` hit at `rec`
+    pub fn new(ray: Ray, rec: &HitRecord) -> AovHit {
+        AovHit {
+            depth: rec.t * ray.direction.norm(),
+            normal: rec.normal.normalize(),
+            albedo: rec.material.albedo(rec.uv, rec.point),
+            position: rec.point,
+            object_id: rec.object_id,
+            material_id: rec.material.id(),
+        }
+    }
+}
+
+/// Running sums of the output variables of one pixel. Surface properties are averaged
+/// over the samples that hit something, while identifiers are taken from the first one,
+/// since averaging them would produce meaningless values.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AovPixel {
+    pub samples: u32,
+    pub hits: u32,
+    depth_sum: f32,
+    normal_sum: Vec3,
+    albedo_sum: Color,
+    position_sum: Point3,
+    object_id: Option<u32>,
+    material_id: Option<u32>,
+    direct_sum: Color,
+    indirect_sum: Color,
+}
+
+impl Default for AovPixel {
+    fn default() -> Self {
+        let zero = Vec3::new(0.0, 0.0, 0.0);
+        AovPixel {
+            samples: 0,
+            hits: 0,
+            depth_sum: 0.0,
+            normal_sum: zero,
+            albedo_sum: zero,
+            position_sum: zero,
+            object_id: None,
+            material_id: None,
+            direct_sum: zero,
+            indirect_sum: zero,
+        }
+    }
+}
+
+impl AovPixel {
+    pub fn add(&mut self, sample: &AovSample) {
+        self.samples += 1;
+        self.direct_sum += sample.radiance.direct;
+        self.indirect_sum += sample.radiance.indirect;
+        if let Some(hit) = sample.hit {
+            self.hits += 1;
+            self.depth_sum += hit.depth;
+            self.normal_sum += hit.normal;
+            self.albedo_sum += hit.albedo;
+            self.position_sum += hit.position;
+            self.object_id.get_or_insert(hit.object_id);
+            self.material_id.get_or_insert(hit.material_id);
+        }
+    }
+
+    /// Value of a variable, with unused channels set to zero. Pixels where nothing was
+    /// hit have an infinite depth and an identifier of -1.
+    pub fn value(&self, aov: Aov) -> [f32; 3] {
+        let hits = self.hits.max(1) as f32;
+        let samples = self.samples.max(1) as f32;
+        let id = |id: Option<u32>| [id.map_or(-1.0, |id| id as f32), 0.0, 0.0];
+        let vector = |v: Vec3| [v.x, v.y, v.z];
+        match aov {
+            Aov::Depth if self.hits == 0 => [f32::INFINITY, 0.0, 0.0],
+            Aov::Depth => [self.depth_sum / hits, 0.0, 0.0],
+            Aov::Normal => {
+                let normal = self.normal_sum;
+                if normal.norm_squared() > 0.0 {
+                    vector(normal.normalize())
+                } else {
+                    [0.0; 3]
+                }
+            }
+            Aov::Albedo => vector(self.albedo_sum / hits),
+            Aov::Position => vector(self.position_sum / hits),
+            Aov::ObjectId => id(self.object_id),
+            Aov::MaterialId => id(self.material_id),
+            Aov::Direct => vector(self.direct_sum / samples),
+            Aov::Indirect => vector(self.indirect_sum / samples),
+        }
+    }
+}
@@ -0,0 +1,260 @@
+use crate::sampler::Sampler;
+use crate::vec3::*;
+
+use image::codecs::hdr::HdrDecoder;
+use std::f32::consts::PI;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Radiance arriving from directions in which a ray leaves the scene
+#[derive(Clone)]
+pub enum Background {
+    Solid(Color),
+    /// Blend between `bottom` for rays pointing down and `top` for rays pointing up
+    Gradient {
+        bottom: Color,
+        top: Color,
+    },
+    Environment(EnvironmentMap),
+}
+
+/// Direction toward the background chosen to illuminate a surface point
+#[derive(Copy, Clone)]
+pub struct BackgroundSample {
+    /// Unit direction
+    pub wi: Vec3,
+    pub radiance: Color,
+    /// Probability density with respect to solid angle
+    pub pdf: f32,
+}
+
+impl Default for Background {
+    fn default() -> Self {
+        Background::Gradient {
+            bottom: Color::new(1.0, 1.0, 1.0),
+            top: Color::new(0.5, 0.7, 1.0),
+        }
+    }
+}
+
+impl Background {
+    pub fn radiance(&self, direction: Vec3) -> Color {
+        match self {
+            Background::Solid(color) => *color,
+            Background::Gradient { bottom, top } => {
+                let t = 0.5 * (direction.normalize().y + 1.0);
+                (1.0 - t) * bottom + t * top
+            }
+            Background::Environment(map) => map.radiance(direction),
+        }
+    }
+
+    /// Pick a direction toward a bright part of the background. Only environment maps are
+    /// sampled this way; smooth backgrounds are left to material sampling.
+    pub fn sample(&self, sampler: &mut dyn Sampler) -> Option<BackgroundSample> {
+        match self {
+            Background::Environment(map) => map.sample(sampler),
+            _ => None,
+        }
+    }
+
+    /// Solid angle density with which `sample` picks the unit direction `wi`
+    pub fn pdf(&self, wi: Vec3) -> f32 {
+        match self {
+            Background::Environment(map) => map.pdf(wi),
+            _ => 0.0,
+        }
+    }
+}
+
+/// Equirectangular environment map, importance sampled by pixel luminance
+#[derive(Clone)]
+pub struct EnvironmentMap {
+    path: PathBuf,
+    /// Rotation about the vertical axis in degrees
+    pub rotation: f32,
+    /// Scale applied to every pixel
+    pub intensity: f32,
+    width: usize,
+    height: usize,
+    /// Pixels row by row, starting at the top
+    pixels: Arc<[Color]>,
+    /// Running total of the pixel weights within each row
+    conditional: Arc<[f32]>,
+    /// Running total of the row weights
+    marginal: Arc<[f32]>,
+}
+
+impl EnvironmentMap {
+    /// Load a Radiance `.hdr` or OpenEXR `.exr` image
+    pub fn load(path: &Path, rotation: f32, intensity: f32) -> Result<EnvironmentMap, String> {
+        let (width, height, pixels) = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("hdr") => read_hdr(path),
+            Some("exr") => read_exr(path),
+            _ => Err("environment maps must be `.hdr` or `.exr` images".to_string()),
+        }
+        .map_err(|err| format!("{}: {}", path.display(), err))?;
+
+        if width == 0 || height == 0 {
+            return Err(format!("{}: image is empty", path.display()));
+        }
+
+        Ok(EnvironmentMap::new(
+            path.to_path_buf(),
+            width,
+            height,
+            pixels,
+            rotation,
+            intensity,
+        ))
+    }
+
+    fn new(
+        path: PathBuf,
+        width: usize,
+        height: usize,
+        pixels: Vec<Color>,
+        rotation: f32,
+        intensity: f32,
+    ) -> EnvironmentMap {
+        // Rows near the poles cover less solid angle, so their pixels are weighted down
+        let mut conditional = Vec::with_capacity(width * height);
+        let mut marginal = Vec::with_capacity(height);
+        let mut total = 0.0;
+        for y in 0..height {
+            let sin_theta = (PI * (y as f32 + 0.5) / height as f32).sin();
+            let mut row = 0.0;
+            for x in 0..width {
+                row += luminance(pixels[y * width + x]) * sin_theta;
+                conditional.push(row);
+            }
+            total += row;
+            marginal.push(total);
+        }
+
+        EnvironmentMap {
+            path,
+            rotation,
+            intensity,
+            width,
+            height,
+            pixels: pixels.into(),
+            conditional: conditional.into(),
+            marginal: marginal.into(),
+        }
+    }
+
+    /// File the map was loaded from
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn radiance(&self, direction: Vec3) -> Color {
+        let (u, v) = self.uv(direction);
+        let x = ((u * self.width as f32) as usize).min(self.width - 1);
+        let y = ((v * self.height as f32) as usize).min(self.height - 1);
+        self.intensity * self.pixels[y * self.width + x]
+    }
+
+    fn sample(&self, sampler: &mut dyn Sampler) -> Option<BackgroundSample> {
+        let total = self.marginal[self.height - 1];
+        if total <= 0.0 {
+            return None;
+        }
+
+        let (pick_row, pick_column) = sampler.next_2d();
+        let y = pick(&self.marginal, pick_row * total);
+        let row = &self.conditional[y * self.width..(y + 1) * self.width];
+        let x = pick(row, pick_column * row[self.width - 1]);
+
+        let (jitter_x, jitter_y) = sampler.next_2d();
+        let u = (x as f32 + jitter_x) / self.width as f32;
+        let v = (y as f32 + jitter_y) / self.height as f32;
+        let wi = self.direction(u, v);
+
+        Some(BackgroundSample {
+            wi,
+            radiance: self.radiance(wi),
+            pdf: self.pdf(wi),
+        })
+    }
+
+    fn pdf(&self, wi: Vec3) -> f32 {
+        let total = self.marginal[self.height - 1];
+        let (u, v) = self.uv(wi);
+        let sin_theta = (PI * v).sin();
+        if total <= 0.0 || sin_theta <= 0.0 {
+            return 0.0;
+        }
+
+        let x = ((u * self.width as f32) as usize).min(self.width - 1);
+        let y = ((v * self.height as f32) as usize).min(self.height - 1);
+        let weight = luminance(self.pixels[y * self.width + x])
+            * (PI * (y as f32 + 0.5) / self.height as f32).sin();
+
+        // Density over the unit square of the map, converted to solid angle
+        let pdf_uv = weight / total * (self.width * self.height) as f32;
+        pdf_uv / (2.0 * PI * PI * sin_theta)
+    }
+
+    /// Map coordinates of a direction, with `v = 0` straight up
+    fn uv(&self, direction: Vec3) -> (f32, f32) {
+        let d = direction.normalize();
+        let phi = d.x.atan2(-d.z) - self.rotation.to_radians();
+        let u = (phi / (2.0 * PI) + 0.5).rem_euclid(1.0);
+        let v = d.y.clamp(-1.0, 1.0).acos() / PI;
+        (u, v)
+    }
+
+    /// Direction seen at map coordinates `(u, v)`
+    fn direction(&self, u: f32, v: f32) -> Vec3 {
+        let phi = (u - 0.5) * 2.0 * PI + self.rotation.to_radians();
+        let theta = v * PI;
+        Vec3::new(
+            theta.sin() * phi.sin(),
+            theta.cos(),
+            -theta.sin() * phi.cos(),
+        )
+    }
+}
+
+/// Index of the first entry of a running total that exceeds `value`
+fn pick(cdf: &[f32], value: f32) -> usize {
+    cdf.partition_point(|&total| total <= value)
+        .min(cdf.len() - 1)
+}
+
+pub(crate) fn read_hdr(path: &Path) -> Result<(usize, usize, Vec<Color>), String> {
+    let file = File::open(path).map_err(|err| err.to_string())?;
+    let decoder = HdrDecoder::new(BufReader::new(file)).map_err(|err| err.to_string())?;
+    let metadata = decoder.metadata();
+    let pixels = decoder
+        .read_image_hdr()
+        .map_err(|err| err.to_string())?
+        .into_iter()
+        .map(|p| Color::new(p[0], p[1], p[2]))
+        .collect();
+    Ok((metadata.width as usize, metadata.height as usize, pixels))
+}
+
+pub(crate) fn read_exr(path: &Path) -> Result<(usize, usize, Vec<Color>), String> {
+    let image = exr::prelude::read_first_rgba_layer_from_file(
+        path,
+        |resolution, _| {
+            (
+                resolution.width(),
+                vec![Color::new(0.0, 0.0, 0.0); resolution.width() * resolution.height()],
+            )
+        },
+        |(width, pixels), position, (r, g, b, _a): (f32, f32, f32, f32)| {
+            pixels[position.y() * *width + position.x()] = Color::new(r, g, b);
+        },
+    )
+    .map_err(|err| err.to_string())?;
+
+    let size = image.layer_data.size;
+    let (_, pixels) = image.layer_data.channel_data.pixels;
+    Ok((size.width(), size.height(), pixels))
+}
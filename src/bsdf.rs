@@ -0,0 +1,307 @@
+//! Bidirectional scattering distribution functions.
+//!
+//! Materials turn into a `Bsdf` at every hit (`Material::bsdf`), with their textures looked
+//! up and their normal maps applied. Directions are unit vectors in world space that point
+//! away from the surface: `wo` toward the viewer and `wi` toward where the light comes from.
+//!
+//! Specular lobes scatter into single directions, so `eval` and `pdf` never see them and
+//! they can only be sampled. Their samples carry the `SPECULAR` flag, and their `pdf` is the
+//! probability of picking the lobe rather than a density.
+
+use crate::principled::Principled;
+use crate::vec3::*;
+
+use std::f32::consts::PI;
+use std::ops::BitOr;
+
+/// Kinds of scattering, combined with `|`
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct LobeFlags(u8);
+
+impl LobeFlags {
+    /// Light leaves on the side it arrived from
+    pub const REFLECTION: LobeFlags = LobeFlags(1);
+    /// Light passes through the surface
+    pub const TRANSMISSION: LobeFlags = LobeFlags(1 << 1);
+    pub const DIFFUSE: LobeFlags = LobeFlags(1 << 2);
+    pub const GLOSSY: LobeFlags = LobeFlags(1 << 3);
+    pub const SPECULAR: LobeFlags = LobeFlags(1 << 4);
+
+    /// Whether all the flags of `other` are set
+    pub fn contains(self, other: LobeFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn is_specular(self) -> bool {
+        self.contains(LobeFlags::SPECULAR)
+    }
+}
+
+impl BitOr for LobeFlags {
+    type Output = LobeFlags;
+
+    fn bitor(self, other: LobeFlags) -> LobeFlags {
+        LobeFlags(self.0 | other.0)
+    }
+}
+
+/// Direction picked by `Bsdf::sample`
+#[derive(Copy, Clone, Debug)]
+pub struct BsdfSample {
+    pub wi: Vec3,
+    /// Factor by which the light arriving from `wi` is scaled toward `wo`, the value of
+    /// `eval` over `pdf` for lobes that are not specular
+    pub weight: Color,
+    /// Solid angle density of `wi`, or the probability of the lobe for specular ones
+    pub pdf: f32,
+    pub lobe: LobeFlags,
+}
+
+/// Outcome of sampling a BSDF
+#[derive(Copy, Clone, Debug)]
+pub enum Sampled {
+    Scattered(BsdfSample),
+    /// No light leaves in the sampled direction, which ends the path
+    Absorbed,
+}
+
+/// Scattering at one point of a surface
+#[derive(Copy, Clone, Debug)]
+pub struct Bsdf {
+    /// Unit shading normal, on the outside of the surface
+    pub normal: Vec3,
+    pub model: BsdfModel,
+}
+
+/// Scattering models, with the parameters of the material at the hit
+#[derive(Copy, Clone, Debug)]
+pub enum BsdfModel {
+    Lambertian {
+        albedo: Color,
+    },
+    /// Mirror reflection blurred by offsetting it inside a sphere of radius `fuzz`, which
+    /// has no density to evaluate and is sampled as a specular lobe
+    Metal {
+        albedo: Color,
+        fuzz: f32,
+    },
+    Dielectric {
+        albedo: Color,
+        refraction_index: f32,
+    },
+    /// Energy normalized Blinn-Phong
+    Phong {
+        albedo: Color,
+        diffuse: f32,
+        specular: f32,
+        shininess: f32,
+    },
+    Principled(Principled),
+    /// Absorbs all the light that arrives, as emitters do
+    Black,
+}
+
+impl Bsdf {
+    /// Normal on the side of the surface `wo` points to
+    fn facing_normal(&self, wo: Vec3) -> Vec3 {
+        if self.normal.dot(&wo) < 0.0 {
+            -self.normal
+        } else {
+            self.normal
+        }
+    }
+
+    /// Light scattered toward `wo` for each unit of light arriving from `wi`, including the
+    /// cosine term. Black for specular lobes.
+    pub fn eval(&self, wo: Vec3, wi: Vec3) -> Color {
+        let cosine = self.facing_normal(wo).dot(&wi).max(0.0);
+        match self.model {
+            BsdfModel::Lambertian { albedo } => albedo * (cosine / PI),
+            BsdfModel::Phong { .. } => self.phong(wo, wi) * cosine,
+            BsdfModel::Principled(principled) => principled.eval(wo, wi, self.normal),
+            BsdfModel::Metal { .. } | BsdfModel::Dielectric { .. } | BsdfModel::Black => {
+                Color::new(0.0, 0.0, 0.0)
+            }
+        }
+    }
+
+    /// Solid angle density with which `sample` picks `wi`. Zero for specular lobes.
+    pub fn pdf(&self, wo: Vec3, wi: Vec3) -> f32 {
+        match self.model {
+            BsdfModel::Lambertian { .. } | BsdfModel::Phong { .. } => {
+                self.facing_normal(wo).dot(&wi).max(0.0) / PI
+            }
+            BsdfModel::Principled(principled) => principled.pdf(wo, wi, self.normal),
+            BsdfModel::Metal { .. } | BsdfModel::Dielectric { .. } | BsdfModel::Black => 0.0,
+        }
+    }
+
+    /// Pick the direction light arrives from with the uniform numbers `u`. Samples that
+    /// carry no light are absorbed.
+    pub fn sample(&self, wo: Vec3, u: [f32; 3]) -> Sampled {
+        let sample = match self.model {
+            BsdfModel::Lambertian { .. } | BsdfModel::Phong { .. } => {
+                // Cosine weighted around the side of the surface the light leaves from
+                let wi = cosine_direction(self.facing_normal(wo), (u[1], u[2])).normalize();
+                self.weighted(wo, wi, LobeFlags::DIFFUSE | LobeFlags::REFLECTION)
+            }
+
+            BsdfModel::Metal { albedo, fuzz } => {
+                let wi = reflect(-wo, self.normal) + fuzz * in_unit_sphere(u);
+                if wi.dot(&self.facing_normal(wo)) <= 0.0 {
+                    return Sampled::Absorbed;
+                }
+                Some(BsdfSample {
+                    wi: wi.normalize(),
+                    weight: albedo,
+                    pdf: 1.0,
+                    lobe: LobeFlags::SPECULAR | LobeFlags::REFLECTION,
+                })
+            }
+
+            BsdfModel::Dielectric {
+                albedo,
+                refraction_index,
+            } => {
+                // Rays traced from outside refract into the material, and back out from inside
+                let (normal, etai_over_etat) = if self.normal.dot(&wo) < 0.0 {
+                    (-self.normal, refraction_index)
+                } else {
+                    (self.normal, 1.0 / refraction_index)
+                };
+
+                let cos_theta = wo.dot(&normal).min(1.0);
+                let sin_theta = (1.0 - cos_theta.powi(2)).sqrt();
+                let reflect_probability = if etai_over_etat * sin_theta > 1.0 {
+                    1.0
+                } else {
+                    reflectance(cos_theta, etai_over_etat)
+                };
+
+                let (wi, pdf, side) = if u[0] < reflect_probability {
+                    (
+                        reflect(-wo, normal),
+                        reflect_probability,
+                        LobeFlags::REFLECTION,
+                    )
+                } else {
+                    (
+                        refract(-wo, normal, etai_over_etat),
+                        1.0 - reflect_probability,
+                        LobeFlags::TRANSMISSION,
+                    )
+                };
+                Some(BsdfSample {
+                    wi: wi.normalize(),
+                    weight: albedo,
+                    pdf,
+                    lobe: LobeFlags::SPECULAR | side,
+                })
+            }
+
+            BsdfModel::Principled(principled) => principled
+                .sample(wo, self.normal, u)
+                .and_then(|(wi, lobe)| self.weighted(wo, wi, lobe)),
+
+            BsdfModel::Black => None,
+        };
+
+        match sample {
+            Some(sample) if sample.weight != Color::new(0.0, 0.0, 0.0) => {
+                Sampled::Scattered(sample)
+            }
+            _ => Sampled::Absorbed,
+        }
+    }
+
+    /// Sample of a lobe that is not specular, weighted by the density of all of them
+    fn weighted(&self, wo: Vec3, wi: Vec3, lobe: LobeFlags) -> Option<BsdfSample> {
+        let pdf = self.pdf(wo, wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            wi,
+            weight: self.eval(wo, wi) / pdf,
+            pdf,
+            lobe,
+        })
+    }
+
+    /// Blinn-Phong BRDF for light arriving from `wi`, without the cosine term
+    fn phong(&self, wo: Vec3, wi: Vec3) -> Color {
+        match self.model {
+            BsdfModel::Phong {
+                albedo,
+                diffuse,
+                specular,
+                shininess,
+            } => {
+                let half = (wi + wo).normalize();
+                let highlight = (shininess + 8.0) / (8.0 * PI)
+                    * self.facing_normal(wo).dot(&half).max(0.0).powf(shininess);
+                diffuse * albedo / PI + Color::repeat(specular * highlight)
+            }
+            _ => Color::new(0.0, 0.0, 0.0),
+        }
+    }
+}
+
+/// Point in the unit sphere for the uniform numbers `u`, the first of which sets the radius
+fn in_unit_sphere(u: [f32; 3]) -> Vec3 {
+    let z = 1.0 - 2.0 * u[1];
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * u[2];
+    u[0].cbrt() * Vec3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+/// Schlick's approximation of the reflectance of a dielectric
+fn reflectance(cosine: f32, refraction_index: f32) -> f32 {
+    let r0 = (1.0 - refraction_index) / (1.0 + refraction_index);
+    let r0_squared = r0.powi(2);
+    r0_squared + (1.0 - r0_squared) * (1.0 - cosine).powi(5)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Direction a ray traced back along `-wo` continues in after refracting at the surface
+    /// of a glass with normal `+z` and index of refraction 1.5
+    fn refracted(wo: Vec3) -> Vec3 {
+        let bsdf = Bsdf {
+            normal: Vec3::new(0.0, 0.0, 1.0),
+            model: BsdfModel::Dielectric {
+                albedo: Color::new(1.0, 1.0, 1.0),
+                refraction_index: 1.5,
+            },
+        };
+        match bsdf.sample(wo, [0.999, 0.5, 0.5]) {
+            Sampled::Scattered(sample) => {
+                assert!(sample.lobe.contains(LobeFlags::TRANSMISSION));
+                sample.wi
+            }
+            Sampled::Absorbed => panic!("glass absorbed the ray"),
+        }
+    }
+
+    /// Sine of the angle between `w` and the normal
+    fn sine(w: Vec3) -> f32 {
+        w.x.hypot(w.y) / w.norm()
+    }
+
+    #[test]
+    fn dielectric_refracts_by_snells_law() {
+        let angle = 0.5f32;
+        // Into the glass, the ray bends toward the normal
+        let outside = Vec3::new(angle.sin(), 0.0, angle.cos());
+        let inside = refracted(outside);
+        assert!(inside.z < 0.0);
+        assert!((sine(inside) - angle.sin() / 1.5).abs() < 1.0e-5);
+
+        // Out of the glass, it bends away from it
+        let outside = refracted(Vec3::new(angle.sin(), 0.0, -angle.cos()));
+        assert!(outside.z > 0.0);
+        assert!((sine(outside) - angle.sin() * 1.5).abs() < 1.0e-5);
+    }
+}
@@ -1,7 +1,10 @@
 use crate::ray::Ray;
+use crate::sampler::Sampler;
 use crate::vec3::*;
 
 use rand_distr::{Distribution, UnitDisc};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
 
 // TODO: refactor both camera models into a single enum
 
@@ -35,6 +38,119 @@ pub struct PerspectiveCamera {
     lens_radius: f32,
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Projection {
+    Perspective,
+    Orthographic,
+}
+
+impl FromStr for Projection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "perspective" => Ok(Projection::Perspective),
+            "orthographic" => Ok(Projection::Orthographic),
+            _ => Err(format!(
+                "unknown projection `{}`, expected `perspective` or `orthographic`",
+                s
+            )),
+        }
+    }
+}
+
+/// Parameters a `Camera` is built from, independent of the image aspect ratio
+#[derive(Copy, Clone, Debug)]
+pub struct CameraSettings {
+    pub projection: Projection,
+    pub lookfrom: Point3,
+    pub lookat: Point3,
+    pub vup: Vec3,
+    pub vfov: f32,
+    pub aperture: f32,
+    pub focal_length: f32,
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        CameraSettings {
+            projection: Projection::Perspective,
+            lookfrom: Point3::new(0.0, 0.0, 0.0),
+            lookat: Point3::new(0.0, 0.0, -1.0),
+            vup: Vec3::new(0.0, 1.0, 0.0),
+            vfov: 27.5,
+            aperture: 0.1,
+            focal_length: 10.0,
+        }
+    }
+}
+
+impl CameraSettings {
+    pub fn build(&self, aspect_ratio: f32) -> Camera {
+        match self.projection {
+            Projection::Perspective => Camera::Perspective(PerspectiveCamera::new(
+                self.lookfrom,
+                self.lookat,
+                self.vup,
+                self.vfov,
+                aspect_ratio,
+                self.aperture,
+                self.focal_length,
+            )),
+            Projection::Orthographic => Camera::Orthographic(OrthographicCamera::new(
+                self.lookfrom,
+                self.lookat,
+                self.vup,
+                self.vfov,
+                aspect_ratio,
+                self.aperture,
+                self.focal_length,
+            )),
+        }
+    }
+
+    /// Check for settings that would produce a degenerate camera basis
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.vfov > 0.0 && self.vfov < 180.0) {
+            return Err(format!(
+                "vertical field of view must be between 0 and 180 degrees, got {}",
+                self.vfov
+            ));
+        }
+        if self.aperture < 0.0 {
+            return Err(format!(
+                "aperture cannot be negative, got {}",
+                self.aperture
+            ));
+        }
+        if self.focal_length <= 0.0 {
+            return Err(format!(
+                "focal length must be positive, got {}",
+                self.focal_length
+            ));
+        }
+        let view = self.lookat - self.lookfrom;
+        if view.norm() == 0.0 {
+            return Err("lookfrom and lookat must be different points".to_string());
+        }
+        if view.cross(&self.vup).norm() == 0.0 {
+            return Err("vup cannot be parallel to the viewing direction".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl Camera {
+    /// Ray through the film coordinates `(s, t)`, with the lens position drawn from `sampler`
+    pub fn get_ray(&self, s: f32, t: f32, sampler: &mut dyn Sampler) -> Ray {
+        match self {
+            Camera::Orthographic(cam) => cam.get_ray(s, t, sampler),
+            Camera::Perspective(cam) => cam.get_ray(s, t, sampler),
+        }
+    }
+}
+
 impl PerspectiveCamera {
     pub fn new(
         lookfrom: Point3,
@@ -72,8 +188,8 @@ impl PerspectiveCamera {
         }
     }
 
-    pub fn get_ray(&self, s: f32, t: f32) -> Ray {
-        let rd = self.lens_radius * random_in_unit_disc();
+    pub fn get_ray(&self, s: f32, t: f32, sampler: &mut dyn Sampler) -> Ray {
+        let rd = self.lens_radius * random_in_unit_disc(sampler);
         let offset = self.u * rd.x + self.v * rd.y;
 
         Ray {
@@ -122,8 +238,8 @@ impl OrthographicCamera {
         }
     }
 
-    pub fn get_ray(&self, s: f32, t: f32) -> Ray {
-        let rd = self.lens_radius * random_in_unit_disc();
+    pub fn get_ray(&self, s: f32, t: f32, sampler: &mut dyn Sampler) -> Ray {
+        let rd = self.lens_radius * random_in_unit_disc(sampler);
         let offset = self.u * rd.x + self.v * rd.y;
 
         Ray {
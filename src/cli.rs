@@ -0,0 +1,478 @@
+use traci::aov::Aov;
+use traci::camera::{CameraSettings, Projection};
+use traci::denoise::{Denoiser, DenoiserType};
+use traci::filter::{Filter, FilterType};
+use traci::integrator::Integrator;
+use traci::render::{Adaptive, Budget, Progressive, Settings};
+use traci::sampler::SamplerType;
+use traci::tile::TileOrder;
+use traci::tonemap::{BitDepth, OutputTransform, ToneMap};
+use traci::vec3::*;
+
+use clap::{Args, Parser, Subcommand};
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Parser, Debug)]
+#[command(name = "traci", version, about = "A multi-core CPU ray tracer")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Render a scene to an image file
+    Render(RenderArgs),
+    /// Print a summary of a scene and the resolved render settings
+    Info(RenderArgs),
+    /// Write a scene, including generated ones, to a `.toml` or `.json` scene file
+    Export {
+        #[command(flatten)]
+        render: RenderArgs,
+
+        /// Scene file to write
+        destination: PathBuf,
+    },
+    /// Render a scene repeatedly and report timings
+    Bench {
+        #[command(flatten)]
+        render: RenderArgs,
+
+        /// Number of timed renders
+        #[arg(long, default_value_t = 3)]
+        iterations: u32,
+    },
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct RenderArgs {
+    /// Builtin scene name (grid, random, test, simple, teapot) or path to a `.toml`, `.json` or `.obj` file
+    #[arg(short, long, default_value = "grid")]
+    pub scene: String,
+
+    /// Output image path; repeat to write several files. `.exr`, `.pfm` and `.hdr` files
+    /// keep the linear high dynamic range colors, other formats are 8-bit.
+    #[arg(short, long, default_value = "images/out.png")]
+    pub output: Vec<PathBuf>,
+
+    /// Exposure adjustment in stops applied before tone mapping
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    pub exposure: f32,
+
+    /// Tone mapping operator for 8 and 16-bit outputs (clamp, reinhard, reinhard-extended,
+    /// aces or agx)
+    #[arg(long, default_value_t = ToneMap::Clamp)]
+    pub tonemap: ToneMap,
+
+    /// Luminance mapped to white by the extended Reinhard operator
+    #[arg(long, default_value_t = 4.0)]
+    pub white_point: f32,
+
+    /// Dither 8 and 16-bit outputs to hide banding
+    #[arg(long)]
+    pub dither: bool,
+
+    /// Bits per channel of PNG and other integer outputs (8 or 16)
+    #[arg(long, default_value_t = BitDepth::Eight)]
+    pub bit_depth: BitDepth,
+
+    /// Image width in pixels
+    #[arg(short = 'W', long, default_value_t = 400)]
+    pub width: u32,
+
+    /// Image height in pixels (derived from the aspect ratio if omitted)
+    #[arg(short = 'H', long)]
+    pub height: Option<u32>,
+
+    /// Aspect ratio as a number or `W:H` (derived from width and height if both are given)
+    #[arg(long, value_parser = parse_aspect_ratio)]
+    pub aspect_ratio: Option<f32>,
+
+    /// Samples per pixel, or the target sample count of a progressive render [default: 10]
+    #[arg(long)]
+    pub spp: Option<u32>,
+
+    /// Render in passes, accumulating samples until a budget runs out. Implied by
+    /// `--time-limit` and `--noise-threshold`.
+    #[arg(long)]
+    pub progressive: bool,
+
+    /// Stop a progressive render after this many seconds
+    #[arg(long)]
+    pub time_limit: Option<f64>,
+
+    /// Stop a progressive render once the relative error of every pixel is below this value
+    #[arg(long)]
+    pub noise_threshold: Option<f32>,
+
+    /// Samples per pixel added by each progressive pass
+    #[arg(long, default_value_t = 4)]
+    pub pass_spp: u32,
+
+    /// Seconds between writes of the intermediate image during a progressive render
+    #[arg(long, default_value_t = 10.0)]
+    pub flush_interval: f64,
+
+    /// Maximum ray bounce depth
+    #[arg(long, default_value_t = 50)]
+    pub max_depth: u32,
+
+    /// Shading model used to compute ray colors (naive, phong or path)
+    #[arg(long, default_value_t = Integrator::Phong)]
+    pub integrator: Integrator,
+
+    /// Sample noisy pixels more than flat ones, with `--spp` as the most any pixel receives
+    #[arg(long)]
+    pub adaptive: bool,
+
+    /// Relative error below which adaptive sampling considers a pixel converged
+    #[arg(long, default_value_t = 0.05)]
+    pub adaptive_threshold: f32,
+
+    /// Samples every pixel receives before adaptive sampling estimates its error
+    #[arg(long, default_value_t = 8)]
+    pub min_spp: u32,
+
+    /// Output variables to write next to the image, separated by commas (depth, normal,
+    /// albedo, position, object_id, material_id, direct, indirect or all). `.exr` outputs
+    /// store them as extra channels, other formats as one image per variable.
+    #[arg(long, value_delimiter = ',', value_parser = parse_aovs)]
+    pub aovs: Vec<Vec<Aov>>,
+
+    /// Denoise the image before tone mapping (bilateral, nlm or atrous), guided by the
+    /// albedo and normal output variables
+    #[arg(long)]
+    pub denoise: Option<DenoiserType>,
+
+    /// How strongly the denoiser smooths; larger values remove more noise and more detail
+    #[arg(long, default_value_t = 1.0)]
+    pub denoise_strength: f32,
+
+    /// Also write an image of how many samples each pixel received
+    #[arg(long)]
+    pub heatmap: Option<PathBuf>,
+
+    /// Width and height in pixels of the tiles the image is split into
+    #[arg(long, default_value_t = 16)]
+    pub tile_size: u32,
+
+    /// Order in which tiles are rendered (scanline, spiral or hilbert)
+    #[arg(long, default_value_t = TileOrder::Scanline)]
+    pub tile_order: TileOrder,
+
+    /// Seed for the random numbers used to generate and render the scene
+    #[arg(long, default_value_t = 0)]
+    pub seed: u64,
+
+    /// Sample generator for pixel, lens, light and scattering decisions (independent,
+    /// stratified, cmj, halton or sobol)
+    #[arg(long, default_value_t = SamplerType::MultiJittered)]
+    pub sampler: SamplerType,
+
+    /// Pixel reconstruction filter (box, tent, gaussian, mitchell or lanczos)
+    #[arg(long, default_value_t = FilterType::Box)]
+    pub filter: FilterType,
+
+    /// Radius of the reconstruction filter in pixels [default: depends on the filter]
+    #[arg(long)]
+    pub filter_radius: Option<f32>,
+
+    /// Skip building the bounding volume hierarchy
+    #[arg(long)]
+    pub no_bvh: bool,
+
+    #[command(flatten)]
+    pub camera: CameraArgs,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct CameraArgs {
+    /// Camera projection model (perspective or orthographic)
+    #[arg(long)]
+    pub projection: Option<Projection>,
+
+    /// Camera position as `x,y,z` [default: 0,0,0]
+    #[arg(long, value_parser = parse_vec3, allow_hyphen_values = true)]
+    pub lookfrom: Option<Point3>,
+
+    /// Point the camera looks at as `x,y,z` [default: 0,0,-1]
+    #[arg(long, value_parser = parse_vec3, allow_hyphen_values = true)]
+    pub lookat: Option<Point3>,
+
+    /// Camera up direction as `x,y,z` [default: 0,1,0]
+    #[arg(long, value_parser = parse_vec3, allow_hyphen_values = true)]
+    pub vup: Option<Vec3>,
+
+    /// Vertical field of view in degrees [default: 27.5]
+    #[arg(long)]
+    pub vfov: Option<f32>,
+
+    /// Lens aperture, 0 for a pinhole camera [default: 0.1]
+    #[arg(long)]
+    pub aperture: Option<f32>,
+
+    /// Distance to the plane of perfect focus [default: 10]
+    #[arg(long)]
+    pub focal_length: Option<f32>,
+}
+
+impl RenderArgs {
+    /// Aspect ratio after taking an explicit height into account. The film is always
+    /// `width` by `height`, so an explicit height wins over `--aspect-ratio` to keep the
+    /// image from being stretched.
+    pub fn resolved_aspect_ratio(&self) -> f32 {
+        match (self.aspect_ratio, self.height) {
+            (_, Some(height)) => self.width as f32 / height as f32,
+            (Some(aspect_ratio), None) => aspect_ratio,
+            (None, None) => 1.0,
+        }
+    }
+
+    /// Image height after taking the aspect ratio into account
+    pub fn resolved_height(&self) -> u32 {
+        match self.height {
+            Some(height) => height,
+            None => (self.width as f32 / self.resolved_aspect_ratio()) as u32,
+        }
+    }
+
+    pub fn samples_per_pixel(&self) -> u32 {
+        self.spp.unwrap_or(10)
+    }
+
+    pub fn is_progressive(&self) -> bool {
+        self.progressive || self.time_limit.is_some() || self.noise_threshold.is_some()
+    }
+
+    /// Progressive render parameters, where only an explicit `--spp` limits the samples
+    pub fn progressive(&self) -> Progressive {
+        Progressive {
+            budget: Budget {
+                time: self.time_limit.map(Duration::from_secs_f64),
+                samples_per_pixel: self.spp,
+                noise: self.noise_threshold,
+            },
+            samples_per_pass: self.pass_spp,
+            flush_interval: Duration::from_secs_f64(self.flush_interval),
+        }
+    }
+
+    /// Reconstruction filter, with the filter's own default radius unless one is given
+    pub fn filter(&self) -> Filter {
+        let radius = self
+            .filter_radius
+            .unwrap_or_else(|| self.filter.default_radius());
+        Filter::new(self.filter, radius)
+    }
+
+    /// Requested output variables, in order and without repeats
+    pub fn aovs(&self) -> Vec<Aov> {
+        let mut aovs: Vec<Aov> = Vec::new();
+        for &aov in self.aovs.iter().flatten() {
+            if !aovs.contains(&aov) {
+                aovs.push(aov);
+            }
+        }
+        aovs
+    }
+
+    pub fn denoiser(&self) -> Option<Denoiser> {
+        self.denoise
+            .map(|kind| Denoiser::new(kind, self.denoise_strength))
+    }
+
+    pub fn output_transform(&self) -> OutputTransform {
+        OutputTransform {
+            exposure: self.exposure,
+            tone_map: self.tonemap,
+            white_point: self.white_point,
+            dither: self.dither,
+            bit_depth: self.bit_depth,
+        }
+    }
+
+    pub fn settings(&self) -> Settings {
+        Settings {
+            width: self.width,
+            height: self.resolved_height(),
+            samples_per_pixel: self.samples_per_pixel(),
+            max_depth: self.max_depth,
+            integrator: self.integrator,
+            tile_size: self.tile_size,
+            tile_order: self.tile_order,
+            seed: self.seed,
+            sampler: self.sampler,
+            filter: self.filter(),
+            aovs: !self.aovs.is_empty() || self.denoise.is_some(),
+            adaptive: if self.adaptive {
+                Some(Adaptive {
+                    min_samples: self.min_spp,
+                    threshold: self.adaptive_threshold,
+                })
+            } else {
+                None
+            },
+        }
+    }
+
+    /// Check the arguments for values that would produce an empty or degenerate render
+    pub fn validate(&self) -> Result<(), String> {
+        if self.width == 0 {
+            return Err("image width must be at least 1 pixel".to_string());
+        }
+        if self.height == Some(0) {
+            return Err("image height must be at least 1 pixel".to_string());
+        }
+        if self.resolved_height() == 0 {
+            return Err(format!(
+                "aspect ratio {} gives an image height of 0 pixels at width {}",
+                self.resolved_aspect_ratio(),
+                self.width
+            ));
+        }
+        if self.spp == Some(0) {
+            return Err("samples per pixel must be at least 1".to_string());
+        }
+        if self.max_depth == 0 {
+            return Err("max depth must be at least 1".to_string());
+        }
+        if self.pass_spp == 0 {
+            return Err("samples per pass must be at least 1".to_string());
+        }
+        if !(self.flush_interval >= 0.0 && self.flush_interval.is_finite()) {
+            return Err(format!(
+                "flush interval must be a non-negative number of seconds, got {}",
+                self.flush_interval
+            ));
+        }
+        if let Some(time_limit) = self.time_limit {
+            if !(time_limit > 0.0 && time_limit.is_finite()) {
+                return Err(format!(
+                    "time limit must be a positive number of seconds, got {}",
+                    time_limit
+                ));
+            }
+        }
+        if let Some(noise) = self.noise_threshold {
+            if noise.is_nan() || noise <= 0.0 {
+                return Err(format!("noise threshold must be positive, got {}", noise));
+            }
+        }
+        if self.adaptive_threshold.is_nan() || self.adaptive_threshold <= 0.0 {
+            return Err(format!(
+                "adaptive threshold must be positive, got {}",
+                self.adaptive_threshold
+            ));
+        }
+        if !self.exposure.is_finite() {
+            return Err(format!(
+                "exposure must be a finite number of stops, got {}",
+                self.exposure
+            ));
+        }
+        if !(self.white_point > 0.0 && self.white_point.is_finite()) {
+            return Err(format!(
+                "white point must be positive, got {}",
+                self.white_point
+            ));
+        }
+        if !(self.denoise_strength > 0.0 && self.denoise_strength.is_finite()) {
+            return Err(format!(
+                "denoise strength must be positive, got {}",
+                self.denoise_strength
+            ));
+        }
+        if let Some(radius) = self.filter_radius {
+            if !(radius > 0.0 && radius.is_finite()) {
+                return Err(format!("filter radius must be positive, got {}", radius));
+            }
+        }
+        if self.tile_size == 0 {
+            return Err("tile size must be at least 1 pixel".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl CameraArgs {
+    /// Override the given settings with any camera options passed on the command line
+    pub fn apply(&self, settings: &mut CameraSettings) {
+        if let Some(projection) = self.projection {
+            settings.projection = projection;
+        }
+        if let Some(lookfrom) = self.lookfrom {
+            settings.lookfrom = lookfrom;
+        }
+        if let Some(lookat) = self.lookat {
+            settings.lookat = lookat;
+        }
+        if let Some(vup) = self.vup {
+            settings.vup = vup;
+        }
+        if let Some(vfov) = self.vfov {
+            settings.vfov = vfov;
+        }
+        if let Some(aperture) = self.aperture {
+            settings.aperture = aperture;
+        }
+        if let Some(focal_length) = self.focal_length {
+            settings.focal_length = focal_length;
+        }
+    }
+}
+
+/// Parse a vector written as `x,y,z`
+pub fn parse_vec3(s: &str) -> Result<Vec3, String> {
+    let parts: Vec<&str> = s.split(',').map(str::trim).collect();
+    if parts.len() != 3 {
+        return Err(format!(
+            "expected three comma separated numbers like `0,1,0`, got `{}`",
+            s
+        ));
+    }
+
+    let mut xyz = [0.0; 3];
+    for (i, part) in parts.iter().enumerate() {
+        xyz[i] = part
+            .parse::<f32>()
+            .map_err(|_| format!("`{}` is not a number", part))?;
+    }
+    Ok(Vec3::new(xyz[0], xyz[1], xyz[2]))
+}
+
+/// Parse an aspect ratio written either as a number (`1.78`) or a ratio (`16:9`)
+pub fn parse_aspect_ratio(s: &str) -> Result<f32, String> {
+    let ratio = match s.split_once(':') {
+        Some((w, h)) => {
+            let w = w
+                .trim()
+                .parse::<f32>()
+                .map_err(|_| format!("`{}` is not a number", w))?;
+            let h = h
+                .trim()
+                .parse::<f32>()
+                .map_err(|_| format!("`{}` is not a number", h))?;
+            w / h
+        }
+        None => s
+            .trim()
+            .parse::<f32>()
+            .map_err(|_| format!("`{}` is not a number or `W:H` ratio", s))?,
+    };
+
+    if ratio.is_finite() && ratio > 0.0 {
+        Ok(ratio)
+    } else {
+        Err(format!("aspect ratio must be positive, got `{}`", s))
+    }
+}
+
+/// Parse one entry of `--aovs`, where `all` stands for every variable
+fn parse_aovs(s: &str) -> Result<Vec<Aov>, String> {
+    if s == "all" {
+        Ok(Aov::ALL.to_vec())
+    } else {
+        s.parse().map(|aov| vec![aov])
+    }
+}
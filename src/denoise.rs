@@ -0,0 +1,311 @@
+//! Removal of sampling noise from a rendered film.
+//!
+//! The filters average each pixel with similar neighbours, where similarity is judged by
+//! color relative to the pixel's estimated variance and by the albedo and normal output
+//! variables, which are noise free and mark the edges the filters must not blur across.
+//! Colors are divided by the albedo before filtering and multiplied by it afterwards, so
+//! that texture detail survives even where the lighting is smoothed heavily.
+
+use crate::aov::Aov;
+use crate::film::Film;
+use crate::vec3::*;
+
+use rayon::prelude::*;
+use std::fmt;
+use std::str::FromStr;
+
+/// Denoising algorithm
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DenoiserType {
+    /// Joint bilateral filter over a square window
+    Bilateral,
+    /// Non-local means, comparing small patches around the pixels instead of single colors
+    NonLocalMeans,
+    /// Edge-avoiding à-trous wavelet filter, which covers a wide area in a few sparse passes
+    ATrous,
+}
+
+impl FromStr for DenoiserType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bilateral" => Ok(DenoiserType::Bilateral),
+            "nlm" => Ok(DenoiserType::NonLocalMeans),
+            "atrous" => Ok(DenoiserType::ATrous),
+            _ => Err(format!(
+                "unknown denoiser `{}`, expected `bilateral`, `nlm` or `atrous`",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for DenoiserType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DenoiserType::Bilateral => write!(f, "bilateral"),
+            DenoiserType::NonLocalMeans => write!(f, "nlm"),
+            DenoiserType::ATrous => write!(f, "atrous"),
+        }
+    }
+}
+
+/// Half the width of the bilateral filter window, in pixels
+const BILATERAL_RADIUS: i32 = 7;
+/// Half the width of the non-local means search window and of its patches
+const NLM_SEARCH_RADIUS: i32 = 7;
+const NLM_PATCH_RADIUS: i32 = 1;
+/// Number of à-trous passes; the last one reaches `2 * 2^(passes - 1)` pixels away
+const ATROUS_PASSES: u32 = 5;
+/// Standard deviations of the normal and albedo differences that still count as similar
+const NORMAL_SIGMA: f32 = 0.1;
+const ALBEDO_SIGMA: f32 = 0.1;
+
+/// Filter that smooths a film's colors while keeping the edges found in its feature buffers
+#[derive(Copy, Clone, Debug)]
+pub struct Denoiser {
+    pub kind: DenoiserType,
+    /// Scale of the color differences treated as noise; larger values smooth more
+    pub strength: f32,
+}
+
+impl Denoiser {
+    pub fn new(kind: DenoiserType, strength: f32) -> Denoiser {
+        Denoiser { kind, strength }
+    }
+
+    /// Copy of `film` with denoised pixel colors. The albedo and normal output variables
+    /// guide the filter when the film recorded them; otherwise only colors are compared.
+    pub fn apply(&self, film: &Film) -> Film {
+        let buffers = Buffers::new(film);
+        let colors = match self.kind {
+            DenoiserType::Bilateral => buffers.bilateral(self.strength),
+            DenoiserType::NonLocalMeans => buffers.non_local_means(self.strength),
+            DenoiserType::ATrous => buffers.a_trous(self.strength),
+        };
+
+        let mut denoised = film.clone();
+        for y in 0..film.height() {
+            for x in 0..film.width() {
+                let i = buffers.index(x as i32, y as i32);
+                let pixel = denoised.pixel_mut(x, y);
+                pixel.weighted_sum = colors[i].component_mul(&buffers.modulation[i]);
+                pixel.weight_sum = 1.0;
+            }
+        }
+        denoised
+    }
+}
+
+/// Per pixel inputs of the filters
+struct Buffers {
+    width: i32,
+    height: i32,
+    /// Pixel colors divided by the albedo
+    color: Vec<Color>,
+    /// Luminance variance of the mean of each demodulated color, smoothed over 3x3 pixels
+    variance: Vec<f32>,
+    /// Factor the colors were divided by
+    modulation: Vec<Color>,
+    albedo: Vec<Color>,
+    normal: Vec<Vec3>,
+}
+
+impl Buffers {
+    fn new(film: &Film) -> Buffers {
+        let (width, height) = (film.width() as i32, film.height() as i32);
+        let len = (width * height) as usize;
+        let mut buffers = Buffers {
+            width,
+            height,
+            color: Vec::with_capacity(len),
+            variance: Vec::with_capacity(len),
+            modulation: Vec::with_capacity(len),
+            albedo: Vec::with_capacity(len),
+            normal: Vec::with_capacity(len),
+        };
+
+        for y in 0..film.height() {
+            for x in 0..film.width() {
+                let pixel = film.pixel(x, y);
+                let (albedo, normal) = if film.has_aovs() {
+                    let aov = film.aov_pixel(x, y);
+                    let [r, g, b] = aov.value(Aov::Albedo);
+                    let [nx, ny, nz] = aov.value(Aov::Normal);
+                    (Color::new(r, g, b), Vec3::new(nx, ny, nz))
+                } else {
+                    (Color::new(1.0, 1.0, 1.0), Vec3::new(0.0, 0.0, 0.0))
+                };
+                // Black albedos, including pixels where nothing was hit, carry no texture
+                // and would amplify the color without bound
+                let modulation = albedo.map(|a| if a > 1.0e-2 { a } else { 1.0 });
+                let color = pixel
+                    .color()
+                    .map(|c| if c.is_finite() { c.max(0.0) } else { 0.0 })
+                    .component_div(&modulation);
+
+                let n = pixel.samples as f32;
+                let variance = if pixel.samples < 2 {
+                    luminance(color).powi(2)
+                } else {
+                    let mean = pixel.luminance_sum / n;
+                    let sample_variance =
+                        ((pixel.luminance_squared_sum - mean * pixel.luminance_sum) / (n - 1.0))
+                            .max(0.0);
+                    sample_variance / n / luminance(modulation).powi(2)
+                };
+
+                buffers.color.push(color);
+                buffers.variance.push(variance);
+                buffers.modulation.push(modulation);
+                buffers.albedo.push(albedo);
+                buffers.normal.push(normal);
+            }
+        }
+
+        // Variance estimates from a handful of samples are noisy themselves
+        buffers.variance = buffers.map_pixels(|x, y| {
+            let mut sum = 0.0;
+            let mut count = 0.0;
+            for (qx, qy) in buffers.window(x, y, 1, 1) {
+                sum += buffers.variance[buffers.index(qx, qy)];
+                count += 1.0;
+            }
+            sum / count
+        });
+        buffers
+    }
+
+    fn index(&self, x: i32, y: i32) -> usize {
+        (y * self.width + x) as usize
+    }
+
+    /// Pixels at multiples of `step` within `radius` steps of `(x, y)`, inside the image
+    fn window(&self, x: i32, y: i32, radius: i32, step: i32) -> impl Iterator<Item = (i32, i32)> {
+        let (width, height) = (self.width, self.height);
+        (-radius..=radius)
+            .flat_map(move |dy| (-radius..=radius).map(move |dx| (x + dx * step, y + dy * step)))
+            .filter(move |&(qx, qy)| qx >= 0 && qx < width && qy >= 0 && qy < height)
+    }
+
+    /// Evaluate `f` for every pixel in parallel, in row-major order
+    fn map_pixels<T: Send>(&self, f: impl Fn(i32, i32) -> T + Sync) -> Vec<T> {
+        let width = self.width;
+        (0..self.width * self.height)
+            .into_par_iter()
+            .map(|i| f(i % width, i / width))
+            .collect()
+    }
+
+    /// How alike the albedos and normals of two pixels are, between zero and one
+    fn feature_weight(&self, p: usize, q: usize) -> f32 {
+        let normal = (self.normal[p] - self.normal[q]).norm_squared();
+        let albedo = (self.albedo[p] - self.albedo[q]).norm_squared();
+        (-normal / (2.0 * NORMAL_SIGMA * NORMAL_SIGMA)
+            - albedo / (2.0 * ALBEDO_SIGMA * ALBEDO_SIGMA))
+            .exp()
+    }
+
+    fn bilateral(&self, strength: f32) -> Vec<Color> {
+        let spatial_sigma = BILATERAL_RADIUS as f32 / 2.0;
+        self.map_pixels(|x, y| {
+            let p = self.index(x, y);
+            let mut sum = Color::new(0.0, 0.0, 0.0);
+            let mut weight_sum = 0.0;
+            for (qx, qy) in self.window(x, y, BILATERAL_RADIUS, 1) {
+                let q = self.index(qx, qy);
+                let distance = ((qx - x).pow(2) + (qy - y).pow(2)) as f32;
+                let color = color_distance_squared(self.color[p], self.color[q]);
+                let tolerance = strength * strength * (self.variance[p] + self.variance[q]);
+                let weight = (-distance / (2.0 * spatial_sigma * spatial_sigma)
+                    - color / (tolerance + 1.0e-4))
+                    .exp()
+                    * self.feature_weight(p, q);
+                sum += self.color[q] * weight;
+                weight_sum += weight;
+            }
+            sum / weight_sum
+        })
+    }
+
+    /// Non-local means with the variance-cancelling patch distance of Rousselle et al.,
+    /// "Adaptive Rendering with Non-Local Means Filtering" (2012)
+    fn non_local_means(&self, strength: f32) -> Vec<Color> {
+        let k = 0.45 * strength;
+        self.map_pixels(|x, y| {
+            let p = self.index(x, y);
+            let mut sum = Color::new(0.0, 0.0, 0.0);
+            let mut weight_sum = 0.0;
+            for (qx, qy) in self.window(x, y, NLM_SEARCH_RADIUS, 1) {
+                let q = self.index(qx, qy);
+                let mut distance = 0.0;
+                let mut count = 0.0;
+                for (px, py) in self.window(x, y, NLM_PATCH_RADIUS, 1) {
+                    let (ox, oy) = (qx + px - x, qy + py - y);
+                    if ox < 0 || ox >= self.width || oy < 0 || oy >= self.height {
+                        continue;
+                    }
+                    let (a, b) = (self.index(px, py), self.index(ox, oy));
+                    let (va, vb) = (self.variance[a], self.variance[b]);
+                    distance += (color_distance_squared(self.color[a], self.color[b])
+                        - (va + va.min(vb)))
+                        / (1.0e-4 + k * k * (va + vb));
+                    count += 1.0;
+                }
+                let weight = if count > 0.0 {
+                    (-(distance / count).max(0.0)).exp() * self.feature_weight(p, q)
+                } else {
+                    0.0
+                };
+                sum += self.color[q] * weight;
+                weight_sum += weight;
+            }
+            if weight_sum > 0.0 {
+                sum / weight_sum
+            } else {
+                self.color[p]
+            }
+        })
+    }
+
+    /// Edge-avoiding à-trous wavelet filter (Dammertz et al. 2010) with the variance
+    /// guided color weight of spatiotemporal variance-guided filtering (Schied et al. 2017)
+    fn a_trous(&self, strength: f32) -> Vec<Color> {
+        const KERNEL: [f32; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+        let sigma = 4.0 * strength;
+        let mut color = self.color.clone();
+        let mut variance = self.variance.clone();
+
+        for pass in 0..ATROUS_PASSES {
+            let step = 1 << pass;
+            let filtered = self.map_pixels(|x, y| {
+                let p = self.index(x, y);
+                let tolerance = sigma * variance[p].sqrt() + 1.0e-4;
+                let mut sum = Color::new(0.0, 0.0, 0.0);
+                let mut variance_sum = 0.0;
+                let mut weight_sum = 0.0;
+                for (qx, qy) in self.window(x, y, 2, step) {
+                    let q = self.index(qx, qy);
+                    let h = KERNEL[((qx - x) / step + 2) as usize]
+                        * KERNEL[((qy - y) / step + 2) as usize];
+                    let difference = color_distance_squared(color[p], color[q]).sqrt();
+                    let weight = h * (-difference / tolerance).exp() * self.feature_weight(p, q);
+                    sum += color[q] * weight;
+                    variance_sum += weight * weight * variance[q];
+                    weight_sum += weight;
+                }
+                (sum / weight_sum, variance_sum / (weight_sum * weight_sum))
+            });
+            let (next_color, next_variance) = filtered.into_iter().unzip();
+            color = next_color;
+            variance = next_variance;
+        }
+        color
+    }
+}
+
+/// Squared difference of two colors, averaged over the channels
+fn color_distance_squared(a: Color, b: Color) -> f32 {
+    (a - b).norm_squared() / 3.0
+}
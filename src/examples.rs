@@ -1,12 +1,82 @@
+use crate::hittable::mesh::TriangleMesh;
+use crate::hittable::plane::Plane;
+use crate::hittable::sphere::Sphere;
+use crate::hittable::triangle::SimpleTriangle;
+use crate::light::PointLight;
+use crate::material::Material;
+use crate::rng::SampleRng;
+use crate::scene::Scene;
+use crate::texture::{constant, constant_scalar};
 use crate::vec3::*;
 
-use rand;
+use rand_distr::{Distribution, Uniform};
 
-fn random_scene(num_spheres: u32) -> Scene {
+/// Names of the scenes that can be built without a scene file
+pub const BUILTIN_SCENES: [&str; 5] = ["grid", "random", "test", "simple", "teapot"];
+
+/// Build one of the builtin example scenes by name, drawing random choices from `rng`
+pub fn builtin_scene(name: &str, rng: &mut SampleRng) -> Option<Scene> {
+    let mut world = match name {
+        "grid" => grid_scene(rng),
+        "random" => random_scene(500, rng),
+        "test" => test_scene(),
+        "simple" => simple_scene(),
+        "teapot" => teapot_scene(rng),
+        _ => return None,
+    };
+
+    world.add_light(default_light());
+
+    Some(world)
+}
+
+/// Load a `.obj` file with a random material, scaled into view of the default camera
+pub fn mesh_scene(file: &str, rng: &mut SampleRng) -> Scene {
+    let mut world = Scene::new();
+    let mut mesh = TriangleMesh::from_file(file, random_material(rng));
+    mesh.shift_scale(Point3::new(0.0, -2.0, -10.0), 0.5);
+    world.add(mesh);
+    world.add_light(default_light());
+    world
+}
+
+/// Point light above and behind the default camera, bright enough to light the builtin
+/// scenes despite the inverse square falloff
+pub fn default_light() -> PointLight {
+    PointLight::new(
+        Point3::new(10.0, 10.0, 10.0),
+        Color::new(300.0, 390.0, 300.0),
+    )
+}
+
+pub fn grid_scene(rng: &mut SampleRng) -> Scene {
+    let mut world = Scene::new();
+
+    for nx in -50..50 {
+        for ny in -50..50 {
+            for nz in -50..50 {
+                world.add(random_sphere(
+                    nx as f32 / 10.0,
+                    ny as f32 / 10.0,
+                    -10.0 + (nz as f32 / 10.0),
+                    rng,
+                ))
+            }
+        }
+    }
+
+    world
+}
+
+pub fn teapot_scene(rng: &mut SampleRng) -> Scene {
+    mesh_scene("objs/teapot.obj", rng)
+}
+
+pub fn random_scene(num_spheres: u32, rng: &mut SampleRng) -> Scene {
     let mut world = Scene::new();
 
     let ground_material = Material::Lambertian {
-        albedo: Color::new(0.5, 0.5, 0.5),
+        albedo: constant(Color::new(0.5, 0.5, 0.5)),
     };
     world.add(Sphere::new(
         Point3::new(0.0, -1000.0, 0.0),
@@ -14,47 +84,43 @@ fn random_scene(num_spheres: u32) -> Scene {
         ground_material,
     ));
 
-    let mut rng = rand::thread_rng();
-
     let sphere_range = (num_spheres as f32).sqrt() as i32;
 
     for a in -sphere_range..sphere_range {
         for b in -sphere_range..sphere_range {
             let random_float = Uniform::new_inclusive(0.0, 1.0);
-            let random_radius = Uniform::new_inclusive(0.1, 0.4).sample(&mut rng);
-            let random_x = Uniform::new_inclusive(11.0, 13.0).sample(&mut rng);
-            let random_z = Uniform::new_inclusive(-5.0, 5.0).sample(&mut rng);
-            let random_albedo_r = Uniform::new_inclusive(0.0, 1.0).sample(&mut rng);
-            let random_albedo_g = Uniform::new_inclusive(0.0, 1.0).sample(&mut rng);
-            let random_albedo_b = Uniform::new_inclusive(0.0, 1.0).sample(&mut rng);
+            let random_radius = Uniform::new_inclusive(0.1, 0.4).sample(rng);
+            let random_albedo_r = Uniform::new_inclusive(0.0, 1.0).sample(rng);
+            let random_albedo_g = Uniform::new_inclusive(0.0, 1.0).sample(rng);
+            let random_albedo_b = Uniform::new_inclusive(0.0, 1.0).sample(rng);
             let random_albedo = Color::new(random_albedo_r, random_albedo_g, random_albedo_b);
-            let random_refractive_index = Uniform::new_inclusive(-1.5, 1.5).sample(&mut rng);
+            let random_refractive_index = Uniform::new_inclusive(-1.5, 1.5).sample(rng);
 
-            let mat_picker = Uniform::new(0, 4).sample(&mut rng);
+            let mat_picker = Uniform::new(0, 4).sample(rng);
 
             let center = Point3::new(
-                a as f32 + 0.9 * random_float.sample(&mut rng),
+                a as f32 + 0.9 * random_float.sample(rng),
                 random_radius,
-                b as f32 + 0.9 * random_float.sample(&mut rng),
+                b as f32 + 0.9 * random_float.sample(rng),
             );
 
             if (center - Point3::new(4.0, 0.2, 0.0)).norm() > 0.9 {
                 let sphere_material = match mat_picker {
                     0 => {
-                        let albedo = random_albedo;
+                        let albedo = constant(random_albedo);
                         Material::Lambertian { albedo }
                     }
                     1 => {
-                        let albedo = random_albedo;
-                        let fuzz = Uniform::new(0.0, 0.5).sample(&mut rand::thread_rng());
+                        let albedo = constant(random_albedo);
+                        let fuzz = constant_scalar(Uniform::new(0.0, 0.5).sample(rng));
                         Material::Metal { albedo, fuzz }
                     }
                     2 => Material::Dielectric {
-                        albedo: Color::new(1.0, 1.0, 1.0),
+                        albedo: constant(Color::new(1.0, 1.0, 1.0)),
                         refraction_index: 1.5,
                     },
                     _ => Material::Dielectric {
-                        albedo: random_albedo,
+                        albedo: constant(random_albedo),
                         refraction_index: random_refractive_index,
                     },
                 };
@@ -66,21 +132,21 @@ fn random_scene(num_spheres: u32) -> Scene {
     world
 }
 
-fn test_scene() -> Scene {
+pub fn test_scene() -> Scene {
     let mut world: Scene = Scene::new();
 
     let material_ground = Material::Lambertian {
-        albedo: Color::new(0.8, 0.8, 0.0),
+        albedo: constant(Color::new(0.8, 0.8, 0.0)),
     };
     let material_center = Material::Lambertian {
-        albedo: Color::new(0.0, 0.8, 0.8),
+        albedo: constant(Color::new(0.0, 0.8, 0.8)),
     };
     let material_left = Material::Metal {
-        albedo: Color::new(0.8, 0.0, 0.8),
-        fuzz: 1.0,
+        albedo: constant(Color::new(0.8, 0.0, 0.8)),
+        fuzz: constant_scalar(1.0),
     };
     let material_right = Material::Dielectric {
-        albedo: Color::new(1.0, 1.0, 1.0),
+        albedo: constant(Color::new(1.0, 1.0, 1.0)),
         refraction_index: 0.4,
     };
 
@@ -99,11 +165,11 @@ fn test_scene() -> Scene {
     //     Vec3::new(0.0, 1.0, -1.0),
     //     material_center,
     // ));
-    world.add(Triangle::new(
+    world.add(SimpleTriangle::new(
         Point3::new(0.0, 0.0, -1.0),
         Point3::new(1.0, 0.0, -1.0),
         Point3::new(0.0, -1.0, -1.0),
-        material_left,
+        material_left.clone(),
     ));
     world.add(Sphere::new(
         Point3::new(-1.0, 0.0, -1.0),
@@ -119,15 +185,15 @@ fn test_scene() -> Scene {
     world
 }
 
-fn simple_scene() -> Scene {
+pub fn simple_scene() -> Scene {
     let mut world: Scene = Scene::new();
-    world.add(Triangle::new(
+    world.add(SimpleTriangle::new(
         Point3::new(4.0, -1.0, -8.0),
         Point3::new(1.0, 1.5, -9.0),
         Point3::new(2.0, 0.0, -10.0),
         Material::Metal {
-            albedo: Color::new(1.0, 0.0, 0.0),
-            fuzz: 1.0,
+            albedo: constant(Color::new(1.0, 0.0, 0.0)),
+            fuzz: constant_scalar(1.0),
         },
     ));
 
@@ -135,8 +201,8 @@ fn simple_scene() -> Scene {
         Point3::new(0.0, -1.0, 0.0),
         Vec3::new(0.0, 1.0, 0.1),
         Material::Metal {
-            albedo: Color::new(1.0, 1.0, 0.0),
-            fuzz: 1.0,
+            albedo: constant(Color::new(1.0, 1.0, 0.0)),
+            fuzz: constant_scalar(1.0),
         },
     ));
 
@@ -144,8 +210,8 @@ fn simple_scene() -> Scene {
         Point3::new(0.0, 0.0, -8.0),
         1.0,
         Material::Metal {
-            albedo: Color::new(1.0, 0.0, 1.0),
-            fuzz: 1.0,
+            albedo: constant(Color::new(1.0, 0.0, 1.0)),
+            fuzz: constant_scalar(1.0),
         },
     ));
 
@@ -153,8 +219,67 @@ fn simple_scene() -> Scene {
         Point3::new(0.8, 0.7, -7.0),
         0.4,
         Material::Metal {
-            albedo: Color::new(0.2, 0.2, 1.0),
-            fuzz: 1.0,
+            albedo: constant(Color::new(0.2, 0.2, 1.0)),
+            fuzz: constant_scalar(1.0),
         },
     ));
+
+    world
+}
+
+pub fn random_sphere(x: f32, y: f32, z: f32, rng: &mut SampleRng) -> Sphere {
+    let random_float = Uniform::new_inclusive(0.0, 1.0);
+    let random_radius = Uniform::new_inclusive(0.05, 0.1).sample(rng);
+    let random_albedo_r = Uniform::new_inclusive(0.0, 1.0).sample(rng);
+    let random_albedo_g = Uniform::new_inclusive(0.0, 1.0).sample(rng);
+    let random_albedo_b = Uniform::new_inclusive(0.0, 1.0).sample(rng);
+    let random_albedo = Color::new(random_albedo_r, random_albedo_g, random_albedo_b);
+    let random_refractive_index = Uniform::new_inclusive(-1.5, 1.5).sample(rng);
+    let mat_picker = Uniform::new(0, 4).sample(rng);
+    let center = Point3::new(x, y, z);
+    let sphere_material = match mat_picker {
+        0 => {
+            let albedo = constant(random_albedo);
+            Material::Lambertian { albedo }
+        }
+        1 => {
+            let albedo = constant(random_albedo);
+            let fuzz = constant_scalar(Uniform::new(0.0, 0.5).sample(rng));
+            Material::Metal { albedo, fuzz }
+        }
+        2 => Material::Dielectric {
+            albedo: constant(Color::new(1.0, 1.0, 1.0)),
+            refraction_index: 1.5,
+        },
+        _ => Material::Dielectric {
+            albedo: constant(random_albedo),
+            refraction_index: random_refractive_index,
+        },
+    };
+    Sphere::new(center, 0.05, sphere_material)
+}
+
+pub fn random_material(rng: &mut SampleRng) -> Material {
+    let mat_picker = Uniform::new(0, 4).sample(rng);
+    let random_albedo_r = Uniform::new_inclusive(0.0, 1.0).sample(rng);
+    let random_albedo_g = Uniform::new_inclusive(0.0, 1.0).sample(rng);
+    let random_albedo_b = Uniform::new_inclusive(0.0, 1.0).sample(rng);
+    let random_albedo = Color::new(random_albedo_r, random_albedo_g, random_albedo_b);
+
+    match mat_picker {
+        0 => {
+            let fuzz = constant_scalar(Uniform::new(0.0, 0.5).sample(rng));
+            Material::Metal {
+                albedo: constant(random_albedo),
+                fuzz,
+            }
+        }
+        1 => Material::Dielectric {
+            albedo: constant(Color::new(1.0, 1.0, 1.0)),
+            refraction_index: 1.5,
+        },
+        _ => Material::Lambertian {
+            albedo: constant(random_albedo),
+        },
+    }
 }
@@ -0,0 +1,633 @@
+use crate::aov::{Aov, AovPixel};
+use crate::filter::Filter;
+use crate::render::{Image, Image16};
+use crate::tile::Tile;
+use crate::tonemap::{BitDepth, OutputTransform};
+use crate::vec3::*;
+
+use image::codecs::hdr::HdrEncoder;
+use image::{ImageBuffer, Rgb};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Floating point accumulation buffer that samples are added to while rendering
+#[derive(Clone, Debug, PartialEq)]
+pub struct Film {
+    width: u32,
+    height: u32,
+    pixels: Vec<FilmPixel>,
+    /// Output variables of each pixel, when they are recorded
+    aovs: Option<Vec<AovPixel>>,
+}
+
+/// Running sums of the samples taken for one pixel
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FilmPixel {
+    pub sum: Color,
+    /// Sums of the sample luminances and their squares, used to estimate the variance
+    pub luminance_sum: f32,
+    pub luminance_squared_sum: f32,
+    pub samples: u32,
+    /// Filter weighted sum of the samples splatted onto this pixel, including samples
+    /// taken in neighbouring pixels, and the sum of their weights
+    pub weighted_sum: Color,
+    pub weight_sum: f32,
+}
+
+impl Default for FilmPixel {
+    fn default() -> Self {
+        FilmPixel {
+            sum: Color::new(0.0, 0.0, 0.0),
+            luminance_sum: 0.0,
+            luminance_squared_sum: 0.0,
+            samples: 0,
+            weighted_sum: Color::new(0.0, 0.0, 0.0),
+            weight_sum: 0.0,
+        }
+    }
+}
+
+impl FilmPixel {
+    pub fn add(&mut self, color: Color) {
+        let y = luminance(color);
+        self.sum += color;
+        self.luminance_sum += y;
+        self.luminance_squared_sum += y * y;
+        self.samples += 1;
+    }
+
+    pub fn mean(&self) -> Color {
+        if self.samples == 0 {
+            Color::new(0.0, 0.0, 0.0)
+        } else {
+            self.sum / self.samples as f32
+        }
+    }
+
+    /// Reconstructed color of the pixel: the filter weighted average of nearby samples,
+    /// or the plain average where the weights cancel out
+    pub fn color(&self) -> Color {
+        if self.weight_sum > 1.0e-6 {
+            self.weighted_sum / self.weight_sum
+        } else {
+            self.mean()
+        }
+    }
+
+    /// Standard error of the mean luminance relative to the mean itself, or infinity when
+    /// there are too few samples to tell. Dark pixels are compared against a floor so that
+    /// they do not need an unbounded number of samples.
+    pub fn relative_error(&self) -> f32 {
+        if self.samples < 2 {
+            return f32::INFINITY;
+        }
+        let n = self.samples as f32;
+        let mean = self.luminance_sum / n;
+        let variance =
+            ((self.luminance_squared_sum - mean * self.luminance_sum) / (n - 1.0)).max(0.0);
+        (variance / n).sqrt() / mean.max(1.0e-2)
+    }
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32) -> Film {
+        Film {
+            width,
+            height,
+            pixels: vec![FilmPixel::default(); width as usize * height as usize],
+            aovs: None,
+        }
+    }
+
+    /// Film that also records the output variables of every pixel
+    pub fn with_aovs(width: u32, height: u32) -> Film {
+        Film {
+            aovs: Some(vec![AovPixel::default(); width as usize * height as usize]),
+            ..Film::new(width, height)
+        }
+    }
+
+    pub fn has_aovs(&self) -> bool {
+        self.aovs.is_some()
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Pixel at `(x, y)`, counted from the top left corner
+    pub fn pixel(&self, x: u32, y: u32) -> &FilmPixel {
+        &self.pixels[self.index(x, y)]
+    }
+
+    pub fn pixel_mut(&mut self, x: u32, y: u32) -> &mut FilmPixel {
+        let index = self.index(x, y);
+        &mut self.pixels[index]
+    }
+
+    pub fn pixels(&self) -> &[FilmPixel] {
+        &self.pixels
+    }
+
+    /// Average number of samples per pixel
+    pub fn mean_samples(&self) -> f32 {
+        let total: u64 = self.pixels.iter().map(|pixel| pixel.samples as u64).sum();
+        total as f32 / self.pixels.len().max(1) as f32
+    }
+
+    /// Largest relative error of any pixel
+    pub fn max_relative_error(&self) -> f32 {
+        self.pixels
+            .iter()
+            .map(FilmPixel::relative_error)
+            .fold(0.0, f32::max)
+    }
+
+    /// Add the samples splatted by one tile
+    pub fn merge(&mut self, splats: &TileSplats) {
+        for (i, &(weighted_sum, weight_sum)) in splats.pixels.iter().enumerate() {
+            let x = splats.x + i as u32 % splats.width;
+            let y = splats.y + i as u32 / splats.width;
+            let pixel = self.pixel_mut(x, y);
+            pixel.weighted_sum += weighted_sum;
+            pixel.weight_sum += weight_sum;
+        }
+    }
+
+    /// Reconstructed color of each pixel as an 8-bit sRGB image, clipped at one
+    pub fn to_image(&self) -> Image {
+        self.to_image_with(&OutputTransform::default())
+    }
+
+    /// 8-bit image of the film as seen through an output transform
+    pub fn to_image_with(&self, transform: &OutputTransform) -> Image {
+        let display = |x, y| transform.apply(self.pixel(x, y).color());
+        self.quantize(transform, &display, |value| value as u8)
+    }
+
+    /// 16-bit image of the film as seen through an output transform
+    pub fn to_image16_with(&self, transform: &OutputTransform) -> Image16 {
+        let display = |x, y| transform.apply(self.pixel(x, y).color());
+        self.quantize(transform, &display, |value| value as u16)
+    }
+
+    /// Write the film to `path` along with the output variables `aovs`. An `.exr` file
+    /// gets them as extra channels named after the variable, such as `depth.Z` or
+    /// `normal.X`; other formats get one more file per variable, named like `path` with
+    /// the variable inserted before the extension.
+    ///
+    /// `.exr`, `.pfm` and `.hdr` files keep the linear floating point values; other formats
+    /// are written through `transform` at its bit depth.
+    pub fn save(
+        &self,
+        path: &Path,
+        transform: &OutputTransform,
+        aovs: &[Aov],
+    ) -> Result<(), String> {
+        if !aovs.is_empty() && self.aovs.is_none() {
+            return Err("the film did not record output variables".to_string());
+        }
+
+        if path.extension().and_then(|ext| ext.to_str()) == Some("exr") {
+            return self
+                .write_exr(path, aovs)
+                .map_err(|err| format!("could not save {}: {}", path.display(), err));
+        }
+
+        self.write_image(path, transform, None)?;
+        for &aov in aovs {
+            self.write_image(&aov_path(path, aov), transform, Some(aov))?;
+        }
+        Ok(())
+    }
+
+    /// Write the beauty image, or one output variable, to a single file
+    fn write_image(
+        &self,
+        path: &Path,
+        transform: &OutputTransform,
+        aov: Option<Aov>,
+    ) -> Result<(), String> {
+        let linear = |x, y| match aov {
+            None => self.pixel(x, y).color(),
+            Some(aov) => {
+                let value = self.aov_pixel(x, y).value(aov);
+                if aov.channels().len() == 1 {
+                    Color::new(value[0], value[0], value[0])
+                } else {
+                    Color::new(value[0], value[1], value[2])
+                }
+            }
+        };
+
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("pfm") => write_pfm(path, self.width, self.height, &linear),
+            Some("hdr") => write_hdr(path, self.width, self.height, &linear),
+            _ => {
+                let display = self.display(transform, aov);
+                match transform.bit_depth {
+                    BitDepth::Eight => self
+                        .quantize(transform, &display, |value| value as u8)
+                        .save(path),
+                    BitDepth::Sixteen => self
+                        .quantize(transform, &display, |value| value as u16)
+                        .save(path),
+                }
+                .map_err(|err| err.to_string())
+            }
+        }
+        .map_err(|err| format!("could not save {}: {}", path.display(), err))
+    }
+
+    /// Integer image of display colors in `[0, 1]`
+    fn quantize<T: image::Primitive + 'static>(
+        &self,
+        transform: &OutputTransform,
+        display: &dyn Fn(u32, u32) -> Color,
+        convert: fn(f32) -> T,
+    ) -> ImageBuffer<Rgb<T>, Vec<T>> {
+        ImageBuffer::from_fn(self.width, self.height, |x, y| {
+            let color = display(x, y);
+            let channel = |c: usize| convert(transform.quantize(color[c], x, y, c as u32));
+            Rgb([channel(0), channel(1), channel(2)])
+        })
+    }
+
+    /// Display colors of the beauty image or of an output variable. Lighting goes through
+    /// the output transform and albedo through the sRGB curve, while geometric variables
+    /// are remapped to show their range: depth from white near the camera to black far
+    /// away, normals and positions as colors, and identifiers as random colors.
+    fn display<'a>(
+        &'a self,
+        transform: &'a OutputTransform,
+        aov: Option<Aov>,
+    ) -> Box<dyn Fn(u32, u32) -> Color + 'a> {
+        let aov = match aov {
+            None => return Box::new(move |x, y| transform.apply(self.pixel(x, y).color())),
+            Some(aov) => aov,
+        };
+        let value = move |x, y| {
+            let v = self.aov_pixel(x, y).value(aov);
+            Vec3::new(v[0], v[1], v[2])
+        };
+        let hit = move |x, y| self.aov_pixel(x, y).hits > 0;
+        let black = Color::new(0.0, 0.0, 0.0);
+
+        match aov {
+            Aov::Direct | Aov::Indirect => Box::new(move |x, y| transform.apply(value(x, y))),
+            Aov::Albedo => Box::new(move |x, y| OutputTransform::default().apply(value(x, y))),
+            Aov::Normal => Box::new(move |x, y| {
+                if hit(x, y) {
+                    value(x, y).map(|n| 0.5 * n + 0.5)
+                } else {
+                    black
+                }
+            }),
+            Aov::Depth | Aov::Position => {
+                // Range of the values over the pixels that hit something
+                let mut min = Vec3::repeat(f32::INFINITY);
+                let mut max = Vec3::repeat(f32::NEG_INFINITY);
+                for y in 0..self.height {
+                    for x in 0..self.width {
+                        if hit(x, y) {
+                            min = min.inf(&value(x, y));
+                            max = max.sup(&value(x, y));
+                        }
+                    }
+                }
+                let range = (max - min).map(|r| r.max(1.0e-6));
+                Box::new(move |x, y| {
+                    if !hit(x, y) {
+                        return black;
+                    }
+                    let t = (value(x, y) - min).component_div(&range);
+                    if aov == Aov::Depth {
+                        Color::repeat(1.0 - t.x)
+                    } else {
+                        t
+                    }
+                })
+            }
+            Aov::ObjectId | Aov::MaterialId => Box::new(move |x, y| {
+                if hit(x, y) {
+                    id_color(value(x, y).x as u32)
+                } else {
+                    black
+                }
+            }),
+        }
+    }
+
+    /// OpenEXR file with the beauty image as `R`, `G` and `B` channels and each output
+    /// variable as channels prefixed by its name
+    fn write_exr(&self, path: &Path, aovs: &[Aov]) -> Result<(), String> {
+        use exr::prelude::*;
+
+        let size = (self.width as usize, self.height as usize);
+        let channel = |name: String, value: &dyn Fn(u32, u32) -> f32| {
+            let mut samples = Vec::with_capacity(size.0 * size.1);
+            for y in 0..self.height {
+                for x in 0..self.width {
+                    samples.push(value(x, y));
+                }
+            }
+            AnyChannel::new(name.as_str(), FlatSamples::F32(samples))
+        };
+
+        let mut channels = Vec::new();
+        for (c, name) in ["R", "G", "B"].iter().enumerate() {
+            channels.push(channel(name.to_string(), &|x, y| {
+                self.pixel(x, y).color()[c]
+            }));
+        }
+        for &aov in aovs {
+            for (c, name) in aov.channels().iter().enumerate() {
+                channels.push(channel(format!("{}.{}", aov, name), &|x, y| {
+                    self.aov_pixel(x, y).value(aov)[c]
+                }));
+            }
+        }
+
+        let layer = Layer::new(
+            size,
+            LayerAttributes::default(),
+            Encoding::SMALL_LOSSLESS,
+            AnyChannels::sort(channels.into()),
+        );
+        Image::from_layer(layer)
+            .write()
+            .to_file(path)
+            .map_err(|err| err.to_string())
+    }
+
+    /// Number of samples taken in each pixel, from black for the fewest through red and
+    /// yellow to white for the most
+    pub fn sample_heatmap(&self) -> Image {
+        let min = self.pixels.iter().map(|p| p.samples).min().unwrap_or(0);
+        let max = self.pixels.iter().map(|p| p.samples).max().unwrap_or(0);
+        let range = (max - min).max(1) as f32;
+        ImageBuffer::from_fn(self.width, self.height, |x, y| {
+            let t = (self.pixel(x, y).samples - min) as f32 / range;
+            let channel = |start: f32| ((3.0 * t - start).clamp(0.0, 1.0) * 255.0) as u8;
+            Rgb([channel(0.0), channel(1.0), channel(2.0)])
+        })
+    }
+
+    /// Output variables of the pixel at `(x, y)`. Panics if the film was created without
+    /// them.
+    pub fn aov_pixel(&self, x: u32, y: u32) -> &AovPixel {
+        let index = self.index(x, y);
+        &self.aovs.as_ref().expect("film has no output variables")[index]
+    }
+
+    pub fn aov_pixel_mut(&mut self, x: u32, y: u32) -> Option<&mut AovPixel> {
+        let index = self.index(x, y);
+        self.aovs.as_mut().map(|aovs| &mut aovs[index])
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        y as usize * self.width as usize + x as usize
+    }
+}
+
+/// Path of the image holding one output variable: `path` with the variable's name
+/// inserted before the extension
+fn aov_path(path: &Path, aov: Aov) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{}.{}.{}", stem, aov, ext.to_string_lossy()),
+        None => format!("{}.{}", stem, aov),
+    };
+    path.with_file_name(name)
+}
+
+/// Portable float map: a short text header followed by little endian floats, with the
+/// rows stored from the bottom up
+fn write_pfm(
+    path: &Path,
+    width: u32,
+    height: u32,
+    color: &dyn Fn(u32, u32) -> Color,
+) -> Result<(), String> {
+    let file = File::create(path).map_err(|err| err.to_string())?;
+    let mut writer = BufWriter::new(file);
+    let mut data = format!("PF\n{} {}\n-1.0\n", width, height).into_bytes();
+    for y in (0..height).rev() {
+        for x in 0..width {
+            for channel in color(x, y).iter() {
+                data.extend_from_slice(&channel.to_le_bytes());
+            }
+        }
+    }
+    writer
+        .write_all(&data)
+        .and_then(|_| writer.flush())
+        .map_err(|err| err.to_string())
+}
+
+fn write_hdr(
+    path: &Path,
+    width: u32,
+    height: u32,
+    color: &dyn Fn(u32, u32) -> Color,
+) -> Result<(), String> {
+    let file = File::create(path).map_err(|err| err.to_string())?;
+    let mut pixels = Vec::with_capacity(width as usize * height as usize);
+    for y in 0..height {
+        for x in 0..width {
+            // Radiance files cannot store infinities, such as the depth of empty pixels
+            let color = color(x, y).map(|c| if c.is_finite() { c } else { 0.0 });
+            pixels.push(Rgb([color.x, color.y, color.z]));
+        }
+    }
+    HdrEncoder::new(BufWriter::new(file))
+        .encode(&pixels, width as usize, height as usize)
+        .map_err(|err| err.to_string())
+}
+
+/// Distinct color for an identifier
+fn id_color(id: u32) -> Color {
+    let mut h = id.wrapping_mul(0x9e37_79b9);
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85eb_ca6b);
+    h ^= h >> 13;
+    let channel = |shift: u32| 0.2 + 0.8 * ((h >> shift) & 0xff) as f32 / 255.0;
+    Color::new(channel(0), channel(8), channel(16))
+}
+
+/// Filter weighted samples taken within one tile, covering the tile and the pixels around
+/// it that the filter reaches
+pub struct TileSplats {
+    /// Tile the samples were taken in
+    pub tile: Tile,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    pixels: Vec<(Color, f32)>,
+}
+
+impl TileSplats {
+    pub fn new(tile: Tile, filter: &Filter, film_width: u32, film_height: u32) -> TileSplats {
+        let margin = filter.radius.ceil() as u32;
+        let x = tile.x.saturating_sub(margin);
+        let y = tile.y.saturating_sub(margin);
+        let width = (tile.x + tile.width + margin).min(film_width) - x;
+        let height = (tile.y + tile.height + margin).min(film_height) - y;
+        TileSplats {
+            tile,
+            x,
+            y,
+            width,
+            height,
+            pixels: vec![(Color::new(0.0, 0.0, 0.0), 0.0); width as usize * height as usize],
+        }
+    }
+
+    /// Add a sample taken at film position `(sample_x, sample_y)` to every pixel whose
+    /// center is within the filter radius
+    pub fn add(&mut self, sample_x: f32, sample_y: f32, color: Color, filter: &Filter) {
+        // Pixel `i` has its center at `i + 0.5`
+        let range = |position: f32, start: u32, size: u32| {
+            let first = ((position - 0.5 - filter.radius).ceil() as i64).max(start as i64);
+            let last = ((position - 0.5 + filter.radius).floor() as i64)
+                .min(start as i64 + size as i64 - 1);
+            first..=last
+        };
+
+        for y in range(sample_y, self.y, self.height) {
+            for x in range(sample_x, self.x, self.width) {
+                let weight = filter.evaluate(x as f32 + 0.5 - sample_x, y as f32 + 0.5 - sample_y);
+                if weight == 0.0 {
+                    continue;
+                }
+                let index = (y as u32 - self.y) * self.width + (x as u32 - self.x);
+                let (weighted_sum, weight_sum) = &mut self.pixels[index as usize];
+                *weighted_sum += weight * color;
+                *weight_sum += weight;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::codecs::hdr::HdrDecoder;
+    use std::io::BufReader;
+
+    const WIDTH: u32 = 3;
+    const HEIGHT: u32 = 2;
+
+    /// Distinct color for every pixel, with values well above one
+    fn expected(x: u32, y: u32) -> Color {
+        Color::new(
+            1.5 + x as f32,
+            10.0 * (y + 1) as f32,
+            0.25 + 3.0 * (x * y) as f32,
+        )
+    }
+
+    fn film() -> Film {
+        let mut film = Film::new(WIDTH, HEIGHT);
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                film.pixel_mut(x, y).add(expected(x, y));
+            }
+        }
+        film
+    }
+
+    /// Write the film to a temporary file with the given extension
+    fn save(extension: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("traci_{}_film.{}", std::process::id(), extension));
+        film()
+            .save(&path, &OutputTransform::default(), &[])
+            .unwrap();
+        path
+    }
+
+    #[test]
+    fn pfm_round_trip() {
+        let path = save("pfm");
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let header = format!("PF\n{} {}\n-1.0\n", WIDTH, HEIGHT);
+        assert!(data.starts_with(header.as_bytes()));
+        let floats: Vec<f32> = data[header.len()..]
+            .chunks(4)
+            .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            .collect();
+        assert_eq!(floats.len(), (3 * WIDTH * HEIGHT) as usize);
+
+        // Rows are stored from the bottom of the image up
+        for (row, pixels) in floats.chunks((3 * WIDTH) as usize).enumerate() {
+            let y = HEIGHT - 1 - row as u32;
+            for (x, rgb) in pixels.chunks(3).enumerate() {
+                assert_eq!(Color::new(rgb[0], rgb[1], rgb[2]), expected(x as u32, y));
+            }
+        }
+    }
+
+    #[test]
+    fn hdr_round_trip() {
+        let path = save("hdr");
+        let decoder = HdrDecoder::new(BufReader::new(File::open(&path).unwrap())).unwrap();
+        let metadata = decoder.metadata();
+        let pixels = decoder.read_image_hdr().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!((metadata.width, metadata.height), (WIDTH, HEIGHT));
+        for (i, pixel) in pixels.iter().enumerate() {
+            let expected = expected(i as u32 % WIDTH, i as u32 / WIDTH);
+            // Radiance files share one exponent between the channels and keep 8 bits of
+            // mantissa each, so small channels lose precision next to large ones
+            let largest = expected.max();
+            for c in 0..3 {
+                assert!(
+                    (pixel[c] - expected[c]).abs() <= largest / 128.0,
+                    "{:?} {:?}",
+                    pixel,
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn exr_round_trip() {
+        let path = save("exr");
+        let image = exr::prelude::read_first_flat_layer_from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let layer = &image.layer_data;
+        assert_eq!(
+            (layer.size.width(), layer.size.height()),
+            (WIDTH as usize, HEIGHT as usize)
+        );
+        for channel in &layer.channel_data.list {
+            let c = match channel.name.to_string().as_str() {
+                "R" => 0,
+                "G" => 1,
+                "B" => 2,
+                name => panic!("unexpected channel {}", name),
+            };
+            let samples = match &channel.sample_data {
+                exr::prelude::FlatSamples::F32(samples) => samples,
+                _ => panic!("channel {} is not stored as f32", c),
+            };
+            // Rows are stored from the top of the image down
+            for (i, &value) in samples.iter().enumerate() {
+                assert_eq!(value, expected(i as u32 % WIDTH, i as u32 / WIDTH)[c]);
+            }
+        }
+        assert_eq!(layer.channel_data.list.len(), 3);
+    }
+}
@@ -0,0 +1,135 @@
+use std::f32::consts::PI;
+use std::fmt;
+use std::str::FromStr;
+
+/// Shape of a pixel reconstruction filter
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FilterType {
+    /// Equal weight for every sample within the radius
+    Box,
+    /// Weight falling off linearly toward the radius
+    Tent,
+    /// Gaussian with a standard deviation of a third of the radius, shifted to reach zero
+    /// at the radius
+    Gaussian,
+    /// Mitchell-Netravali cubic with B = C = 1/3, which sharpens slightly
+    Mitchell,
+    /// Sinc windowed by a wider sinc, with as many lobes as the radius
+    Lanczos,
+}
+
+impl FilterType {
+    /// Radius in pixels used when none is given
+    pub fn default_radius(self) -> f32 {
+        match self {
+            FilterType::Box => 0.5,
+            FilterType::Tent => 1.0,
+            FilterType::Gaussian => 1.5,
+            FilterType::Mitchell => 2.0,
+            FilterType::Lanczos => 3.0,
+        }
+    }
+}
+
+impl FromStr for FilterType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "box" => Ok(FilterType::Box),
+            "tent" => Ok(FilterType::Tent),
+            "gaussian" => Ok(FilterType::Gaussian),
+            "mitchell" => Ok(FilterType::Mitchell),
+            "lanczos" => Ok(FilterType::Lanczos),
+            _ => Err(format!(
+                "unknown filter `{}`, expected `box`, `tent`, `gaussian`, `mitchell` or `lanczos`",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for FilterType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FilterType::Box => write!(f, "box"),
+            FilterType::Tent => write!(f, "tent"),
+            FilterType::Gaussian => write!(f, "gaussian"),
+            FilterType::Mitchell => write!(f, "mitchell"),
+            FilterType::Lanczos => write!(f, "lanczos"),
+        }
+    }
+}
+
+/// Weights the contribution of a sample to the pixels whose centers lie within `radius`
+/// of it. Filters are separable, and weights are normalized per pixel by the film, so they
+/// only need to be correct up to a constant factor.
+#[derive(Copy, Clone, Debug)]
+pub struct Filter {
+    pub kind: FilterType,
+    /// Half the width of the filter's support, in pixels
+    pub radius: f32,
+}
+
+impl Default for Filter {
+    /// Box filter covering exactly one pixel, which averages the samples inside each pixel
+    fn default() -> Self {
+        Filter::new(FilterType::Box, FilterType::Box.default_radius())
+    }
+}
+
+impl Filter {
+    pub fn new(kind: FilterType, radius: f32) -> Filter {
+        Filter { kind, radius }
+    }
+
+    /// Weight of a sample at offset `(dx, dy)` from a pixel center
+    pub fn evaluate(&self, dx: f32, dy: f32) -> f32 {
+        self.evaluate_1d(dx) * self.evaluate_1d(dy)
+    }
+
+    fn evaluate_1d(&self, x: f32) -> f32 {
+        let x = x.abs();
+        let radius = self.radius;
+        if x >= radius {
+            return 0.0;
+        }
+        match self.kind {
+            FilterType::Box => 1.0,
+            FilterType::Tent => radius - x,
+            FilterType::Gaussian => {
+                let sigma = radius / 3.0;
+                let gaussian = |x: f32| (-x * x / (2.0 * sigma * sigma)).exp();
+                (gaussian(x) - gaussian(radius)).max(0.0)
+            }
+            FilterType::Mitchell => mitchell(2.0 * x / radius),
+            FilterType::Lanczos => sinc(x) * sinc(x / radius),
+        }
+    }
+}
+
+/// Mitchell-Netravali cubic with B = C = 1/3 over `[0, 2]`
+fn mitchell(x: f32) -> f32 {
+    const B: f32 = 1.0 / 3.0;
+    const C: f32 = 1.0 / 3.0;
+    if x < 1.0 {
+        ((12.0 - 9.0 * B - 6.0 * C) * x * x * x
+            + (-18.0 + 12.0 * B + 6.0 * C) * x * x
+            + (6.0 - 2.0 * B))
+            / 6.0
+    } else {
+        ((-B - 6.0 * C) * x * x * x
+            + (6.0 * B + 30.0 * C) * x * x
+            + (-12.0 * B - 48.0 * C) * x
+            + (8.0 * B + 24.0 * C))
+            / 6.0
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x.abs() < 1.0e-5 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
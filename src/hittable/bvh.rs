@@ -1,7 +1,8 @@
 use crate::hittable::aabb::AABB;
-use crate::hittable::{HitRecord, Hittable};
+use crate::hittable::{HitRecord, Hittable, Primitive};
 use crate::light::{Light, PointLight};
 use crate::ray::Ray;
+use crate::rng::SampleRng;
 use crate::vec3::*;
 use crate::Scene;
 use crate::Sphere;
@@ -29,15 +30,18 @@ pub struct BVH {
 
 impl BVH {
     pub fn new(left: Box<dyn Hittable>, right: Box<dyn Hittable>, bbox: AABB) -> Self {
-        BVH {
-            left: left,
-            right: right,
-            bbox: bbox,
-        }
+        BVH { left, right, bbox }
     }
 
-    pub fn build(mut objects: Vec<Box<dyn Hittable>>, t0: f32, t1: f32) -> Box<dyn Hittable> {
-        let axis = rand::thread_rng().gen_range(0, 3);
+    /// Build a hierarchy over bounded objects, splitting each level along an axis drawn
+    /// from `rng`
+    pub fn build(
+        mut objects: Vec<Box<dyn Hittable>>,
+        t0: f32,
+        t1: f32,
+        rng: &mut SampleRng,
+    ) -> Box<dyn Hittable> {
+        let axis = rng.gen_range(0, 3);
         objects.sort_by(|a, b| {
             let lhit = a.bounding_box(0.0, 0.0).unwrap().min;
             let rhit = b.bounding_box(0.0, 0.0).unwrap().min;
@@ -59,8 +63,8 @@ impl BVH {
             _ => {
                 let mut a = objects;
                 let b = a.split_off(a.len() / 2);
-                let left = Self::build(b, t0, t1);
-                let right = Self::build(a, t0, t1);
+                let left = Self::build(b, t0, t1, rng);
+                let right = Self::build(a, t0, t1, rng);
                 let lbbox = left.bounding_box(t0, t1).unwrap();
                 let rbbox = right.bounding_box(t0, t1).unwrap();
                 // let bbox = AABB::surrounding_box(lbbox, rbbox);
@@ -72,7 +76,7 @@ impl BVH {
 }
 
 impl Hittable for BVH {
-    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
         if self.bbox.hit(r, t_min, t_max) {
             let lhit = self.left.hit(r, t_min, t_max);
             let rhit = self.right.hit(r, t_min, t_max);
@@ -96,16 +100,31 @@ impl Hittable for BVH {
     fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB> {
         Some(self.bbox)
     }
+
+    fn primitives(&self) -> Vec<Primitive> {
+        let mut primitives = self.left.primitives();
+        primitives.append(&mut self.right.primitives());
+        primitives
+    }
+
+    fn objects(&self) -> Vec<(u32, Vec<Primitive>)> {
+        let mut objects = self.left.objects();
+        objects.append(&mut self.right.objects());
+        objects
+    }
 }
 
 #[derive(Copy, Clone)]
 struct Null {}
 
 impl Hittable for Null {
-    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
         None
     }
     fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB> {
         None
     }
+    fn primitives(&self) -> Vec<Primitive> {
+        Vec::new()
+    }
 }
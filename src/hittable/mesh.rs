@@ -1,18 +1,18 @@
+use crate::hittable::mtl;
 use crate::hittable::triangle::{SimpleTriangle, Triangle};
 use crate::hittable::{aabb::AABB, bvh::BVH};
-use crate::hittable::{HitRecord, Hittable};
+use crate::hittable::{HitRecord, Hittable, Primitive};
 use crate::ray::Ray;
 use crate::vec3::*;
 use crate::Material;
 use crate::Scene;
 
 use indicatif::{ParallelProgressIterator, ProgressBar, ProgressIterator, ProgressStyle};
-use obj::Obj;
-use std::fs::{read_to_string, File};
-use std::io::BufReader;
+use na::{Matrix3, Matrix4};
+use std::collections::HashMap;
+use std::fs::read_to_string;
 use std::path::Path;
-use tri_mesh::{mesh::Mesh, MeshBuilder};
-use wavefront_obj;
+use wavefront_obj::obj::{self, Primitive as ObjPrimitive};
 
 #[derive(Clone)]
 pub struct TriangleMesh {
@@ -20,42 +20,157 @@ pub struct TriangleMesh {
 }
 
 impl TriangleMesh {
+    pub fn new(triangles: Vec<Triangle>) -> Self {
+        TriangleMesh { triangles }
+    }
+
     /// Construct `TriangleMesh` from a `.obj` file
     pub fn from_file(file: &str, material: Material) -> Self {
-        let mesh = MeshBuilder::new()
-            .with_obj(read_to_string(file).unwrap())
-            .build()
-            .unwrap();
+        Self::load(file, material).unwrap()
+    }
+
+    /// Construct `TriangleMesh` from a `.obj` file, returning an error if it cannot be read or parsed.
+    /// Vertex normals (`vn`) and texture coordinates (`vt`) are used when the faces reference
+    /// them. Faces without normals are smooth shaded with the average of the normals of the
+    /// faces around each vertex.
+    ///
+    /// Faces get the material named by their `usemtl` statement from the library given by
+    /// `mtllib`. `material` is only used for faces that have no such material, which is all
+    /// of them when the file names no library.
+    pub fn load(file: &str, material: Material) -> Result<Self, String> {
+        Self::load_with_progress(file, material, false)
+    }
+
+    /// Same as `load`, with a progress bar on the terminal over the faces of the file when
+    /// `progress` is set
+    pub fn load_with_progress(
+        file: &str,
+        material: Material,
+        progress: bool,
+    ) -> Result<Self, String> {
+        let source = read_to_string(file).map_err(|err| format!("{}: {}", file, err))?;
+        let set = obj::parse(source)
+            .map_err(|err| format!("{}:{}: {}", file, err.line_number, err.message))?;
+        let library = match &set.material_library {
+            Some(name) => {
+                let path = Path::new(file)
+                    .parent()
+                    .unwrap_or_else(|| Path::new(""))
+                    .join(name);
+                let mut materials = HashMap::new();
+                for (name, desc) in mtl::load(&path)? {
+                    let converted = desc.to_material().map_err(|err| {
+                        format!("{}: material `{}`: {}", path.display(), name, err)
+                    })?;
+                    materials.insert(name, converted);
+                }
+                Some((path, materials))
+            }
+            None => None,
+        };
+        let face_count = set
+            .objects
+            .iter()
+            .flat_map(|object| &object.geometry)
+            .map(|geometry| geometry.shapes.len())
+            .sum::<usize>();
         let mut triangles = Vec::new();
-        let pb = ProgressBar::new(mesh.no_faces() as u64);
+        let pb = if progress {
+            ProgressBar::new(face_count as u64)
+        } else {
+            ProgressBar::hidden()
+        };
         pb.set_style(
             ProgressStyle::default_bar()
                 .template("{spinner:.green} [{elapsed_precise}] [{bar:40.yellow}] ({eta})")
                 .progress_chars("=> "),
         );
 
-        for face_id in mesh.face_iter() {
-            mesh.face_area(face_id);
-            let (a, b, c) = mesh.face_positions(face_id);
-            let p1 = Point3::new(a[0] as f32, a[1] as f32, a[2] as f32);
-            let p2 = Point3::new(b[0] as f32, b[1] as f32, b[2] as f32);
-            let p3 = Point3::new(c[0] as f32, c[1] as f32, c[2] as f32);
-
-            let (ida, idb, idc) = mesh.face_vertices(face_id);
-            let na = mesh.vertex_normal(ida);
-            let nb = mesh.vertex_normal(idb);
-            let nc = mesh.vertex_normal(idc);
-            let n1 = Vec3::new(na[0] as f32, na[1] as f32, na[2] as f32);
-            let n2 = Vec3::new(nb[0] as f32, nb[1] as f32, nb[2] as f32);
-            let n3 = Vec3::new(nc[0] as f32, nc[1] as f32, nc[2] as f32);
-            let triangle = Triangle::new(p1, p2, p3, n1, n2, n3, material);
-            triangles.push(triangle);
-            pb.inc(1);
-        }
+        for object in &set.objects {
+            let position = |index: usize| -> Result<Point3, String> {
+                let v = object
+                    .vertices
+                    .get(index)
+                    .ok_or_else(|| format!("{}: vertex {} does not exist", file, index + 1))?;
+                Ok(Point3::new(v.x as f32, v.y as f32, v.z as f32))
+            };
+            let shapes = || object.geometry.iter().flat_map(|geometry| &geometry.shapes);
 
-        TriangleMesh {
-            triangles: triangles,
+            // Sum of the unit normals of the faces around each vertex
+            let mut vertex_normals = vec![Vec3::new(0.0, 0.0, 0.0); object.vertices.len()];
+            for shape in shapes() {
+                if let ObjPrimitive::Triangle(a, b, c) = shape.primitive {
+                    let (p1, p2, p3) = (position(a.0)?, position(b.0)?, position(c.0)?);
+                    let normal = (p2 - p1).cross(&(p3 - p1)).normalize();
+                    if normal.iter().all(|x| x.is_finite()) {
+                        for index in [a.0, b.0, c.0] {
+                            vertex_normals[index] += normal;
+                        }
+                    }
+                }
+            }
+
+            for geometry in &object.geometry {
+                let material = match (&library, &geometry.material_name) {
+                    (Some((path, materials)), Some(name)) => {
+                        materials.get(name).ok_or_else(|| {
+                            format!(
+                                "{}: material `{}` is not defined in {}",
+                                file,
+                                name,
+                                path.display()
+                            )
+                        })?
+                    }
+                    _ => &material,
+                };
+
+                for shape in &geometry.shapes {
+                    pb.inc(1);
+                    let (a, b, c) = match shape.primitive {
+                        ObjPrimitive::Triangle(a, b, c) => (a, b, c),
+                        // Points and lines have no area to render
+                        _ => continue,
+                    };
+                    let (p1, p2, p3) = (position(a.0)?, position(b.0)?, position(c.0)?);
+
+                    let normals = match (a.2, b.2, c.2) {
+                        (Some(na), Some(nb), Some(nc)) => {
+                            let normal = |index: usize| -> Result<Vec3, String> {
+                                let n = object.normals.get(index).ok_or_else(|| {
+                                    format!("{}: normal {} does not exist", file, index + 1)
+                                })?;
+                                Ok(Vec3::new(n.x as f32, n.y as f32, n.z as f32).normalize())
+                            };
+                            [normal(na)?, normal(nb)?, normal(nc)?]
+                        }
+                        _ => [a.0, b.0, c.0].map(|index| vertex_normals[index].normalize()),
+                    };
+
+                    let mut triangle = Triangle::new(
+                        p1,
+                        p2,
+                        p3,
+                        normals[0],
+                        normals[1],
+                        normals[2],
+                        material.clone(),
+                    );
+                    if let (Some(ta), Some(tb), Some(tc)) = (a.1, b.1, c.1) {
+                        let uv = |index: usize| -> Result<Vec2, String> {
+                            let t = object.tex_vertices.get(index).ok_or_else(|| {
+                                format!("{}: texture coordinate {} does not exist", file, index + 1)
+                            })?;
+                            Ok(Vec2::new(t.u as f32, t.v as f32))
+                        };
+                        triangle = triangle.with_uvs([uv(ta)?, uv(tb)?, uv(tc)?]);
+                    }
+                    triangles.push(triangle);
+                }
+            }
         }
+
+        Ok(TriangleMesh { triangles })
     }
 
     /// Shift and scale all the triangles in the mesh
@@ -67,6 +182,23 @@ impl TriangleMesh {
         }
     }
 
+    /// Apply an affine transformation to all the triangles in the mesh
+    pub fn transform(&mut self, matrix: &Matrix4<f32>) {
+        let normal_matrix = matrix
+            .fixed_slice::<3, 3>(0, 0)
+            .try_inverse()
+            .unwrap_or_else(Matrix3::identity)
+            .transpose();
+        for triangle in &mut self.triangles {
+            triangle.p1 = matrix.transform_point(&triangle.p1.into()).coords;
+            triangle.p2 = matrix.transform_point(&triangle.p2.into()).coords;
+            triangle.p3 = matrix.transform_point(&triangle.p3.into()).coords;
+            triangle.n1 = (normal_matrix * triangle.n1).normalize();
+            triangle.n2 = (normal_matrix * triangle.n2).normalize();
+            triangle.n3 = (normal_matrix * triangle.n3).normalize();
+        }
+    }
+
     /// Insert a `TriangleMesh` into a `World`
     pub fn insert_in(&self, world: &mut Scene, center: Point3, scale: f32) {
         for triangle in &self.triangles {
@@ -80,15 +212,16 @@ impl TriangleMesh {
                 triangle.n1,
                 triangle.n2,
                 triangle.n3,
-                triangle.material,
-            );
+                triangle.material.clone(),
+            )
+            .with_uvs(triangle.uvs);
             world.add(shifted);
         }
     }
 }
 
 impl Hittable for TriangleMesh {
-    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
         let mut closest = t_max;
         let mut closest_hit = None;
 
@@ -125,4 +258,11 @@ impl Hittable for TriangleMesh {
         }
         Some(output_box)
     }
+
+    fn primitives(&self) -> Vec<Primitive> {
+        self.triangles
+            .iter()
+            .map(|triangle| Primitive::Triangle(triangle.clone()))
+            .collect()
+    }
 }
@@ -1,25 +1,53 @@
 use crate::hittable::aabb::AABB;
+use crate::hittable::plane::Plane;
+use crate::hittable::sphere::Sphere;
+use crate::hittable::triangle::{SimpleTriangle, Triangle};
 use crate::material::Material;
 use crate::{ray::Ray, vec3::*};
 
 pub mod aabb;
 pub mod bvh;
 pub mod mesh;
+pub mod mtl;
 pub mod plane;
 pub mod sphere;
 pub mod triangle;
 
 pub trait Hittable: HittableClone + Send + Sync {
-    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord>;
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>>;
     fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB>;
+    /// Describe the object as the primitives it is made of
+    fn primitives(&self) -> Vec<Primitive>;
+    /// Primitives of each object added to a scene, with the object's identifier. Only scene
+    /// objects and the hierarchies built over them know which object a primitive belongs to.
+    fn objects(&self) -> Vec<(u32, Vec<Primitive>)> {
+        Vec::new()
+    }
+}
+
+/// Plain description of a single primitive, used to inspect and export scenes
+#[derive(Clone)]
+pub enum Primitive {
+    Sphere(Sphere),
+    Plane(Plane),
+    SimpleTriangle(SimpleTriangle),
+    Triangle(Triangle),
 }
 
 #[derive(Copy, Clone)]
-pub struct HitRecord {
+pub struct HitRecord<'a> {
     pub t: f32,
     pub point: Point3,
     pub normal: Vec3,
-    pub material: Material,
+    /// Surface coordinates of the hit, which textures are looked up with
+    pub uv: Vec2,
+    /// Derivatives of the hit point with respect to `u` and `v`. They lie in the surface
+    /// and are neither unit length nor perpendicular in general.
+    pub tangent: Vec3,
+    pub bitangent: Vec3,
+    pub material: &'a Material,
+    /// Position of the hit object among the objects added to the scene
+    pub object_id: u32,
 }
 
 pub trait HittableClone {
@@ -0,0 +1,325 @@
+//! Material libraries (`.mtl` files) referenced by OBJ meshes.
+//!
+//! Only the statements that have a counterpart among traci's materials are read; the
+//! rest, such as ambient colors and illumination models, are skipped. Each MTL material
+//! becomes the closest traci material:
+//!
+//! - an `Emissive` material if its emission `Ke` is not black,
+//! - a `Dielectric` with refraction index `Ni` if it is partly transparent (`d` below one,
+//!   or `Tr` above zero),
+//! - a `Metal` tinted by `Ks` if it has a specular color but no diffuse color, with a fuzz
+//!   that matches the highlight size given by the exponent `Ns`,
+//! - a `Phong` material if it has both, and otherwise a `Lambertian` one.
+//!
+//! The diffuse color `Kd` is replaced by the image named by `map_Kd` when there is one.
+
+use crate::material::Material;
+use crate::texture::{constant, ColorSpace, ImageTexture, WrapMode};
+use crate::vec3::*;
+
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Material as described in a material library
+#[derive(Clone, Debug)]
+pub struct MtlMaterial {
+    pub name: String,
+    /// `Kd`
+    pub diffuse: Color,
+    /// `Ks`
+    pub specular: Color,
+    /// `Ns`, the Phong exponent of the highlight
+    pub specular_exponent: f32,
+    /// `Ni`
+    pub refraction_index: Option<f32>,
+    /// `d`, one for opaque surfaces
+    pub dissolve: f32,
+    /// `Ke`
+    pub emission: Color,
+    /// `map_Kd`, resolved against the directory of the library
+    pub diffuse_map: Option<PathBuf>,
+}
+
+impl MtlMaterial {
+    fn new(name: &str) -> MtlMaterial {
+        MtlMaterial {
+            name: name.to_string(),
+            diffuse: Color::new(0.8, 0.8, 0.8),
+            specular: Color::new(0.0, 0.0, 0.0),
+            specular_exponent: 0.0,
+            refraction_index: None,
+            dissolve: 1.0,
+            emission: Color::new(0.0, 0.0, 0.0),
+            diffuse_map: None,
+        }
+    }
+
+    /// Closest traci material, loading the diffuse texture if there is one
+    pub fn to_material(&self) -> Result<Material, String> {
+        let black = Color::new(0.0, 0.0, 0.0);
+        if self.emission != black {
+            return Ok(Material::Emissive {
+                color: constant(self.emission),
+                strength: 1.0,
+            });
+        }
+        if self.dissolve < 1.0 {
+            return Ok(Material::Dielectric {
+                albedo: constant(Color::new(1.0, 1.0, 1.0)),
+                refraction_index: self.refraction_index.unwrap_or(1.5),
+            });
+        }
+
+        let albedo = match &self.diffuse_map {
+            Some(path) => Arc::new(ImageTexture::load(
+                path,
+                WrapMode::Repeat,
+                ColorSpace::Srgb,
+            )?),
+            None => constant(self.diffuse),
+        };
+        let has_diffuse = self.diffuse != black || self.diffuse_map.is_some();
+        Ok(match (has_diffuse, self.specular != black) {
+            (false, true) => Material::Metal {
+                albedo: constant(self.specular),
+                // Inverse of the highlight exponent `Material::phong` gives metals
+                fuzz: constant(Color::repeat(
+                    (2.0 / self.specular_exponent.max(2.0)).sqrt(),
+                )),
+            },
+            (true, true) => Material::Phong {
+                albedo,
+                ambient: 0.1,
+                diffuse: 1.0,
+                specular: (self.specular.x + self.specular.y + self.specular.z) / 3.0,
+                shininess: self.specular_exponent.max(1.0),
+            },
+            _ => Material::Lambertian { albedo },
+        })
+    }
+}
+
+/// Read the materials of a library, keyed by name
+pub fn load(path: &Path) -> Result<HashMap<String, MtlMaterial>, String> {
+    let source = read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    parse(&source, path)
+}
+
+/// Read the materials of the library `source`, which was loaded from `path`
+fn parse(source: &str, path: &Path) -> Result<HashMap<String, MtlMaterial>, String> {
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut materials = HashMap::new();
+    let mut current: Option<MtlMaterial> = None;
+
+    for (number, line) in source.lines().enumerate() {
+        let error = |message: String| format!("{}:{}: {}", path.display(), number + 1, message);
+        let line = line.split('#').next().unwrap_or("").trim();
+        let mut words = line.split_whitespace();
+        let keyword = match words.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        let arguments: Vec<&str> = words.collect();
+
+        if keyword == "newmtl" {
+            if let Some(material) = current.take() {
+                materials.insert(material.name.clone(), material);
+            }
+            current = Some(MtlMaterial::new(&arguments.join(" ")));
+            continue;
+        }
+        let material = match current.as_mut() {
+            Some(material) => material,
+            None => return Err(error(format!("`{}` before any `newmtl`", keyword))),
+        };
+
+        let numbers = || -> Result<Vec<f32>, String> {
+            arguments
+                .iter()
+                .map(|word| {
+                    word.parse::<f32>()
+                        .map_err(|_| error(format!("invalid number `{}` in `{}`", word, keyword)))
+                })
+                .collect()
+        };
+        let number = || -> Result<f32, String> {
+            match numbers()?.as_slice() {
+                [value, ..] => Ok(*value),
+                [] => Err(error(format!("`{}` needs a value", keyword))),
+            }
+        };
+        let color = || -> Result<Color, String> {
+            match numbers()?.as_slice() {
+                // A single value is a gray
+                [value] => Ok(Color::repeat(*value)),
+                [r, g, b, ..] => Ok(Color::new(*r, *g, *b)),
+                _ => Err(error(format!("`{}` needs an `r g b` color", keyword))),
+            }
+        };
+
+        match keyword {
+            "Kd" => material.diffuse = color()?,
+            "Ks" => material.specular = color()?,
+            "Ke" => material.emission = color()?,
+            "Ns" => material.specular_exponent = number()?,
+            "Ni" => material.refraction_index = Some(number()?),
+            "d" => material.dissolve = number()?,
+            "Tr" => material.dissolve = 1.0 - number()?,
+            "map_Kd" => {
+                // Options such as `-s 1 1 1` come before the file name
+                let file = arguments
+                    .last()
+                    .ok_or_else(|| error("`map_Kd` needs a file name".to_string()))?;
+                material.diffuse_map = Some(directory.join(file));
+            }
+            _ => {}
+        }
+    }
+    if let Some(material) = current {
+        materials.insert(material.name.clone(), material);
+    }
+
+    Ok(materials)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::mesh::TriangleMesh;
+    use crate::texture::{Texture, TextureKind};
+
+    /// Traci material made from the only material of the library `source`
+    fn material(source: &str) -> Material {
+        let materials = parse(source, Path::new("test.mtl")).unwrap();
+        assert_eq!(materials.len(), 1);
+        materials.values().next().unwrap().to_material().unwrap()
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("traci_{}_{}", std::process::id(), name));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Color of `texture` at the middle of its surface coordinates
+    fn value(texture: &Arc<dyn Texture>) -> Color {
+        texture.value(Vec2::new(0.5, 0.5), Point3::new(0.0, 0.0, 0.0))
+    }
+
+    #[test]
+    fn emission_gives_emissive() {
+        match material("newmtl lamp\nKd 0.5 0.5 0.5\nKe 2 3 4\n") {
+            Material::Emissive { color, strength } => {
+                assert_eq!(value(&color), Color::new(2.0, 3.0, 4.0));
+                assert_eq!(strength, 1.0);
+            }
+            other => panic!("expected an emissive material, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn transparency_gives_dielectric() {
+        for source in [
+            "newmtl glass\nKd 1 1 1\nNi 1.33\nd 0.5\n",
+            "newmtl glass\nKd 1 1 1\nNi 1.33\nTr 0.5\n",
+        ] {
+            match material(source) {
+                Material::Dielectric {
+                    refraction_index, ..
+                } => assert_eq!(refraction_index, 1.33),
+                other => panic!("expected a dielectric, got {:?}", other),
+            }
+        }
+        match material("newmtl glass\nd 0.2\n") {
+            Material::Dielectric {
+                refraction_index, ..
+            } => assert_eq!(refraction_index, 1.5),
+            other => panic!("expected a dielectric, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn specular_alone_gives_metal() {
+        match material("newmtl steel\nKd 0 0 0\nKs 0.9 0.8 0.7\nNs 50\n") {
+            Material::Metal { albedo, fuzz } => {
+                assert_eq!(value(&albedo), Color::new(0.9, 0.8, 0.7));
+                assert!((value(&fuzz).x - (2.0f32 / 50.0).sqrt()).abs() < 1.0e-6);
+            }
+            other => panic!("expected a metal, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn diffuse_and_specular_give_phong() {
+        match material("newmtl plastic\nKd 0.2 0.4 0.6\nKs 0.3 0.3 0.6\nNs 32\n") {
+            Material::Phong {
+                albedo,
+                specular,
+                shininess,
+                ..
+            } => {
+                assert_eq!(value(&albedo), Color::new(0.2, 0.4, 0.6));
+                assert!((specular - 0.4).abs() < 1.0e-6);
+                assert_eq!(shininess, 32.0);
+            }
+            other => panic!("expected a Phong material, got {:?}", other),
+        }
+        match material("newmtl chalk\nKd 0.2 0.4 0.6\n") {
+            Material::Lambertian { albedo } => {
+                assert_eq!(value(&albedo), Color::new(0.2, 0.4, 0.6))
+            }
+            other => panic!("expected a Lambertian material, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn diffuse_map_replaces_diffuse_color() {
+        let dir = temp_dir("map_kd");
+        image::RgbImage::from_pixel(2, 2, image::Rgb([255, 0, 0]))
+            .save(dir.join("red.png"))
+            .unwrap();
+
+        let materials = parse(
+            "newmtl painted\nKd 0 1 0\nmap_Kd -s 1 1 1 red.png\n",
+            &dir.join("test.mtl"),
+        )
+        .unwrap();
+        let painted = &materials["painted"];
+        assert_eq!(painted.diffuse_map, Some(dir.join("red.png")));
+        let converted = painted.to_material().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        match converted {
+            Material::Lambertian { albedo } => {
+                assert!(matches!(albedo.kind(), TextureKind::Image(_)));
+                // Red from the image rather than the green `Kd`
+                assert_eq!(value(&albedo), Color::new(1.0, 0.0, 0.0));
+            }
+            other => panic!("expected a Lambertian material, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn unknown_material_is_an_error() {
+        let dir = temp_dir("usemtl");
+        std::fs::write(dir.join("scene.mtl"), "newmtl known\nKd 1 1 1\n").unwrap();
+        let obj = dir.join("scene.obj");
+        std::fs::write(
+            &obj,
+            "mtllib scene.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl missing\nf 1 2 3\n",
+        )
+        .unwrap();
+
+        let result = TriangleMesh::load(
+            obj.to_str().unwrap(),
+            Material::Lambertian {
+                albedo: constant(Color::new(0.5, 0.5, 0.5)),
+            },
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+        let err = result.err().expect("loading should fail");
+        assert!(err.contains("material `missing` is not defined"), "{}", err);
+    }
+}
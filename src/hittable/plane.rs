@@ -1,11 +1,11 @@
 use crate::hittable::aabb::AABB;
 use crate::hittable::bvh::BoundingBox;
-use crate::hittable::{HitRecord, Hittable};
+use crate::hittable::{HitRecord, Hittable, Primitive};
 use crate::material::*;
 use crate::ray::Ray;
 use crate::vec3::*;
 
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 pub struct Plane {
     pub point: Point3,
     pub normal: Vec3,
@@ -23,7 +23,7 @@ impl Plane {
 }
 
 impl Hittable for Plane {
-    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
         if r.direction.dot(&self.normal) == 0.0 {
             return None;
         }
@@ -35,11 +35,19 @@ impl Hittable for Plane {
 
         let p = r.at(root);
 
+        // Distances along two directions within the plane, measured from `point`
+        let (tangent, bitangent) = orthonormal_basis(self.normal);
+        let offset = p - self.point;
+
         let rec = HitRecord {
             t: root,
             point: p,
             normal: self.normal,
-            material: self.material,
+            uv: Vec2::new(offset.dot(&tangent), offset.dot(&bitangent)),
+            tangent,
+            bitangent,
+            material: &self.material,
+            object_id: 0,
         };
 
         Some(rec)
@@ -48,6 +56,10 @@ impl Hittable for Plane {
     fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB> {
         None
     }
+
+    fn primitives(&self) -> Vec<Primitive> {
+        vec![Primitive::Plane(self.clone())]
+    }
 }
 
 impl BoundingBox for Plane {
@@ -1,10 +1,10 @@
 use crate::hittable::bvh::BoundingBox;
-use crate::hittable::{aabb::AABB, HitRecord, Hittable};
+use crate::hittable::{aabb::AABB, HitRecord, Hittable, Primitive};
 use crate::material::*;
 use crate::ray::Ray;
 use crate::vec3::*;
 
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 pub struct Sphere {
     pub center: Vec3,
     pub radius: f32,
@@ -22,7 +22,7 @@ impl Sphere {
 }
 
 impl Hittable for Sphere {
-    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
         let oc = r.origin - self.center;
         let a = r.direction.norm_squared();
         let b = oc.dot(&r.direction);
@@ -44,11 +44,17 @@ impl Hittable for Sphere {
         }
 
         let p = r.at(root);
+        let normal = (p - self.center) / self.radius;
+        let (tangent, bitangent) = sphere_derivatives(normal, self.radius);
         let rec = HitRecord {
             t: root,
             point: p,
-            normal: (p - self.center) / self.radius,
-            material: self.material,
+            normal,
+            uv: sphere_uv(normal),
+            tangent,
+            bitangent,
+            material: &self.material,
+            object_id: 0,
         };
 
         Some(rec)
@@ -60,4 +66,42 @@ impl Hittable for Sphere {
             max: self.center + (self.radius * Vec3::new(1.0, 1.0, 1.0)),
         })
     }
+
+    fn primitives(&self) -> Vec<Primitive> {
+        vec![Primitive::Sphere(self.clone())]
+    }
+}
+
+/// Longitude and latitude of a point on the unit sphere, both scaled to `[0, 1]`, with
+/// `u = 0` along -x and `v = 0` at the bottom
+fn sphere_uv(normal: Vec3) -> Vec2 {
+    let theta = (-normal.y).clamp(-1.0, 1.0).acos();
+    let phi = (-normal.z).atan2(normal.x) + std::f32::consts::PI;
+    Vec2::new(
+        phi / (2.0 * std::f32::consts::PI),
+        theta / std::f32::consts::PI,
+    )
+}
+
+/// Derivatives of the point with outward `normal` on a sphere of `radius` with respect to
+/// the coordinates of `sphere_uv`. At the poles, where longitude is undefined, any frame
+/// perpendicular to the normal is returned.
+fn sphere_derivatives(normal: Vec3, radius: f32) -> (Vec3, Vec3) {
+    let ring = (normal.x * normal.x + normal.z * normal.z).sqrt();
+    if ring < 1.0e-6 {
+        let (tangent, bitangent) = orthonormal_basis(normal);
+        return (
+            2.0 * std::f32::consts::PI * radius * tangent,
+            std::f32::consts::PI * radius * bitangent,
+        );
+    }
+    let tangent = 2.0 * std::f32::consts::PI * radius * Vec3::new(normal.z, 0.0, -normal.x);
+    let bitangent = std::f32::consts::PI
+        * radius
+        * Vec3::new(
+            -normal.y * normal.x / ring,
+            ring,
+            -normal.y * normal.z / ring,
+        );
+    (tangent, bitangent)
 }
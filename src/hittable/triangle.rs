@@ -1,15 +1,17 @@
 use crate::hittable::bvh::BoundingBox;
-use crate::hittable::{aabb::AABB, HitRecord, Hittable};
+use crate::hittable::{aabb::AABB, HitRecord, Hittable, Primitive};
 use crate::material::*;
 use crate::ray::Ray;
 use crate::vec3::*;
 
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 /// Triangle without interpolated normals
 pub struct SimpleTriangle {
     pub p1: Point3,
     pub p2: Point3,
     pub p3: Point3,
+    /// Surface coordinates of the three vertices
+    pub uvs: [Vec2; 3],
     pub material: Material,
 }
 
@@ -19,14 +21,27 @@ impl SimpleTriangle {
             p1,
             p2,
             p3,
+            uvs: default_uvs(),
             material,
         }
     }
+
+    /// Use the given surface coordinates at the three vertices
+    pub fn with_uvs(mut self, uvs: [Vec2; 3]) -> Self {
+        self.uvs = uvs;
+        self
+    }
+
+    /// Surface coordinates of the point with barycentric weights `weights` of the second
+    /// and third vertices
+    pub fn uv_at(&self, weights: Vec2) -> Vec2 {
+        interpolate_uv(&self.uvs, weights.x, weights.y)
+    }
 }
 
 impl Hittable for SimpleTriangle {
     // Uses the Möller–Trumbore intersection algorithm
-    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
         let epsilon = 1.0e-5_f32;
 
         let edge1 = self.p2 - self.p1;
@@ -46,7 +61,7 @@ impl Hittable for SimpleTriangle {
         let q = s.cross(&edge1);
         let v = f * r.direction.dot(&q);
 
-        if u < 0.0 || u > 1.0 {
+        if !(0.0..=1.0).contains(&u) {
             return None;
         };
 
@@ -62,63 +77,33 @@ impl Hittable for SimpleTriangle {
             return None;
         }
 
+        let normal = edge1.cross(&edge2).normalize();
+        let (tangent, bitangent) = surface_derivatives(edge1, edge2, &self.uvs, normal);
+
         let rec = HitRecord {
-            t: t,
+            t,
             point: r.at(t),
-            normal: edge1.cross(&edge2).normalize(),
-            material: self.material,
+            normal,
+            uv: interpolate_uv(&self.uvs, u, v),
+            tangent,
+            bitangent,
+            material: &self.material,
+            object_id: 0,
         };
 
         Some(rec)
     }
 
     fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB> {
-        let max_x = [self.p1.x, self.p2.x, self.p3.x]
-            .iter()
-            .fold(
-                std::f32::MIN,
-                |max, val| if val > &max { *val } else { max },
-            );
-        let max_y = [self.p1.y, self.p2.y, self.p3.y]
-            .iter()
-            .fold(
-                std::f32::MIN,
-                |max, val| if val > &max { *val } else { max },
-            );
-        let max_z = [self.p1.z, self.p2.z, self.p3.z]
-            .iter()
-            .fold(
-                std::f32::MIN,
-                |max, val| if val > &max { *val } else { max },
-            );
-
-        let min_x = [self.p1.x, self.p2.x, self.p3.x]
-            .iter()
-            .fold(
-                std::f32::MAX,
-                |min, val| if val < &min { min } else { *val },
-            );
-        let min_y = [self.p1.y, self.p2.y, self.p3.y]
-            .iter()
-            .fold(
-                std::f32::MAX,
-                |min, val| if val < &min { min } else { *val },
-            );
-        let min_z = [self.p1.z, self.p2.z, self.p3.z]
-            .iter()
-            .fold(
-                std::f32::MAX,
-                |min, val| if val < &min { min } else { *val },
-            );
-
-        Some(AABB {
-            min: Point3::new(max_x, max_y, max_z),
-            max: Point3::new(min_x, min_y, min_z),
-        })
+        Some(triangle_box(self.p1, self.p2, self.p3))
+    }
+
+    fn primitives(&self) -> Vec<Primitive> {
+        vec![Primitive::SimpleTriangle(self.clone())]
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 pub struct Triangle {
     pub p1: Point3,
     pub p2: Point3,
@@ -126,6 +111,8 @@ pub struct Triangle {
     pub n1: Vec3,
     pub n2: Vec3,
     pub n3: Vec3,
+    /// Surface coordinates of the three vertices
+    pub uvs: [Vec2; 3],
     pub material: Material,
 }
 
@@ -147,10 +134,17 @@ impl Triangle {
             n1,
             n2,
             n3,
+            uvs: default_uvs(),
             material,
         }
     }
 
+    /// Use the given surface coordinates at the three vertices
+    pub fn with_uvs(mut self, uvs: [Vec2; 3]) -> Self {
+        self.uvs = uvs;
+        self
+    }
+
     /// Compute barycentric coordinates w.r.t this triangle given a point
     fn barycentric(&self, p: Point3) -> (f32, f32, f32) {
         let v0: Vec3 = self.p2 - self.p1;
@@ -178,7 +172,7 @@ impl Triangle {
 
 impl Hittable for Triangle {
     // Uses the Möller–Trumbore intersection algorithm with interpolated normals
-    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
         let epsilon = 1.0e-5_f32;
 
         let edge1 = self.p2 - self.p1;
@@ -198,7 +192,7 @@ impl Hittable for Triangle {
         let q = s.cross(&edge1);
         let v = f * r.direction.dot(&q);
 
-        if u < 0.0 || u > 1.0 {
+        if !(0.0..=1.0).contains(&u) {
             return None;
         };
 
@@ -215,59 +209,72 @@ impl Hittable for Triangle {
         }
 
         let point = r.at(t);
+        let normal = self.normal(point);
+        let (tangent, bitangent) = surface_derivatives(edge1, edge2, &self.uvs, normal);
 
         let rec = HitRecord {
-            t: t,
-            point: point,
-            normal: self.normal(point),
-            material: self.material,
+            t,
+            point,
+            normal,
+            uv: interpolate_uv(&self.uvs, u, v),
+            tangent,
+            bitangent,
+            material: &self.material,
+            object_id: 0,
         };
 
         Some(rec)
     }
 
     fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB> {
-        let max_x = [self.p1.x, self.p2.x, self.p3.x]
-            .iter()
-            .fold(
-                std::f32::MIN,
-                |max, val| if val > &max { *val } else { max },
-            );
-        let max_y = [self.p1.y, self.p2.y, self.p3.y]
-            .iter()
-            .fold(
-                std::f32::MIN,
-                |max, val| if val > &max { *val } else { max },
-            );
-        let max_z = [self.p1.z, self.p2.z, self.p3.z]
-            .iter()
-            .fold(
-                std::f32::MIN,
-                |max, val| if val > &max { *val } else { max },
-            );
-
-        let min_x = [self.p1.x, self.p2.x, self.p3.x]
-            .iter()
-            .fold(
-                std::f32::MAX,
-                |min, val| if val < &min { min } else { *val },
-            );
-        let min_y = [self.p1.y, self.p2.y, self.p3.y]
-            .iter()
-            .fold(
-                std::f32::MAX,
-                |min, val| if val < &min { min } else { *val },
-            );
-        let min_z = [self.p1.z, self.p2.z, self.p3.z]
-            .iter()
-            .fold(
-                std::f32::MAX,
-                |min, val| if val < &min { min } else { *val },
-            );
-
-        Some(AABB {
-            min: Point3::new(max_x, max_y, max_z),
-            max: Point3::new(min_x, min_y, min_z),
-        })
+        Some(triangle_box(self.p1, self.p2, self.p3))
+    }
+
+    fn primitives(&self) -> Vec<Primitive> {
+        vec![Primitive::Triangle(self.clone())]
+    }
+}
+
+/// Surface coordinates used when a triangle has none of its own, which make `uv` the
+/// barycentric weights of the second and third vertices
+fn default_uvs() -> [Vec2; 3] {
+    [
+        Vec2::new(0.0, 0.0),
+        Vec2::new(1.0, 0.0),
+        Vec2::new(0.0, 1.0),
+    ]
+}
+
+/// Surface coordinates at the point with barycentric weights `u` and `v` of the second
+/// and third vertices
+fn interpolate_uv(uvs: &[Vec2; 3], u: f32, v: f32) -> Vec2 {
+    (1.0 - u - v) * uvs[0] + u * uvs[1] + v * uvs[2]
+}
+
+/// Derivatives of the position with respect to the surface coordinates, found by solving
+/// for the combination of edges that spans one unit of `u` and of `v`. Triangles whose
+/// coordinates do not span an area get an arbitrary frame around `normal` instead.
+fn surface_derivatives(edge1: Vec3, edge2: Vec3, uvs: &[Vec2; 3], normal: Vec3) -> (Vec3, Vec3) {
+    let duv1 = uvs[1] - uvs[0];
+    let duv2 = uvs[2] - uvs[0];
+    let determinant = duv1.x * duv2.y - duv1.y * duv2.x;
+    if determinant.abs() < 1.0e-12 {
+        return orthonormal_basis(normal);
     }
+    let tangent = (duv2.y * edge1 - duv1.y * edge2) / determinant;
+    let bitangent = (duv1.x * edge2 - duv2.x * edge1) / determinant;
+    (tangent, bitangent)
+}
+
+/// Thickness added around triangle bounding boxes, so that triangles lying in an axis plane
+/// still enclose a volume that rays can hit
+const BOX_PADDING: f32 = 1.0e-4;
+
+/// Bounding box of the triangle with corners `p1`, `p2` and `p3`
+fn triangle_box(p1: Point3, p2: Point3, p3: Point3) -> AABB {
+    let padding = Vec3::repeat(BOX_PADDING);
+    AABB::new(
+        p1.inf(&p2).inf(&p3) - padding,
+        p1.sup(&p2).sup(&p3) + padding,
+    )
 }
@@ -0,0 +1,458 @@
+use crate::aov::AovHit;
+use crate::bsdf::{Bsdf, Sampled};
+use crate::hittable::{HitRecord, Hittable};
+use crate::material::{facing_normal, Material};
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::scene::Scene;
+use crate::vec3::*;
+
+use std::fmt;
+use std::str::FromStr;
+
+/// Method used to compute the color seen along a camera ray
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Integrator {
+    /// Recursive material scattering (`ray_color`)
+    Naive,
+    /// Blinn-Phong shading with recursive reflection and refraction (`ray_color_phong`)
+    Phong,
+    /// Path tracing with explicit light sampling (`path_trace`)
+    Path,
+}
+
+impl Integrator {
+    pub fn ray_color(
+        &self,
+        ray: Ray,
+        world: &Scene,
+        depth: i32,
+        sampler: &mut dyn Sampler,
+    ) -> Color {
+        self.radiance(ray, world, depth, sampler).total()
+    }
+
+    /// Light arriving along `ray`, split into direct and indirect light. Only the path
+    /// tracer tells the two apart; the other integrators report everything as direct.
+    pub fn radiance(
+        &self,
+        ray: Ray,
+        world: &Scene,
+        depth: i32,
+        sampler: &mut dyn Sampler,
+    ) -> Radiance {
+        self.trace(ray, world, depth, sampler, None)
+    }
+
+    /// `radiance`, along with the surface `ray` hit first, so output variables don't need
+    /// a second trace of the camera ray
+    pub fn radiance_with_hit(
+        &self,
+        ray: Ray,
+        world: &Scene,
+        depth: i32,
+        sampler: &mut dyn Sampler,
+    ) -> (Radiance, Option<AovHit>) {
+        let mut first_hit = None;
+        let radiance = self.trace(ray, world, depth, sampler, Some(&mut first_hit));
+        (radiance, first_hit)
+    }
+
+    fn trace(
+        &self,
+        ray: Ray,
+        world: &Scene,
+        depth: i32,
+        sampler: &mut dyn Sampler,
+        first_hit: Option<&mut Option<AovHit>>,
+    ) -> Radiance {
+        match self {
+            Integrator::Naive => {
+                Radiance::direct(trace_naive(ray, world, depth, sampler, first_hit))
+            }
+            Integrator::Phong => {
+                Radiance::direct(trace_phong(ray, world, depth, sampler, first_hit))
+            }
+            Integrator::Path => path_trace_split(ray, world, depth, sampler, first_hit),
+        }
+    }
+}
+
+/// Store the surface `ray` hit in `first_hit`, when the caller asked for it
+fn record_first_hit(first_hit: Option<&mut Option<AovHit>>, ray: Ray, hit: Option<HitRecord>) {
+    if let Some(first_hit) = first_hit {
+        *first_hit = hit.map(|rec| AovHit::new(ray, &rec));
+    }
+}
+
+/// Light arriving along a camera ray. Direct light reached the first surface straight
+/// from an emitter, or is an emitter seen by the camera; indirect light bounced at least
+/// twice.
+#[derive(Copy, Clone, Debug)]
+pub struct Radiance {
+    pub direct: Color,
+    pub indirect: Color,
+}
+
+impl Radiance {
+    fn direct(direct: Color) -> Radiance {
+        Radiance {
+            direct,
+            indirect: Color::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    pub fn total(&self) -> Color {
+        self.direct + self.indirect
+    }
+
+    /// Add light found at path vertex `depth`, where emission seen from the first surface
+    /// (`depth` 1) still counts as direct
+    fn add_emission(&mut self, depth: i32, light: Color) {
+        if depth <= 1 {
+            self.direct += light;
+        } else {
+            self.indirect += light;
+        }
+    }
+
+    /// Add light sampled from vertex `depth` toward an emitter
+    fn add_light_sample(&mut self, depth: i32, light: Color) {
+        if depth == 0 {
+            self.direct += light;
+        } else {
+            self.indirect += light;
+        }
+    }
+}
+
+impl FromStr for Integrator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "naive" => Ok(Integrator::Naive),
+            "phong" => Ok(Integrator::Phong),
+            "path" => Ok(Integrator::Path),
+            _ => Err(format!(
+                "unknown integrator `{}`, expected `naive`, `phong` or `path`",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Integrator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Integrator::Naive => write!(f, "naive"),
+            Integrator::Phong => write!(f, "phong"),
+            Integrator::Path => write!(f, "path"),
+        }
+    }
+}
+
+pub fn ray_color(ray: Ray, world: &Scene, depth: i32, sampler: &mut dyn Sampler) -> Color {
+    trace_naive(ray, world, depth, sampler, None)
+}
+
+fn trace_naive(
+    ray: Ray,
+    world: &Scene,
+    depth: i32,
+    sampler: &mut dyn Sampler,
+    first_hit: Option<&mut Option<AovHit>>,
+) -> Color {
+    if depth <= 0 {
+        return Color::new(0.0, 0.0, 0.0);
+    }
+
+    let hit = world.hit(ray, 0.001, f32::MAX);
+    record_first_hit(first_hit, ray, hit);
+    let t_max = hit.map_or(f32::MAX, |rec| rec.t);
+    if let Some(light_hit) = world.hit_light(ray, 0.001, t_max) {
+        return light_hit.radiance;
+    }
+
+    match hit {
+        Some(rec) => {
+            let emitted = rec.material.emitted(ray, rec);
+            let wo = -ray.direction.normalize();
+            match rec.material.bsdf(&rec).sample(wo, bsdf_numbers(sampler)) {
+                Sampled::Scattered(sample) => {
+                    let scattered = Ray::new(rec.point, sample.wi);
+                    emitted
+                        + ray_color(scattered, world, depth - 1, sampler)
+                            .component_mul(&sample.weight)
+                }
+                Sampled::Absorbed => emitted,
+            }
+        }
+        None => world.background().radiance(ray.direction),
+    }
+}
+
+/// Whitted style ray tracer: Blinn-Phong shading of every light with a shadow ray per
+/// light, plus recursively traced reflections and refractions for metals and dielectrics
+pub fn ray_color_phong(ray: Ray, world: &Scene, depth: i32, sampler: &mut dyn Sampler) -> Color {
+    trace_phong(ray, world, depth, sampler, None)
+}
+
+fn trace_phong(
+    ray: Ray,
+    world: &Scene,
+    depth: i32,
+    sampler: &mut dyn Sampler,
+    first_hit: Option<&mut Option<AovHit>>,
+) -> Color {
+    if depth <= 0 {
+        return Color::new(0.0, 0.0, 0.0);
+    }
+
+    let hit = world.hit(ray, 0.001, f32::MAX);
+    record_first_hit(first_hit, ray, hit);
+    let t_max = hit.map_or(f32::MAX, |rec| rec.t);
+    if let Some(light_hit) = world.hit_light(ray, 0.001, t_max) {
+        return light_hit.radiance;
+    }
+
+    let rec = match hit {
+        Some(rec) => rec,
+        None => return world.background().radiance(ray.direction),
+    };
+
+    let phong = rec.material.phong(rec.uv, rec.point);
+    let albedo = rec.material.albedo(rec.uv, rec.point);
+    let normal = facing_normal(ray, rec);
+    let view = -ray.direction.normalize();
+    // Metals tint their highlights, everything else reflects the light's color
+    let tint = match rec.material {
+        Material::Metal { albedo, .. } => albedo.value(rec.uv, rec.point),
+        Material::Principled { .. } => {
+            let principled = rec.material.principled(rec.uv, rec.point).unwrap();
+            (1.0 - principled.metallic) * Color::new(1.0, 1.0, 1.0)
+                + principled.metallic * principled.base_color
+        }
+        _ => Color::new(1.0, 1.0, 1.0),
+    };
+
+    let mut color = rec.material.emitted(ray, rec) + phong.ambient * albedo;
+
+    for light in &world.lights {
+        let sample = light.sample(rec.point, sampler);
+        let cosine = normal.dot(&sample.wi);
+        if cosine <= 0.0 || sample.radiance == Color::new(0.0, 0.0, 0.0) {
+            continue;
+        }
+
+        // Only objects between the surface and the light cast shadows
+        let shadow_ray = Ray::new(rec.point, sample.wi);
+        if world
+            .hit(shadow_ray, 0.001, sample.distance - 0.001)
+            .is_some()
+        {
+            continue;
+        }
+
+        let irradiance = match sample.pdf {
+            Some(pdf) if pdf > 0.0 => sample.radiance / pdf,
+            Some(_) => continue,
+            None => sample.radiance,
+        };
+
+        let half = (sample.wi + view).normalize();
+        let diffuse = phong.diffuse * cosine * albedo;
+        let specular = phong.specular * normal.dot(&half).max(0.0).powf(phong.shininess) * tint;
+        color += (diffuse + specular).component_mul(&irradiance);
+    }
+
+    match rec.material {
+        Material::Metal { .. } | Material::Dielectric { .. } => {
+            if let Sampled::Scattered(sample) =
+                rec.material.bsdf(&rec).sample(view, bsdf_numbers(sampler))
+            {
+                let scattered = Ray::new(rec.point, sample.wi);
+                color += ray_color_phong(scattered, world, depth - 1, sampler)
+                    .component_mul(&sample.weight);
+            }
+        }
+        _ => {}
+    }
+
+    color
+}
+
+/// Number of bounces before Russian roulette may terminate a path
+const ROULETTE_DEPTH: i32 = 3;
+
+/// Path tracer that samples every light at each surface hit and continues the path by
+/// sampling the material. Area lights can be reached both ways, so the two estimates are
+/// combined with multiple importance sampling. Paths are terminated with Russian roulette;
+/// `max_depth` only acts as a safety limit.
+pub fn path_trace(ray: Ray, world: &Scene, max_depth: i32, sampler: &mut dyn Sampler) -> Color {
+    path_trace_split(ray, world, max_depth, sampler, None).total()
+}
+
+/// `path_trace`, keeping direct and indirect light apart
+fn path_trace_split(
+    ray: Ray,
+    world: &Scene,
+    max_depth: i32,
+    sampler: &mut dyn Sampler,
+    mut first_hit: Option<&mut Option<AovHit>>,
+) -> Radiance {
+    let mut radiance = Radiance::direct(Color::new(0.0, 0.0, 0.0));
+    let mut throughput = Color::new(1.0, 1.0, 1.0);
+    let mut ray = ray;
+    // Density of the material sample that produced `ray`, zero for camera rays and
+    // specular bounces where no light sampling took place
+    let mut scatter_pdf = 0.0;
+
+    for depth in 0..max_depth {
+        let hit = world.hit(ray, 0.001, f32::MAX);
+        if depth == 0 {
+            record_first_hit(first_hit.take(), ray, hit);
+        }
+        let t_max = hit.map_or(f32::MAX, |rec| rec.t);
+
+        if let Some(light_hit) = world.hit_light(ray, 0.001, t_max) {
+            let weight = if scatter_pdf > 0.0 {
+                power_heuristic(scatter_pdf, light_hit.pdf)
+            } else {
+                1.0
+            };
+            radiance.add_emission(
+                depth,
+                weight * throughput.component_mul(&light_hit.radiance),
+            );
+            break;
+        }
+
+        let rec = match hit {
+            Some(rec) => rec,
+            None => {
+                let background = world.background();
+                let weight = if scatter_pdf > 0.0 {
+                    power_heuristic(scatter_pdf, background.pdf(ray.direction.normalize()))
+                } else {
+                    1.0
+                };
+                radiance.add_emission(
+                    depth,
+                    weight * throughput.component_mul(&background.radiance(ray.direction)),
+                );
+                break;
+            }
+        };
+
+        // Emissive triangles are lights and were handled above, so this only picks up
+        // emitters that cannot be sampled, such as glowing spheres
+        radiance.add_emission(
+            depth,
+            throughput.component_mul(&rec.material.emitted(ray, rec)),
+        );
+        let bsdf = rec.material.bsdf(&rec);
+        let wo = -ray.direction.normalize();
+        radiance.add_light_sample(
+            depth,
+            throughput.component_mul(&direct_light(&bsdf, wo, &rec, world, sampler)),
+        );
+
+        let sample = match bsdf.sample(wo, bsdf_numbers(sampler)) {
+            Sampled::Scattered(sample) => sample,
+            Sampled::Absorbed => break,
+        };
+        // Light sampling cannot reach specular directions, so their hits need no weighting
+        scatter_pdf = if sample.lobe.is_specular() {
+            0.0
+        } else {
+            sample.pdf
+        };
+        throughput = throughput.component_mul(&sample.weight);
+
+        if depth >= ROULETTE_DEPTH {
+            let survival = throughput.max().min(0.95);
+            if sampler.next_1d() >= survival {
+                break;
+            }
+            throughput /= survival;
+        }
+
+        ray = Ray::new(rec.point, sample.wi);
+    }
+
+    radiance
+}
+
+/// Light arriving at a surface directly from one sample on each light and on the
+/// background, with shadow rays
+fn direct_light(
+    bsdf: &Bsdf,
+    wo: Vec3,
+    rec: &HitRecord,
+    world: &Scene,
+    sampler: &mut dyn Sampler,
+) -> Color {
+    let mut light = Color::new(0.0, 0.0, 0.0);
+
+    for source in &world.lights {
+        let sample = source.sample(rec.point, sampler);
+        if sample.radiance == Color::new(0.0, 0.0, 0.0) {
+            continue;
+        }
+
+        let f = bsdf.eval(wo, sample.wi);
+        if f == Color::new(0.0, 0.0, 0.0) {
+            continue;
+        }
+
+        let shadow_ray = Ray::new(rec.point, sample.wi);
+        if world
+            .hit(shadow_ray, 0.001, sample.distance - 0.001)
+            .is_some()
+        {
+            continue;
+        }
+
+        light += match sample.pdf {
+            Some(pdf) => {
+                let weight = power_heuristic(pdf, bsdf.pdf(wo, sample.wi));
+                weight * f.component_mul(&sample.radiance) / pdf
+            }
+            None => f.component_mul(&sample.radiance),
+        };
+    }
+
+    if let Some(sample) = world.background().sample(sampler) {
+        let f = bsdf.eval(wo, sample.wi);
+        let shadow_ray = Ray::new(rec.point, sample.wi);
+        if f != Color::new(0.0, 0.0, 0.0)
+            && sample.pdf > 0.0
+            && world.hit(shadow_ray, 0.001, f32::MAX).is_none()
+            && world.hit_light(shadow_ray, 0.001, f32::MAX).is_none()
+        {
+            let weight = power_heuristic(sample.pdf, bsdf.pdf(wo, sample.wi));
+            light += weight * f.component_mul(&sample.radiance) / sample.pdf;
+        }
+    }
+
+    light
+}
+
+/// Uniform numbers for `Bsdf::sample`: one to choose a lobe and two for the direction
+fn bsdf_numbers(sampler: &mut dyn Sampler) -> [f32; 3] {
+    let choice = sampler.next_1d();
+    let (u1, u2) = sampler.next_2d();
+    [choice, u1, u2]
+}
+
+/// Multiple importance sampling weight for a sample drawn with density `pdf` when the
+/// same point could also have been drawn with density `other_pdf`
+fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a + b == 0.0 {
+        0.0
+    } else {
+        a / (a + b)
+    }
+}
@@ -0,0 +1,84 @@
+//! Traci is a multi-core CPU ray tracer.
+//!
+//! A render needs a [`Scene`], a [`Camera`] and render [`Settings`]:
+//!
+//! ```no_run
+//! use traci::{CameraSettings, Renderer, Scene, Settings, Material, Sphere};
+//! use traci::texture::constant;
+//! use traci::vec3::{Color, Point3};
+//!
+//! let mut scene = Scene::new();
+//! scene.add(Sphere::new(
+//!     Point3::new(0.0, 0.0, -1.0),
+//!     0.5,
+//!     Material::Lambertian { albedo: constant(Color::new(0.8, 0.3, 0.3)) },
+//! ));
+//!
+//! let settings = Settings::default();
+//! let camera = CameraSettings::default().build(settings.width as f32 / settings.height as f32);
+//! let image = Renderer::new().render(&scene, &camera, &settings);
+//! image.save("out.png").unwrap();
+//! ```
+
+#![allow(
+    dead_code,
+    unused_imports,
+    unused_assignments,
+    unused_variables,
+    non_upper_case_globals,
+    clippy::upper_case_acronyms
+)]
+
+#[macro_use]
+extern crate approx;
+extern crate glam;
+extern crate image;
+extern crate nalgebra as na;
+
+pub mod aov;
+pub mod background;
+pub mod bsdf;
+pub mod camera;
+pub mod denoise;
+pub mod examples;
+pub mod film;
+pub mod filter;
+pub mod hittable;
+pub mod integrator;
+pub mod light;
+pub mod material;
+pub mod principled;
+pub mod ray;
+pub mod render;
+pub mod rng;
+pub mod sampler;
+pub mod scene;
+pub mod scene_file;
+pub mod texture;
+pub mod tile;
+pub mod tonemap;
+pub mod vec3;
+
+pub use aov::Aov;
+pub use background::{Background, EnvironmentMap};
+pub use bsdf::{Bsdf, BsdfModel, BsdfSample, LobeFlags, Sampled};
+pub use camera::{Camera, CameraSettings, OrthographicCamera, PerspectiveCamera, Projection};
+pub use denoise::{Denoiser, DenoiserType};
+pub use film::Film;
+pub use filter::{Filter, FilterType};
+pub use hittable::bvh::BVH;
+pub use hittable::mesh::TriangleMesh;
+pub use hittable::plane::Plane;
+pub use hittable::sphere::Sphere;
+pub use hittable::triangle::{SimpleTriangle, Triangle};
+pub use hittable::{HitRecord, Hittable, Primitive};
+pub use integrator::{Integrator, Radiance};
+pub use light::{Light, PointLight};
+pub use material::Material;
+pub use ray::Ray;
+pub use render::{Adaptive, Budget, Image, Image16, Progressive, Renderer, Settings};
+pub use sampler::{Sampler, SamplerType};
+pub use scene::Scene;
+pub use texture::{ColorSpace, Texture, TextureKind, WrapMode};
+pub use tile::{Tile, TileOrder};
+pub use tonemap::{BitDepth, OutputTransform, ToneMap};
@@ -1,47 +1,614 @@
+use crate::hittable::aabb::AABB;
+use crate::hittable::triangle::SimpleTriangle;
+use crate::hittable::Hittable;
+use crate::material::Material;
 use crate::ray::Ray;
+use crate::sampler::Sampler;
 use crate::vec3::*;
 
-#[derive(Copy, Clone)]
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+#[derive(Clone)]
 pub enum Light {
     Point(PointLight),
     Area(AreaLight),
+    /// Emissive triangles of scene geometry, registered by `Scene::add`
+    Mesh(MeshLight),
+}
+
+/// Point on a light chosen to illuminate a surface point
+#[derive(Copy, Clone)]
+pub struct LightSample {
+    /// Unit direction from the surface point toward the light
+    pub wi: Vec3,
+    pub distance: f32,
+    pub radiance: Color,
+    /// Probability density of the sample with respect to solid angle, or `None` for lights
+    /// that can only be reached through explicit sampling
+    pub pdf: Option<f32>,
+}
+
+/// Intersection of a ray with the surface of an area light
+#[derive(Copy, Clone)]
+pub struct LightHit {
+    pub t: f32,
+    pub radiance: Color,
+    /// Solid angle density with which `Light::sample` picks this point from the ray origin
+    pub pdf: f32,
 }
 
 impl Light {
     pub fn new(position: Point3, color: Color) -> Light {
-        Light::Point(PointLight {
-            position: position,
-            color: color,
-        })
+        Light::Point(PointLight { position, color })
+    }
+
+    /// Representative position of the light, used by shading models that treat every
+    /// light as a point
+    pub fn position(&self) -> Point3 {
+        match self {
+            Light::Point(light) => light.position,
+            Light::Area(light) => light.center(),
+            Light::Mesh(light) => light.center(),
+        }
+    }
+
+    pub fn color(&self) -> Color {
+        match self {
+            Light::Point(light) => light.color,
+            Light::Area(light) => light.color,
+            Light::Mesh(light) => light.color(),
+        }
+    }
+
+    /// Pick a point on the light as seen from `point`
+    pub fn sample(&self, point: Point3, sampler: &mut dyn Sampler) -> LightSample {
+        match self {
+            Light::Point(light) => {
+                let to_light = light.position - point;
+                let distance = to_light.norm();
+                LightSample {
+                    wi: to_light / distance,
+                    distance,
+                    radiance: light.color / (distance * distance),
+                    pdf: None,
+                }
+            }
+            Light::Area(light) => light.sample(point, sampler),
+            Light::Mesh(light) => light.sample(point, sampler),
+        }
+    }
+
+    /// Intersect a ray with the emitting surface of the light. Point lights cannot be hit.
+    pub fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<LightHit> {
+        match self {
+            Light::Point(_) => None,
+            Light::Area(light) => light.hit(r, t_min, t_max),
+            Light::Mesh(light) => light.hit(r, t_min, t_max),
+        }
+    }
+}
+
+impl From<PointLight> for Light {
+    fn from(light: PointLight) -> Light {
+        Light::Point(light)
+    }
+}
+
+impl From<AreaLight> for Light {
+    fn from(light: AreaLight) -> Light {
+        Light::Area(light)
+    }
+}
+
+impl From<MeshLight> for Light {
+    fn from(light: MeshLight) -> Light {
+        Light::Mesh(light)
     }
 }
 
 #[derive(Copy, Clone)]
 pub struct PointLight {
     pub position: Point3,
+    /// Radiant intensity, falling off with the square of the distance
     pub color: Color,
 }
 
 impl PointLight {
     pub fn new(position: Point3, color: Color) -> PointLight {
-        PointLight {
-            position: position,
-            color: color,
-        }
+        PointLight { position, color }
     }
 }
 
+/// Surface an area light emits from
+#[derive(Copy, Clone)]
+pub enum AreaShape {
+    /// Parallelogram spanned by the edges `u` and `v`, emitting on the side `u × v` points to
+    Rectangle { center: Point3, u: Vec3, v: Vec3 },
+    /// Disk emitting on the side its normal points to
+    Disk {
+        center: Point3,
+        normal: Vec3,
+        radius: f32,
+    },
+    /// Sphere emitting outward
+    Sphere { center: Point3, radius: f32 },
+}
+
+/// Light with a finite emitting surface, which casts soft shadows
 #[derive(Copy, Clone)]
 pub struct AreaLight {
-    pub position: Point3,
+    pub shape: AreaShape,
+    /// Emitted radiance
     pub color: Color,
 }
 
 impl AreaLight {
-    pub fn new(position: Point3, color: Color) -> AreaLight {
-        AreaLight {
-            position: position,
-            color: color,
+    pub fn new(shape: AreaShape, color: Color) -> AreaLight {
+        AreaLight { shape, color }
+    }
+
+    pub fn rectangle(center: Point3, u: Vec3, v: Vec3, color: Color) -> AreaLight {
+        AreaLight::new(AreaShape::Rectangle { center, u, v }, color)
+    }
+
+    pub fn disk(center: Point3, normal: Vec3, radius: f32, color: Color) -> AreaLight {
+        let normal = normal.normalize();
+        AreaLight::new(
+            AreaShape::Disk {
+                center,
+                normal,
+                radius,
+            },
+            color,
+        )
+    }
+
+    pub fn sphere(center: Point3, radius: f32, color: Color) -> AreaLight {
+        AreaLight::new(AreaShape::Sphere { center, radius }, color)
+    }
+
+    pub fn center(&self) -> Point3 {
+        match self.shape {
+            AreaShape::Rectangle { center, .. }
+            | AreaShape::Disk { center, .. }
+            | AreaShape::Sphere { center, .. } => center,
+        }
+    }
+
+    pub fn area(&self) -> f32 {
+        match self.shape {
+            AreaShape::Rectangle { u, v, .. } => u.cross(&v).norm(),
+            AreaShape::Disk { radius, .. } => PI * radius * radius,
+            AreaShape::Sphere { radius, .. } => 4.0 * PI * radius * radius,
+        }
+    }
+
+    /// Uniformly sample a point on the surface, returning the point and its normal
+    fn sample_surface(&self, sampler: &mut dyn Sampler) -> (Point3, Vec3) {
+        let (u1, u2) = sampler.next_2d();
+        match self.shape {
+            AreaShape::Rectangle { center, u, v } => {
+                let point = center + (u1 - 0.5) * u + (u2 - 0.5) * v;
+                (point, u.cross(&v).normalize())
+            }
+            AreaShape::Disk {
+                center,
+                normal,
+                radius,
+            } => {
+                let (tangent, bitangent) = orthonormal_basis(normal);
+                let r = radius * u1.sqrt();
+                let phi = 2.0 * PI * u2;
+                let point = center + r * (phi.cos() * tangent + phi.sin() * bitangent);
+                (point, normal)
+            }
+            AreaShape::Sphere { center, radius } => {
+                let normal = random_on_unit_sphere(sampler);
+                (center + radius * normal, normal)
+            }
+        }
+    }
+
+    fn sample(&self, point: Point3, sampler: &mut dyn Sampler) -> LightSample {
+        if let AreaShape::Sphere { center, radius } = self.shape {
+            if let Some(cone) = SphereCone::new(center, radius, point) {
+                return cone.sample(sampler, self.color);
+            }
+        }
+
+        let (position, normal) = self.sample_surface(sampler);
+        let to_light = position - point;
+        let distance = to_light.norm();
+        let wi = to_light / distance;
+        let cosine = -normal.dot(&wi);
+
+        if cosine <= 0.0 {
+            return LightSample {
+                wi,
+                distance,
+                radiance: Color::new(0.0, 0.0, 0.0),
+                pdf: Some(0.0),
+            };
+        }
+
+        LightSample {
+            wi,
+            distance,
+            radiance: self.color,
+            pdf: Some(distance * distance / (cosine * self.area())),
+        }
+    }
+
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<LightHit> {
+        let (t, normal) = match self.shape {
+            AreaShape::Rectangle { center, u, v } => {
+                let n = u.cross(&v);
+                let t = plane_hit(r, center, n, t_min, t_max)?;
+                // Coordinates of the hit point along the edges, using the dual basis so
+                // that edges which are not perpendicular work as well
+                let w = n / n.dot(&n);
+                let offset = r.at(t) - center;
+                let alpha = w.dot(&offset.cross(&v));
+                let beta = w.dot(&u.cross(&offset));
+                if alpha.abs() > 0.5 || beta.abs() > 0.5 {
+                    return None;
+                }
+                (t, n.normalize())
+            }
+            AreaShape::Disk {
+                center,
+                normal,
+                radius,
+            } => {
+                let t = plane_hit(r, center, normal, t_min, t_max)?;
+                if (r.at(t) - center).norm_squared() > radius * radius {
+                    return None;
+                }
+                (t, normal)
+            }
+            AreaShape::Sphere { center, radius } => {
+                let oc = r.origin - center;
+                let a = r.direction.norm_squared();
+                let b = oc.dot(&r.direction);
+                let c = oc.dot(&oc) - radius * radius;
+                let discriminant = b * b - a * c;
+                if discriminant < 0.0 {
+                    return None;
+                }
+                let sqrtd = discriminant.sqrt();
+                let mut root = (-b - sqrtd) / a;
+                if root < t_min || t_max < root {
+                    root = (-b + sqrtd) / a;
+                    if root < t_min || t_max < root {
+                        return None;
+                    }
+                }
+                (root, (r.at(root) - center) / radius)
+            }
+        };
+
+        let distance = t * r.direction.norm();
+        let cosine = -normal.dot(&r.direction.normalize());
+        if cosine <= 0.0 {
+            return Some(LightHit {
+                t,
+                radiance: Color::new(0.0, 0.0, 0.0),
+                pdf: 0.0,
+            });
+        }
+
+        let pdf = match self.shape {
+            AreaShape::Sphere { center, radius } => SphereCone::new(center, radius, r.origin)
+                .map(|cone| cone.pdf())
+                .unwrap_or_else(|| distance * distance / (cosine * self.area())),
+            _ => distance * distance / (cosine * self.area()),
+        };
+        Some(LightHit {
+            t,
+            radiance: self.color,
+            pdf,
+        })
+    }
+}
+
+/// Cone of directions in which a sphere is seen from a point outside it. Sampling
+/// directions uniformly inside it only picks points on the visible side of the sphere,
+/// where sampling its whole surface would waste the samples that land on the far side.
+#[derive(Copy, Clone)]
+struct SphereCone {
+    /// Unit direction from the point to the center of the sphere
+    axis: Vec3,
+    /// Distance from the point to the center
+    distance: f32,
+    radius: f32,
+    /// One minus the cosine of the half angle of the cone
+    one_minus_cos_max: f32,
+}
+
+impl SphereCone {
+    /// Cone of the sphere seen from `point`, or `None` from inside the sphere
+    fn new(center: Point3, radius: f32, point: Point3) -> Option<SphereCone> {
+        let to_center = center - point;
+        let distance_squared = to_center.norm_squared();
+        if distance_squared <= radius * radius {
+            return None;
+        }
+        let sin2_max = radius * radius / distance_squared;
+        let cos_max = (1.0 - sin2_max).sqrt();
+        let distance = distance_squared.sqrt();
+        Some(SphereCone {
+            axis: to_center / distance,
+            distance,
+            radius,
+            // Written without the difference of nearly equal numbers for distant spheres
+            one_minus_cos_max: sin2_max / (1.0 + cos_max),
+        })
+    }
+
+    /// Solid angle density of the directions in the cone
+    fn pdf(&self) -> f32 {
+        1.0 / (2.0 * PI * self.one_minus_cos_max)
+    }
+
+    fn sample(&self, sampler: &mut dyn Sampler, radiance: Color) -> LightSample {
+        let (u1, u2) = sampler.next_2d();
+        let cos_theta = 1.0 - u1 * self.one_minus_cos_max;
+        let sin2_theta = (1.0 - cos_theta * cos_theta).max(0.0);
+        let phi = 2.0 * PI * u2;
+        let (tangent, bitangent) = orthonormal_basis(self.axis);
+        let wi = (cos_theta * self.axis
+            + sin2_theta.sqrt() * (phi.cos() * tangent + phi.sin() * bitangent))
+            .normalize();
+
+        // Nearest intersection of the direction with the sphere
+        let d = self.distance;
+        let half_chord = (self.radius * self.radius - d * d * sin2_theta)
+            .max(0.0)
+            .sqrt();
+        LightSample {
+            wi,
+            distance: d * cos_theta - half_chord,
+            radiance,
+            pdf: Some(self.pdf()),
+        }
+    }
+}
+
+/// Triangle of scene geometry with an emissive material
+#[derive(Clone)]
+pub struct EmissiveTriangle {
+    /// The triangle with its material, whose emission may be textured
+    pub triangle: SimpleTriangle,
+    /// Unit normal of the emitting side
+    pub normal: Vec3,
+}
+
+impl EmissiveTriangle {
+    /// Emitting triangle whose front side is the one `facing` points to
+    pub fn new(triangle: SimpleTriangle, facing: Vec3) -> Self {
+        let t = &triangle;
+        let mut normal = (t.p2 - t.p1).cross(&(t.p3 - t.p1)).normalize();
+        if normal.dot(&facing) < 0.0 {
+            normal = -normal;
+        }
+        EmissiveTriangle { triangle, normal }
+    }
+
+    /// Radiance emitted at the point with barycentric weights `weights` of the second and
+    /// third vertices
+    pub fn radiance(&self, weights: Vec2) -> Color {
+        let t = &self.triangle;
+        let point = (1.0 - weights.x - weights.y) * t.p1 + weights.x * t.p2 + weights.y * t.p3;
+        t.material.emission(t.uv_at(weights), point)
+    }
+
+    pub fn area(&self) -> f32 {
+        let t = &self.triangle;
+        0.5 * (t.p2 - t.p1).cross(&(t.p3 - t.p1)).norm()
+    }
+}
+
+/// Light made of the emissive triangles of a mesh. Triangles are sampled in proportion to
+/// their area, so the cost of next-event estimation does not grow with the triangle count.
+#[derive(Clone)]
+pub struct MeshLight {
+    triangles: Arc<[EmissiveTriangle]>,
+    /// Running total of the triangle areas
+    cdf: Arc<[f32]>,
+    bbox: AABB,
+}
+
+impl MeshLight {
+    /// Group emissive triangles into a single light, or `None` if they have no area
+    pub fn new(triangles: Vec<EmissiveTriangle>) -> Option<MeshLight> {
+        let mut total = 0.0;
+        let mut cdf = Vec::with_capacity(triangles.len());
+        for triangle in &triangles {
+            total += triangle.area();
+            cdf.push(total);
+        }
+        if total <= 0.0 || !total.is_finite() {
+            return None;
+        }
+
+        // Padded so that the box of a flat, axis aligned light still has some thickness
+        let mut min = Point3::repeat(f32::MAX);
+        let mut max = Point3::repeat(f32::MIN);
+        for t in &triangles {
+            for p in [t.triangle.p1, t.triangle.p2, t.triangle.p3] {
+                min = min.inf(&p);
+                max = max.sup(&p);
+            }
+        }
+        let padding = Vec3::repeat(1.0e-4);
+
+        Some(MeshLight {
+            triangles: triangles.into(),
+            cdf: cdf.into(),
+            bbox: AABB::new(min - padding, max + padding),
+        })
+    }
+
+    pub fn triangles(&self) -> &[EmissiveTriangle] {
+        &self.triangles
+    }
+
+    pub fn area(&self) -> f32 {
+        self.cdf[self.cdf.len() - 1]
+    }
+
+    pub fn center(&self) -> Point3 {
+        0.5 * (self.bbox.min + self.bbox.max)
+    }
+
+    /// Area weighted mean of the radiance emitted at the triangle centroids
+    pub fn color(&self) -> Color {
+        let centroid = Vec2::repeat(1.0 / 3.0);
+        self.triangles
+            .iter()
+            .map(|t| t.area() * t.radiance(centroid))
+            .sum::<Color>()
+            / self.area()
+    }
+
+    fn sample(&self, point: Point3, sampler: &mut dyn Sampler) -> LightSample {
+        let picked = sampler.next_1d() * self.area();
+        let index = self
+            .cdf
+            .partition_point(|&area| area < picked)
+            .min(self.triangles.len() - 1);
+        let emitter = &self.triangles[index];
+        let t = &emitter.triangle;
+
+        // Uniform point on the triangle
+        let (u1, u2) = sampler.next_2d();
+        let s = u1.sqrt();
+        let b = u2 * s;
+        let weights = Vec2::new(b, s - b);
+        let position = (1.0 - s) * t.p1 + b * t.p2 + (s - b) * t.p3;
+
+        let to_light = position - point;
+        let distance = to_light.norm();
+        let wi = to_light / distance;
+        let cosine = -emitter.normal.dot(&wi);
+
+        if cosine <= 0.0 {
+            return LightSample {
+                wi,
+                distance,
+                radiance: Color::new(0.0, 0.0, 0.0),
+                pdf: Some(0.0),
+            };
+        }
+
+        LightSample {
+            wi,
+            distance,
+            radiance: emitter.radiance(weights),
+            pdf: Some(distance * distance / (cosine * self.area())),
+        }
+    }
+
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<LightHit> {
+        if !self.bbox.hit(r, t_min, t_max) {
+            return None;
+        }
+
+        let mut closest = t_max;
+        let mut closest_hit = None;
+        for emitter in self.triangles.iter() {
+            if let Some(rec) = emitter.triangle.hit(r, t_min, closest) {
+                if rec.t >= t_min && rec.t <= closest {
+                    closest = rec.t;
+                    closest_hit = Some((emitter, rec));
+                }
+            }
+        }
+        let (emitter, rec) = closest_hit?;
+
+        let distance = closest * r.direction.norm();
+        let cosine = -emitter.normal.dot(&r.direction.normalize());
+        if cosine <= 0.0 {
+            return Some(LightHit {
+                t: closest,
+                radiance: Color::new(0.0, 0.0, 0.0),
+                pdf: 0.0,
+            });
+        }
+
+        Some(LightHit {
+            t: closest,
+            radiance: emitter.triangle.material.emission(rec.uv, rec.point),
+            pdf: distance * distance / (cosine * self.area()),
+        })
+    }
+}
+
+fn plane_hit(r: Ray, point: Point3, normal: Vec3, t_min: f32, t_max: f32) -> Option<f32> {
+    let denom = r.direction.dot(&normal);
+    if denom == 0.0 {
+        return None;
+    }
+    let t = (point - r.origin).dot(&normal) / denom;
+    if t < t_min || t > t_max {
+        return None;
+    }
+    Some(t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::SamplerType;
+
+    fn sphere() -> AreaLight {
+        AreaLight::sphere(Point3::new(0.5, 1.0, 4.0), 1.5, Color::new(2.0, 3.0, 4.0))
+    }
+
+    #[test]
+    fn sphere_samples_hit_the_visible_side() {
+        let light = sphere();
+        let point = Point3::new(0.0, 0.0, 0.0);
+        let mut sampler = SamplerType::Independent.build(1, 1);
+        for index in 0..1000 {
+            sampler.start_sample(0, 0, index);
+            let sample = light.sample(point, sampler.as_mut());
+            assert_eq!(sample.radiance, light.color);
+
+            let hit = light
+                .hit(Ray::new(point, sample.wi), 0.0, f32::MAX)
+                .expect("sample missed the light");
+            assert!((hit.t - sample.distance).abs() < 1.0e-3 * sample.distance);
+            assert!((hit.pdf - sample.pdf.unwrap()).abs() < 1.0e-3 * hit.pdf);
+        }
+    }
+
+    #[test]
+    fn sphere_irradiance_matches_closed_form() {
+        // Seen along the surface normal, a sphere whose half angle is `theta` delivers an
+        // irradiance of `pi * sin^2(theta)` times its radiance
+        let light = sphere();
+        let point = Point3::new(0.0, 0.0, 0.0);
+        let to_center = light.center() - point;
+        let normal = to_center.normalize();
+        let sin2_max = 1.5f32.powi(2) / to_center.norm_squared();
+        let expected = PI * sin2_max * light.color.x;
+
+        let count = 20000;
+        let mut sampler = SamplerType::Independent.build(2, 1);
+        let mut sum = 0.0;
+        for index in 0..count {
+            sampler.start_sample(0, 0, index);
+            let sample = light.sample(point, sampler.as_mut());
+            sum += sample.radiance.x * sample.wi.dot(&normal) / sample.pdf.unwrap();
         }
+        let estimate = sum / count as f32;
+        assert!(
+            (estimate - expected).abs() < 0.01 * expected,
+            "{} {}",
+            estimate,
+            expected
+        );
     }
 }
@@ -1,264 +1,216 @@
-#![allow(
-    dead_code,
-    unused_imports,
-    unused_assignments,
-    unused_variables,
-    non_upper_case_globals
-)]
+mod cli;
 
-#[macro_use]
-extern crate approx;
-extern crate glam;
-extern crate image;
-extern crate nalgebra as na;
+use cli::{Cli, Command, RenderArgs};
+use traci::camera::{Camera, CameraSettings};
+use traci::film::Film;
+use traci::hittable::Hittable;
+use traci::render::{Image, Renderer};
+use traci::rng::seeded_rng;
+use traci::scene::Scene;
+use traci::{examples, scene_file};
 
-mod camera;
-mod hittable;
-mod light;
-mod material;
-mod ray;
-mod scene;
-mod vec3;
+use clap::{error::ErrorKind, CommandFactory, Parser};
+use std::path::Path;
+use std::time::{Duration, Instant};
 
-use camera::{Camera, OrthographicCamera, PerspectiveCamera};
-use hittable::mesh::TriangleMesh;
-use hittable::plane::Plane;
-use hittable::sphere::Sphere;
-use hittable::triangle::Triangle;
-use hittable::{bvh::BVH, HitRecord, Hittable};
-use light::{Light, PointLight};
-use material::{Material, Scatter};
-use ray::Ray;
-use scene::Scene;
-use vec3::*;
+fn main() {
+    let cli = Cli::parse();
 
-use image::{GenericImage, GenericImageView, ImageBuffer, Rgb, RgbImage, Rgba, RgbaImage};
-use indicatif::{ParallelProgressIterator, ProgressBar, ProgressIterator, ProgressStyle};
-use rand::prelude::*;
-use rand_distr::{Distribution, Uniform};
-use rayon::prelude::*;
+    let args = match &cli.command {
+        Command::Render(args) | Command::Info(args) => args,
+        Command::Export { render, .. } | Command::Bench { render, .. } => render,
+    };
+    if let Err(msg) = args.validate() {
+        Cli::command().error(ErrorKind::ValueValidation, msg).exit();
+    }
 
-fn ray_color(ray: Ray, world: &Scene, depth: i32) -> Color {
-    if depth <= 0 {
-        return Color::new(0.0, 0.0, 0.0);
+    let (mut world, mut camera) = match load_scene(args) {
+        Ok(loaded) => loaded,
+        Err(msg) => Cli::command().error(ErrorKind::InvalidValue, msg).exit(),
+    };
+    args.camera.apply(&mut camera);
+    if let Err(msg) = camera.validate() {
+        Cli::command().error(ErrorKind::ValueValidation, msg).exit();
     }
+    let cam = camera.build(args.resolved_aspect_ratio());
 
-    let hit = world.hit(ray, 0.001, std::f32::MAX);
-    match hit {
-        Some(rec) => {
-            let scatter = rec.material.scatter(ray, rec);
-            return ray_color(scatter.ray, world, depth - 1).component_mul(&scatter.attenuation);
-        }
-        None => {
-            let unit_direction = ray.direction.normalize();
-            let t = 0.5 * (unit_direction.y + 1.0);
-            return ((1.0 - t) * Color::new(1.0, 1.0, 1.0)) + (t * Color::new(0.5, 0.7, 1.0));
+    // Construct BVH and replace bounded objects
+    if !args.no_bvh && matches!(cli.command, Command::Render(_) | Command::Bench { .. }) {
+        let accelerated = world.accelerate(0.0, 0.0);
+        println!("Found {} non-boundable objects", accelerated.unbounded);
+        if accelerated.bounded > 0 {
+            println!("Adding {} hittables to BVH", accelerated.bounded);
         }
     }
-}
-
-fn ray_color_phong(ray: Ray, world: &Scene, depth: i32) -> Color {
-    let hit = world.hit(ray, 0.001, std::f32::MAX);
-
-    match hit {
-        Some(rec) => {
-            let scatter = rec.material.scatter(ray, rec);
-            let ambient = scatter.attenuation;
-            let mut specular = Color::new(0.0, 0.0, 0.0);
-            let mut diffuse = Color::new(0.0, 0.0, 0.0);
 
-            let ambient_coeff: f32 = 0.3;
-            let mut diffuse_coeff: f32 = 0.7;
-            let mut specular_coeff: f32 = 0.6;
-
-            for light in &world.lights {
-                let light_vector = (rec.point - light.position).normalize();
-
-                diffuse += rec.normal.dot(&light_vector).max(0.0)
-                    * scatter.attenuation.component_mul(&light.color);
-                let reflected = 2.0 * (light_vector.dot(&rec.normal)) * rec.normal - light_vector;
-                specular += ray
-                    .direction
-                    .dot(&reflected.normalize())
-                    .max(0.0)
-                    .min(1.0)
-                    .powi(40)
-                    * scatter.attenuation.component_mul(&light.color);
+    match &cli.command {
+        Command::Render(args) => {
+            let renderer = Renderer::new().with_progress(true);
+            let film = if args.is_progressive() {
+                let start = Instant::now();
+                let film = renderer.render_progressive(
+                    &world,
+                    &cam,
+                    &args.settings(),
+                    &args.progressive(),
+                    |film| save_film(film, args),
+                );
+                println!(
+                    "Rendered {} spp in {:.1} s, largest relative error {:.3}",
+                    film.mean_samples(),
+                    start.elapsed().as_secs_f64(),
+                    film.max_relative_error()
+                );
+                film
+            } else {
+                renderer.render_film(&world, &cam, &args.settings())
+            };
+            save_film(&film, args);
+            if let Some(path) = &args.heatmap {
+                save_image(&film.sample_heatmap(), path);
             }
-
-            for light in &world.lights {
-                let shadow_ray = Ray::new(rec.point, rec.point - light.position);
-                let shadow_hit = world.hit(shadow_ray, 0.001, std::f32::MAX);
-                if let Some(_) = shadow_hit {
-                    diffuse_coeff = 0.0;
-                    specular_coeff = 0.0;
-                }
-            }
-            // println!("{}", specular);
-            return ambient_coeff * ambient + diffuse_coeff * diffuse;
         }
-        None => {
-            let unit_direction = ray.direction.normalize();
-            let t = 0.5 * (unit_direction.y + 1.0);
-            return ((1.0 - t) * Color::new(1.0, 1.0, 1.0)) + (t * Color::new(0.5, 0.7, 1.0));
-            // return Color::new(0.0, 0.0, 0.0);
+        Command::Info(args) => print_info(&world, &camera, args),
+        Command::Export { destination, .. } => {
+            if let Err(err) = scene_file::save(&world, Some(&camera), destination) {
+                eprintln!("error: {}", err);
+                std::process::exit(1);
+            }
         }
+        Command::Bench {
+            render: args,
+            iterations,
+        } => bench(&world, &cam, args, *iterations),
     }
 }
 
-fn main() {
-    // TODO: convert to clap args
-    //  Image parameters
-    const aspect_ratio: f32 = 1.0; // 16.0 / 9.0;
-    const image_width: u32 = 400;
-    const image_height: u32 = (image_width as f32 / aspect_ratio) as u32;
-    const samples_per_pixel: i32 = 10;
-    const max_depth: i32 = 50;
-
-    // Camera parameters
-    let lookfrom = Point3::new(0.0, 0.0, 0.0);
-    let lookat = Point3::new(0.0, 0.0, -1.0);
-    // Alternate viewpoint
-    // let lookfrom = Point3::new(5.5, 0.5, -1.0);
-    // let lookat = Point3::new(0.0, 0.0, -8.0);
-    let vup = Point3::new(0.0, 1.0, 0.0);
-    let vfov = 27.5;
-    let focal_length = 10.0;
-    let aperture = 0.1;
-
-    // Setup main objects used for rendering
-    let cam = PerspectiveCamera::new(
-        lookfrom,
-        lookat,
-        vup,
-        vfov,
-        aspect_ratio,
-        aperture,
-        focal_length,
-    );
-    let mut img = ImageBuffer::new(image_width, image_height);
-    let mut world: Scene = Scene::new();
-
-    // Scene parameters
-    // let num_spheres = 30;
-    // let radius_min = 0.0;
-    // let radius_max = 0.0;
-    // let x_min = 0.0;
-    // let x_max = 0.0;
-    // let y_min = 0.0;
-    // let y_max = 0.0;
-
-    // let world = random_scene(num_spheres);
-
-    // Grid of spheres
-    for nx in -50..50 {
-        for ny in -50..50 {
-            for nz in -50..50 {
-                world.add(random_sphere(
-                    nx as f32 / 10.0,
-                    ny as f32 / 10.0,
-                    -10.0 + (nz as f32 / 10.0),
-                ))
-            }
+fn save_film(film: &Film, args: &RenderArgs) {
+    let denoised = args.denoiser().map(|denoiser| denoiser.apply(film));
+    let film = denoised.as_ref().unwrap_or(film);
+    let transform = args.output_transform();
+    let aovs = args.aovs();
+    for path in &args.output {
+        if let Err(err) = film.save(path, &transform, &aovs) {
+            eprintln!("error: {}", err);
+            std::process::exit(1);
         }
     }
+}
 
-    // Teapot mesh
-    // let mut mesh = TriangleMesh::from_file("objs/teapot.obj", random_material());
-    // mesh.shift_scale(Point3::new(0.0, -2.0, -10.0), 0.5);
-    // world.add(mesh);
-
-    // Construct BVH and replace bounded objects
-    world.accelerate(0.0, 0.0);
-
-    world.add_light(PointLight::new(
-        Point3::new(-10.0, -10.0, -10.0),
-        Color::new(1.0, 1.3, 1.0),
-    ));
-
-    let pb = ProgressBar::new(image_height as u64 * image_width as u64);
-    pb.set_style(
-        ProgressStyle::default_bar()
-            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] ({eta})")
-            .progress_chars("=> "),
-    );
-
-    for (x, y, pix) in img.enumerate_pixels_mut() {
-        let color_samples = [Color::new(0.0, 0.0, 0.0); samples_per_pixel as usize];
-        let color: Color = color_samples
-            .par_iter()
-            .map(|_| {
-                let mut rng = rand::thread_rng();
-                let u = (x as f32 + rng.gen::<f32>()) / (image_width as f32 - 1.0);
-                let v = (y as f32 + rng.gen::<f32>()) / (image_height as f32 - 1.0);
-                let r = cam.get_ray(u, v);
-                ray_color_phong(r, &world, max_depth)
-            })
-            .sum();
-
-        *pix = color.to_rgb_samples(samples_per_pixel);
-        pb.inc(1);
+fn save_image(img: &Image, path: &Path) {
+    if let Err(err) = img.save(path) {
+        eprintln!("error: could not save {}: {}", path.display(), err);
+        std::process::exit(1);
     }
-
-    image::imageops::flip_vertical_in_place(&mut img);
-    img.save("images/out.png").unwrap();
 }
 
-fn random_sphere(x: f32, y: f32, z: f32) -> Sphere {
-    let mut rng = rand::thread_rng();
-    let random_float = Uniform::new_inclusive(0.0, 1.0);
-    let random_radius = Uniform::new_inclusive(0.05, 0.1).sample(&mut rng);
-    let random_albedo_r = Uniform::new_inclusive(0.0, 1.0).sample(&mut rng);
-    let random_albedo_g = Uniform::new_inclusive(0.0, 1.0).sample(&mut rng);
-    let random_albedo_b = Uniform::new_inclusive(0.0, 1.0).sample(&mut rng);
-    let random_albedo = Color::new(random_albedo_r, random_albedo_g, random_albedo_b);
-    let random_refractive_index = Uniform::new_inclusive(-1.5, 1.5).sample(&mut rng);
-    let mat_picker = Uniform::new(0, 4).sample(&mut rng);
-    let center = Point3::new(x, y, z);
-    let sphere_material = match mat_picker {
-        0 => {
-            let albedo = random_albedo;
-            Material::Lambertian { albedo }
-        }
-        1 => {
-            let albedo = random_albedo;
-            let fuzz = Uniform::new(0.0, 0.5).sample(&mut rand::thread_rng());
-            Material::Metal { albedo, fuzz }
+/// Build the scene named by `--scene` along with the camera it was authored with
+fn load_scene(args: &RenderArgs) -> Result<(Scene, CameraSettings), String> {
+    if args.scene.ends_with(".toml") || args.scene.ends_with(".json") {
+        let loaded = scene_file::load(Path::new(&args.scene)).map_err(|err| err.to_string())?;
+        Ok((loaded.scene, loaded.camera.unwrap_or_default()))
+    } else if args.scene.ends_with(".obj") {
+        if !Path::new(&args.scene).is_file() {
+            return Err(format!("mesh file `{}` does not exist", args.scene));
         }
-        2 => Material::Dielectric {
-            albedo: Color::new(1.0, 1.0, 1.0),
-            refraction_index: 1.5,
-        },
-        _ => Material::Dielectric {
-            albedo: random_albedo,
-            refraction_index: random_refractive_index,
-        },
-    };
-    Sphere::new(center, 0.05, sphere_material)
+        Ok((
+            examples::mesh_scene(&args.scene, &mut seeded_rng(args.seed)),
+            CameraSettings::default(),
+        ))
+    } else {
+        let world =
+            examples::builtin_scene(&args.scene, &mut seeded_rng(args.seed)).ok_or_else(|| {
+                format!(
+                    "unknown scene `{}`, expected a scene file or one of: {}",
+                    args.scene,
+                    examples::BUILTIN_SCENES.join(", ")
+                )
+            })?;
+        Ok((world, CameraSettings::default()))
+    }
 }
 
-fn random_material() -> Material {
-    let mut rng = rand::thread_rng();
-    let mat_picker = Uniform::new(0, 4).sample(&mut rng);
-    let random_albedo_r = Uniform::new_inclusive(0.0, 1.0).sample(&mut rng);
-    let random_albedo_g = Uniform::new_inclusive(0.0, 1.0).sample(&mut rng);
-    let random_albedo_b = Uniform::new_inclusive(0.0, 1.0).sample(&mut rng);
-    let random_albedo = Color::new(random_albedo_r, random_albedo_g, random_albedo_b);
+fn print_info(world: &Scene, camera: &CameraSettings, args: &RenderArgs) {
+    println!("Scene:       {}", args.scene);
+    println!("Objects:     {}", world.len());
+    println!("Lights:      {}", world.lights.len());
+    match world.bounding_box(0.0, 0.0) {
+        Some(aabb) => println!(
+            "Bounds:      ({}, {}, {}) to ({}, {}, {})",
+            aabb.min.x, aabb.min.y, aabb.min.z, aabb.max.x, aabb.max.y, aabb.max.z
+        ),
+        None => println!("Bounds:      unbounded"),
+    }
+    println!(
+        "Resolution:  {}x{} (aspect ratio {:.3})",
+        args.width,
+        args.resolved_height(),
+        args.resolved_aspect_ratio()
+    );
+    println!(
+        "Samples:     {} spp, max depth {}",
+        args.samples_per_pixel(),
+        args.max_depth
+    );
+    println!("Integrator:  {}", args.integrator);
+    println!(
+        "Tiles:       {}px, {} order",
+        args.tile_size, args.tile_order
+    );
+    println!("Seed:        {}", args.seed);
+    println!("Sampler:     {}", args.sampler);
+    let filter = args.filter();
+    println!("Filter:      {}, radius {}", filter.kind, filter.radius);
+    match args.denoiser() {
+        Some(denoiser) => println!(
+            "Denoiser:    {}, strength {}",
+            denoiser.kind, denoiser.strength
+        ),
+        None => println!("Denoiser:    none"),
+    }
+    println!(
+        "Output:      {} tone mapping, exposure {:+}, {}-bit{}",
+        args.tonemap,
+        args.exposure,
+        args.bit_depth,
+        if args.dither { ", dithered" } else { "" }
+    );
+    println!(
+        "Camera:      {:?} from ({}, {}, {}) to ({}, {}, {}), vfov {}, aperture {}, focal length {}",
+        camera.projection,
+        camera.lookfrom.x,
+        camera.lookfrom.y,
+        camera.lookfrom.z,
+        camera.lookat.x,
+        camera.lookat.y,
+        camera.lookat.z,
+        camera.vfov,
+        camera.aperture,
+        camera.focal_length
+    );
+}
 
-    match mat_picker {
-        0 => {
-            let fuzz = Uniform::new(0.0, 0.5).sample(&mut rand::thread_rng());
-            Material::Metal {
-                albedo: random_albedo,
-                fuzz,
-            }
-        }
-        1 => Material::Dielectric {
-            albedo: Color::new(1.0, 1.0, 1.0),
-            refraction_index: 1.5,
-        },
-        _ => Material::Lambertian {
-            albedo: random_albedo,
-        },
+fn bench(world: &Scene, cam: &Camera, args: &RenderArgs, iterations: u32) {
+    let pixels = args.width as u64 * args.resolved_height() as u64;
+    let samples = pixels * args.samples_per_pixel() as u64;
+    let settings = args.settings();
+    let mut times: Vec<Duration> = Vec::new();
+
+    for i in 0..iterations.max(1) {
+        let start = Instant::now();
+        Renderer::new().render(world, cam, &settings);
+        let elapsed = start.elapsed();
+        println!("Run {}: {:.3} s", i + 1, elapsed.as_secs_f64());
+        times.push(elapsed);
     }
+
+    let total: Duration = times.iter().sum();
+    let mean = total.as_secs_f64() / times.len() as f64;
+    let best = times.iter().min().unwrap().as_secs_f64();
+    println!(
+        "Mean {:.3} s, best {:.3} s, {:.0} camera samples/s",
+        mean,
+        best,
+        samples as f64 / best
+    );
 }
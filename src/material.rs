@@ -1,95 +1,401 @@
+use crate::bsdf::{Bsdf, BsdfModel};
 use crate::hittable::HitRecord;
+use crate::principled::Principled;
 use crate::ray::Ray;
-use crate::vec3::{random_in_unit_sphere, Color, Vec3, *};
+use crate::texture::Texture;
+use crate::vec3::*;
 
-#[derive(Copy, Clone)]
-pub struct RayScatter {
-    pub ray: Ray,
-    pub attenuation: Color,
-}
+use std::fmt::{self, Write};
+use std::sync::Arc;
 
-impl RayScatter {
-    pub fn new(attenuation: Vec3, ray: Ray) -> RayScatter {
-        RayScatter { attenuation, ray }
-    }
-}
-
-pub trait Scatter {
-    fn scatter(&self, ray: Ray, hit: HitRecord) -> RayScatter;
-}
-
-#[derive(Copy, Clone)]
+/// Surface appearance. Colors and the fuzz of metals are textures, looked up at every hit.
+#[derive(Clone, Debug)]
 pub enum Material {
     Lambertian {
-        albedo: Vec3,
+        albedo: Arc<dyn Texture>,
     },
     Metal {
-        albedo: Vec3,
-        fuzz: f32,
+        albedo: Arc<dyn Texture>,
+        fuzz: Arc<dyn Texture>,
     },
     Dielectric {
-        albedo: Color,
+        albedo: Arc<dyn Texture>,
         refraction_index: f32,
     },
+    /// Surface that glows on the side its normal points to and absorbs all incoming light
+    Emissive {
+        color: Arc<dyn Texture>,
+        strength: f32,
+    },
+    /// Diffuse surface with a Blinn-Phong highlight
+    Phong {
+        albedo: Arc<dyn Texture>,
+        ambient: f32,
+        diffuse: f32,
+        specular: f32,
+        shininess: f32,
+    },
+    /// Metallic-roughness surface after Disney's principled model, see `principled`.
+    /// `metallic` and `roughness` are read from the textures as scalars.
+    Principled {
+        base_color: Arc<dyn Texture>,
+        metallic: Arc<dyn Texture>,
+        roughness: Arc<dyn Texture>,
+        specular: f32,
+        sheen: f32,
+        clearcoat: f32,
+        transmission: f32,
+        ior: f32,
+    },
+    /// Another material with its shading normal taken from a tangent space normal map,
+    /// whose colors encode directions as `2 * color - 1`. `strength` scales the tilt away
+    /// from the surface normal.
+    NormalMapped {
+        material: Box<Material>,
+        normal_map: Arc<dyn Texture>,
+        strength: f32,
+    },
+    /// Another material on a surface displaced along its normal by `scale` times the
+    /// height read from `bump_map`. Only the shading normal changes, not the geometry.
+    BumpMapped {
+        material: Box<Material>,
+        bump_map: Arc<dyn Texture>,
+        scale: f32,
+    },
 }
 
-impl Scatter for Material {
-    fn scatter(&self, ray: Ray, hit: HitRecord) -> RayScatter {
-        match hit.material {
-            Material::Lambertian { albedo } => {
-                let scatter_direction = hit.normal + random_in_unit_sphere();
-                let scattered = Ray::new(hit.point, scatter_direction);
-                let attenuation = albedo;
-                RayScatter::new(attenuation, scattered)
+/// Blinn-Phong shading coefficients of a material
+#[derive(Copy, Clone, Debug)]
+pub struct PhongCoefficients {
+    pub ambient: f32,
+    pub diffuse: f32,
+    pub specular: f32,
+    /// Exponent of the highlight, higher values give smaller and sharper highlights
+    pub shininess: f32,
+}
+
+impl Material {
+    /// Base color of the surface at surface coordinates `uv` of `point`
+    pub fn albedo(&self, uv: Vec2, point: Point3) -> Color {
+        match self {
+            Material::Lambertian { albedo }
+            | Material::Metal { albedo, .. }
+            | Material::Dielectric { albedo, .. }
+            | Material::Phong { albedo, .. } => albedo.value(uv, point),
+            Material::Principled { base_color, .. } => base_color.value(uv, point),
+            Material::Emissive { .. } => Color::new(0.0, 0.0, 0.0),
+            Material::NormalMapped { material, .. } | Material::BumpMapped { material, .. } => {
+                material.albedo(uv, point)
+            }
+        }
+    }
+
+    /// Identifier derived from the kind of material and its parameters, including those of
+    /// its textures, so that surfaces sharing a material get the same one. It fits in 24
+    /// bits, which `f32` images store exactly.
+    pub fn id(&self) -> u32 {
+        // FNV-1a over the description of the material, which lists every parameter. It is
+        // hashed as it is formatted, since this runs for every camera ray that hits.
+        let mut hasher = Fnv1a(0x811c_9dc5);
+        write!(hasher, "{:?}", self).unwrap();
+        let hash = hasher.0;
+        (hash ^ (hash >> 24)) & 0x00ff_ffff
+    }
+
+    /// Coefficients used by the Blinn-Phong shading mode. Materials other than `Phong` get
+    /// values that match their look: no highlight for Lambertian surfaces, and only a
+    /// highlight for the specular ones, whose reflections are traced separately.
+    pub fn phong(&self, uv: Vec2, point: Point3) -> PhongCoefficients {
+        match *self {
+            Material::Lambertian { .. } => PhongCoefficients {
+                ambient: 0.3,
+                diffuse: 0.7,
+                specular: 0.0,
+                shininess: 1.0,
+            },
+            Material::Metal { ref fuzz, .. } => {
+                let fuzz = fuzz.scalar(uv, point);
+                PhongCoefficients {
+                    ambient: 0.0,
+                    diffuse: 0.0,
+                    specular: 1.0,
+                    // Rougher metals spread the highlight the way they blur reflections
+                    shininess: 2.0 / (fuzz * fuzz).max(1.0e-3),
+                }
+            }
+            Material::Dielectric { .. } => PhongCoefficients {
+                ambient: 0.0,
+                diffuse: 0.0,
+                specular: 1.0,
+                shininess: 1000.0,
+            },
+            Material::Emissive { .. } => PhongCoefficients {
+                ambient: 0.0,
+                diffuse: 0.0,
+                specular: 0.0,
+                shininess: 1.0,
+            },
+            Material::Phong {
+                ambient,
+                diffuse,
+                specular,
+                shininess,
+                ..
+            } => PhongCoefficients {
+                ambient,
+                diffuse,
+                specular,
+                shininess,
+            },
+            Material::Principled { .. } => {
+                let principled = self.principled(uv, point).unwrap();
+                let alpha = (principled.roughness * principled.roughness).max(1.0e-3);
+                PhongCoefficients {
+                    ambient: 0.1,
+                    diffuse: (1.0 - principled.metallic) * (1.0 - principled.transmission),
+                    specular: 1.0,
+                    // Blinn-Phong exponent whose highlight is about as wide as the GGX one
+                    shininess: 2.0 / (alpha * alpha) - 2.0,
+                }
+            }
+            Material::NormalMapped { ref material, .. }
+            | Material::BumpMapped { ref material, .. } => material.phong(uv, point),
+        }
+    }
+
+    /// Parameters of a `Principled` material at surface coordinates `uv` of `point`
+    pub fn principled(&self, uv: Vec2, point: Point3) -> Option<Principled> {
+        match self {
+            Material::Principled {
+                base_color,
+                metallic,
+                roughness,
+                specular,
+                sheen,
+                clearcoat,
+                transmission,
+                ior,
+            } => Some(Principled {
+                base_color: base_color.value(uv, point),
+                metallic: metallic.scalar(uv, point).clamp(0.0, 1.0),
+                roughness: roughness.scalar(uv, point).clamp(0.0, 1.0),
+                specular: *specular,
+                sheen: *sheen,
+                clearcoat: *clearcoat,
+                transmission: transmission.clamp(0.0, 1.0),
+                ior: *ior,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Radiance emitted from the front side of the surface at surface coordinates `uv` of
+    /// `point`
+    pub fn emission(&self, uv: Vec2, point: Point3) -> Color {
+        match self {
+            Material::Emissive { color, strength } => *strength * color.value(uv, point),
+            Material::NormalMapped { material, .. } | Material::BumpMapped { material, .. } => {
+                material.emission(uv, point)
             }
+            _ => Color::new(0.0, 0.0, 0.0),
+        }
+    }
 
-            Material::Metal { albedo, fuzz } => {
-                let reflected = ray.reflect(hit);
-                let attenuation = albedo;
-                let scattered = Ray::new(
-                    reflected.origin,
-                    reflected.direction + fuzz * random_in_unit_sphere(),
-                );
-                RayScatter::new(attenuation, scattered)
+    pub fn is_emissive(&self) -> bool {
+        match self {
+            Material::Emissive { strength, .. } => *strength != 0.0,
+            Material::NormalMapped { material, .. } | Material::BumpMapped { material, .. } => {
+                material.is_emissive()
             }
+            _ => false,
+        }
+    }
 
+    /// Scattering at `hit`, with the textures of the material looked up and its normal and
+    /// bump maps applied
+    pub fn bsdf(&self, hit: &HitRecord) -> Bsdf {
+        let (uv, point) = (hit.uv, hit.point);
+        let model = match self {
+            Material::Lambertian { albedo } => BsdfModel::Lambertian {
+                albedo: albedo.value(uv, point),
+            },
+            Material::Metal { albedo, fuzz } => BsdfModel::Metal {
+                albedo: albedo.value(uv, point),
+                fuzz: fuzz.scalar(uv, point),
+            },
             Material::Dielectric {
                 albedo,
                 refraction_index,
+            } => BsdfModel::Dielectric {
+                albedo: albedo.value(uv, point),
+                refraction_index: *refraction_index,
+            },
+            Material::Phong {
+                albedo,
+                diffuse,
+                specular,
+                shininess,
+                ..
+            } => BsdfModel::Phong {
+                albedo: albedo.value(uv, point),
+                diffuse: *diffuse,
+                specular: *specular,
+                shininess: *shininess,
+            },
+            Material::Principled { .. } => {
+                BsdfModel::Principled(self.principled(uv, point).unwrap())
+            }
+            Material::Emissive { .. } => BsdfModel::Black,
+            Material::NormalMapped { .. } | Material::BumpMapped { .. } => {
+                let mapped = apply_normal_maps(HitRecord {
+                    material: self,
+                    ..*hit
+                });
+                return mapped.material.bsdf(&mapped);
+            }
+        };
+        Bsdf {
+            normal: hit.normal.normalize(),
+            model,
+        }
+    }
+
+    /// Light given off by the surface toward the origin of `ray`
+    pub fn emitted(&self, ray: Ray, hit: HitRecord) -> Color {
+        if hit.normal.dot(&ray.direction) < 0.0 {
+            self.emission(hit.uv, hit.point)
+        } else {
+            Color::new(0.0, 0.0, 0.0)
+        }
+    }
+}
+
+/// 32-bit FNV-1a hash of the text written to it
+struct Fnv1a(u32);
+
+impl fmt::Write for Fnv1a {
+    fn write_str(&mut self, text: &str) -> fmt::Result {
+        for byte in text.bytes() {
+            self.0 ^= byte as u32;
+            self.0 = self.0.wrapping_mul(0x0100_0193);
+        }
+        Ok(())
+    }
+}
+
+/// Distance in `u` and `v` over which bump maps are differentiated
+const BUMP_DELTA: f32 = 1.0e-3;
+
+/// The hit as seen by the material underneath any normal and bump maps wrapped around
+/// `hit.material`, with the maps applied to its normal from the outside in
+pub fn apply_normal_maps(hit: HitRecord) -> HitRecord {
+    let mut hit = hit;
+    loop {
+        match hit.material {
+            Material::NormalMapped {
+                material,
+                normal_map,
+                strength,
             } => {
-                let (normal, etai_over_etat) = if hit.normal.dot(&ray.direction) > 0.0 {
-                    (-hit.normal, 1.0 / refraction_index)
-                } else {
-                    (hit.normal, refraction_index)
-                };
-
-                let unit_direction = ray.direction.normalize();
-                let cos_theta = (-unit_direction).dot(&normal).min(1.0);
-                let sin_theta = (1.0 - cos_theta.powi(2)).sqrt();
-
-                let out_ray = if etai_over_etat * sin_theta > 1.0 {
-                    let reflected = reflect(unit_direction, normal);
-                    Ray::new(hit.point, reflected)
-                } else if rand::random::<f32>() < reflectance(cos_theta, etai_over_etat) {
-                    let reflected = reflect(unit_direction, normal);
-                    Ray::new(hit.point, reflected)
-                } else {
-                    let refracted = refract(unit_direction, normal, etai_over_etat);
-                    Ray::new(hit.point, refracted)
-                };
-
-                // let attenuation = Color::new(1.0, 1.0, 1.0);
-                let attenuation = albedo;
-                // let refracted = ray.refract(hit, refraction_index);
-                RayScatter::new(attenuation, out_ray)
+                hit.normal = normal_mapped(&hit, normal_map.as_ref(), *strength);
+                hit.material = material;
             }
+            Material::BumpMapped {
+                material,
+                bump_map,
+                scale,
+            } => {
+                hit.normal = bump_mapped(&hit, bump_map.as_ref(), *scale);
+                hit.material = material;
+            }
+            _ => return hit,
         }
     }
 }
 
-// Reflectance helper function
-fn reflectance(cosine: f32, refraction_index: f32) -> f32 {
-    let r0 = (1.0 - refraction_index) / (1.0 + refraction_index);
-    let r0_squared = r0.powi(2);
-    r0_squared + (1.0 - r0_squared) * (1.0 - cosine).powi(5)
+/// Normal read from a tangent space normal map, whose red, green and blue channels point
+/// along the `u` and `v` directions and the surface normal
+fn normal_mapped(hit: &HitRecord, normal_map: &dyn Texture, strength: f32) -> Vec3 {
+    let normal = hit.normal;
+    let tangent = (hit.tangent - normal * normal.dot(&hit.tangent)).normalize();
+    let mut bitangent = normal.cross(&tangent);
+    // Mirrored texture layouts run `v` the other way around
+    if bitangent.dot(&hit.bitangent) < 0.0 {
+        bitangent = -bitangent;
+    }
+
+    let encoded = 2.0 * normal_map.value(hit.uv, hit.point) - Vec3::repeat(1.0);
+    let mapped =
+        (strength * (encoded.x * tangent + encoded.y * bitangent) + encoded.z * normal).normalize();
+    if mapped.iter().all(|x| x.is_finite()) {
+        mapped
+    } else {
+        normal
+    }
+}
+
+/// Normal of the surface displaced along its normal by `scale` times the height map, found
+/// from the slope of the height in `u` and `v`
+fn bump_mapped(hit: &HitRecord, bump_map: &dyn Texture, scale: f32) -> Vec3 {
+    let height = |du: f32, dv: f32| {
+        let uv = hit.uv + Vec2::new(du, dv);
+        let point = hit.point + du * hit.tangent + dv * hit.bitangent;
+        scale * bump_map.scalar(uv, point)
+    };
+    let center = height(0.0, 0.0);
+    let slope_u = (height(BUMP_DELTA, 0.0) - center) / BUMP_DELTA;
+    let slope_v = (height(0.0, BUMP_DELTA) - center) / BUMP_DELTA;
+
+    // Gradient of the height over the surface, expressed through the surface derivatives
+    // so that it works for any parameterization that is not degenerate
+    let normal = hit.normal;
+    let area = normal.dot(&hit.tangent.cross(&hit.bitangent));
+    if area.abs() < 1.0e-12 {
+        return normal;
+    }
+    let gradient =
+        (slope_u * hit.bitangent.cross(&normal) + slope_v * normal.cross(&hit.tangent)) / area;
+    let bumped = (normal - gradient).normalize();
+    if bumped.iter().all(|x| x.is_finite()) {
+        bumped
+    } else {
+        normal
+    }
+}
+
+/// Surface normal on the side of the surface the ray arrived from
+pub fn facing_normal(ray: Ray, hit: HitRecord) -> Vec3 {
+    if hit.normal.dot(&ray.direction) > 0.0 {
+        -hit.normal
+    } else {
+        hit.normal
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::texture::constant;
+
+    fn metal(fuzz: f32) -> Material {
+        Material::Metal {
+            albedo: constant(Color::new(0.9, 0.8, 0.7)),
+            fuzz: constant(Color::repeat(fuzz)),
+        }
+    }
+
+    #[test]
+    fn id_follows_parameters() {
+        assert_eq!(metal(0.1).id(), metal(0.1).id());
+        assert_ne!(metal(0.1).id(), metal(0.2).id());
+        assert!(metal(0.1).id() < 1 << 24);
+
+        // Same as hashing the whole description at once
+        let mut hash: u32 = 0x811c_9dc5;
+        for byte in format!("{:?}", metal(0.1)).bytes() {
+            hash ^= byte as u32;
+            hash = hash.wrapping_mul(0x0100_0193);
+        }
+        assert_eq!(metal(0.1).id(), (hash ^ (hash >> 24)) & 0x00ff_ffff);
+    }
 }
@@ -0,0 +1,496 @@
+//! Principled metallic-roughness surface model.
+//!
+//! The model follows Burley's "Physically Based Shading at Disney" (2012): a Burley diffuse
+//! base with a sheen term for cloth-like grazing highlights, a GGX microfacet specular lobe
+//! whose color blends from a dielectric reflectance to the base color as the surface turns
+//! metallic, a rough GGX glass lobe for transmission (Walter et al., "Microfacet Models for
+//! Refraction through Rough Surfaces", 2007) and a thin, glossy clearcoat on top.
+//!
+//! Directions point away from the surface. Sampling picks one lobe in proportion to a rough
+//! estimate of how much light it reflects, and densities are summed over all lobes, so the
+//! lobes combine like multiple importance sampling.
+
+use crate::bsdf::LobeFlags;
+use crate::vec3::*;
+
+use std::f32::consts::PI;
+
+/// Microfacet roughness of the clearcoat, which is always glossy
+const CLEARCOAT_ALPHA: f32 = 0.05;
+/// Smallest microfacet roughness, which keeps smooth surfaces from becoming perfect mirrors
+/// that have no density to evaluate
+const MIN_ALPHA: f32 = 1.0e-3;
+/// How much the sheen takes on the hue of the base color
+const SHEEN_TINT: f32 = 0.5;
+
+/// Parameters of the principled model at one point of a surface
+#[derive(Copy, Clone, Debug)]
+pub struct Principled {
+    pub base_color: Color,
+    /// Zero for dielectrics, one for metals
+    pub metallic: f32,
+    /// Perceptual roughness; microfacet roughness is its square
+    pub roughness: f32,
+    /// Reflectance of dielectrics at normal incidence, where 0.5 means 4 %
+    pub specular: f32,
+    pub sheen: f32,
+    pub clearcoat: f32,
+    /// Fraction of the dielectric base that is glass instead of diffuse
+    pub transmission: f32,
+    /// Index of refraction of the glass
+    pub ior: f32,
+}
+
+/// The ways light can leave a principled surface
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Lobe {
+    Diffuse,
+    Specular,
+    Glass,
+    Clearcoat,
+}
+
+const LOBES: [Lobe; 4] = [Lobe::Diffuse, Lobe::Specular, Lobe::Glass, Lobe::Clearcoat];
+
+impl Principled {
+    fn alpha(&self) -> f32 {
+        (self.roughness * self.roughness).max(MIN_ALPHA)
+    }
+
+    /// Reflectance of the specular lobe at normal incidence
+    fn specular_color(&self) -> Color {
+        let dielectric = Color::repeat(0.08 * self.specular);
+        (1.0 - self.metallic) * dielectric + self.metallic * self.base_color
+    }
+
+    /// Weight of a lobe in the mixture
+    fn weight(&self, lobe: Lobe) -> f32 {
+        let dielectric = 1.0 - self.metallic;
+        match lobe {
+            Lobe::Diffuse => dielectric * (1.0 - self.transmission),
+            Lobe::Specular => 1.0 - dielectric * self.transmission,
+            Lobe::Glass => dielectric * self.transmission,
+            Lobe::Clearcoat => 0.25 * self.clearcoat,
+        }
+    }
+
+    /// Probabilities of sampling each lobe of `LOBES` when viewed at `cos_o` from the
+    /// normal, or `None` if the surface reflects nothing
+    fn lobe_probabilities(&self, cos_o: f32) -> Option<[f32; 4]> {
+        let albedo = |lobe| match lobe {
+            Lobe::Diffuse => luminance(self.base_color) + self.sheen,
+            Lobe::Specular => luminance(schlick(self.specular_color(), cos_o)),
+            Lobe::Glass => 1.0,
+            Lobe::Clearcoat => schlick_scalar(0.04, cos_o),
+        };
+        let mut probabilities = LOBES.map(|lobe| self.weight(lobe) * albedo(lobe));
+        let total: f32 = probabilities.iter().sum();
+        if total <= 0.0 || !total.is_finite() {
+            return None;
+        }
+        for p in &mut probabilities {
+            *p /= total;
+        }
+        Some(probabilities)
+    }
+
+    /// Ratio of glass indices of refraction on the side of `wi` to the side of `wo`, given
+    /// whether `wo` is outside
+    fn eta(&self, outside: bool) -> f32 {
+        if outside {
+            self.ior
+        } else {
+            1.0 / self.ior
+        }
+    }
+
+    /// Light reflected or transmitted toward `wo` for each unit of light arriving from `wi`,
+    /// including the cosine term. `normal` is the outward unit normal.
+    pub fn eval(&self, wo: Vec3, wi: Vec3, normal: Vec3) -> Color {
+        let outside = wo.dot(&normal) >= 0.0;
+        let n = if outside { normal } else { -normal };
+        let cos_o = wo.dot(&n);
+        let cos_i = wi.dot(&n);
+        if cos_o <= 1.0e-6 || cos_i.abs() <= 1.0e-6 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        let alpha = self.alpha();
+
+        if cos_i < 0.0 {
+            // Only the glass lets light through
+            let weight = self.weight(Lobe::Glass);
+            if weight <= 0.0 {
+                return Color::new(0.0, 0.0, 0.0);
+            }
+            let eta = self.eta(outside);
+            let glass = glass_transmission(wo, wi, n, alpha, eta);
+            return weight * glass * (-cos_i) * self.base_color;
+        }
+
+        let h = (wo + wi).normalize();
+        let cos_d = wi.dot(&h);
+
+        // Burley diffuse with a retro-reflective rim on rough surfaces, plus sheen
+        let fd90 = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+        let retro = (1.0 + (fd90 - 1.0) * (1.0 - cos_i).powi(5))
+            * (1.0 + (fd90 - 1.0) * (1.0 - cos_o).powi(5));
+        let tint = if luminance(self.base_color) > 0.0 {
+            self.base_color / luminance(self.base_color)
+        } else {
+            Color::repeat(1.0)
+        };
+        let sheen_color = (1.0 - SHEEN_TINT) * Color::repeat(1.0) + SHEEN_TINT * tint;
+        let diffuse =
+            self.base_color * (retro / PI) + self.sheen * (1.0 - cos_d).powi(5) * sheen_color;
+
+        let specular =
+            schlick(self.specular_color(), cos_d) * microfacet_reflection(wo, wi, n, alpha);
+        let clearcoat =
+            schlick_scalar(0.04, cos_d) * microfacet_reflection(wo, wi, n, CLEARCOAT_ALPHA);
+        let glass = fresnel_dielectric(wo.dot(&h), self.eta(outside))
+            * microfacet_reflection(wo, wi, n, alpha);
+
+        (self.weight(Lobe::Diffuse) * diffuse
+            + self.weight(Lobe::Specular) * specular
+            + Color::repeat(
+                self.weight(Lobe::Clearcoat) * clearcoat + self.weight(Lobe::Glass) * glass,
+            ))
+            * cos_i
+    }
+
+    /// Solid angle density with which `sample` picks `wi`
+    pub fn pdf(&self, wo: Vec3, wi: Vec3, normal: Vec3) -> f32 {
+        let outside = wo.dot(&normal) >= 0.0;
+        let n = if outside { normal } else { -normal };
+        let cos_o = wo.dot(&n);
+        let cos_i = wi.dot(&n);
+        if cos_o <= 1.0e-6 || cos_i.abs() <= 1.0e-6 {
+            return 0.0;
+        }
+        let probabilities = match self.lobe_probabilities(cos_o) {
+            Some(probabilities) => probabilities,
+            None => return 0.0,
+        };
+        let alpha = self.alpha();
+        let eta = self.eta(outside);
+
+        LOBES
+            .iter()
+            .zip(probabilities)
+            .filter(|(_, p)| *p > 0.0)
+            .map(|(lobe, p)| {
+                p * match lobe {
+                    Lobe::Diffuse if cos_i > 0.0 => cos_i / PI,
+                    Lobe::Specular if cos_i > 0.0 => reflection_pdf(wo, wi, n, alpha),
+                    Lobe::Clearcoat if cos_i > 0.0 => reflection_pdf(wo, wi, n, CLEARCOAT_ALPHA),
+                    Lobe::Glass => glass_pdf(wo, wi, n, alpha, eta),
+                    _ => 0.0,
+                }
+            })
+            .sum()
+    }
+
+    /// Pick a direction `wi` for light arriving toward `wo` from the uniform numbers `u`,
+    /// the first of which chooses the lobe. Returns the kind of lobe it came from, or `None`
+    /// if the sample left the surface on the wrong side.
+    pub fn sample(&self, wo: Vec3, normal: Vec3, u: [f32; 3]) -> Option<(Vec3, LobeFlags)> {
+        let outside = wo.dot(&normal) >= 0.0;
+        let n = if outside { normal } else { -normal };
+        let probabilities = self.lobe_probabilities(wo.dot(&n))?;
+        let alpha = self.alpha();
+
+        let mut pick = u[0];
+        let mut lobe = Lobe::Diffuse;
+        for (candidate, p) in LOBES.iter().zip(probabilities) {
+            lobe = *candidate;
+            if pick < p {
+                break;
+            }
+            pick -= p;
+        }
+        let u = (u[1], u[2]);
+
+        let mut refracted = false;
+        let wi = match lobe {
+            Lobe::Diffuse => cosine_direction(n, u),
+            Lobe::Specular => reflect(-wo, ggx_normal(n, alpha, u)),
+            Lobe::Clearcoat => reflect(-wo, ggx_normal(n, CLEARCOAT_ALPHA, u)),
+            Lobe::Glass => {
+                let h = ggx_normal(n, alpha, u);
+                let eta = self.eta(outside);
+                // What is left of the lobe choice decides between reflection and refraction
+                let reflect_probability = fresnel_dielectric(wo.dot(&h), eta);
+                if pick < reflect_probability * probabilities[2] {
+                    reflect(-wo, h)
+                } else {
+                    refracted = true;
+                    refract(-wo, h, 1.0 / eta)
+                }
+            }
+        };
+
+        // Reflections have to stay on the side of `wo` and refractions have to cross the
+        // surface, which is how `pdf` tells them apart
+        let cos_i = wi.dot(&n);
+        let valid = if refracted {
+            cos_i < 0.0 && wi.iter().all(|x| x.is_finite())
+        } else {
+            cos_i > 0.0
+        };
+        if !valid {
+            return None;
+        }
+        let kind = if lobe == Lobe::Diffuse {
+            LobeFlags::DIFFUSE
+        } else {
+            LobeFlags::GLOSSY
+        };
+        let side = if cos_i > 0.0 {
+            LobeFlags::REFLECTION
+        } else {
+            LobeFlags::TRANSMISSION
+        };
+        Some((wi.normalize(), kind | side))
+    }
+}
+
+/// GGX distribution of microfacet normals `h` around `n`
+fn ggx_d(n: Vec3, h: Vec3, alpha: f32) -> f32 {
+    let cos = n.dot(&h);
+    if cos <= 0.0 {
+        return 0.0;
+    }
+    let a2 = alpha * alpha;
+    let denominator = cos * cos * (a2 - 1.0) + 1.0;
+    a2 / (PI * denominator * denominator)
+}
+
+/// Smith masking auxiliary function of the GGX distribution
+fn ggx_lambda(n: Vec3, w: Vec3, alpha: f32) -> f32 {
+    let cos2 = n.dot(&w).powi(2);
+    let tan2 = (1.0 - cos2).max(0.0) / cos2;
+    0.5 * ((1.0 + alpha * alpha * tan2).sqrt() - 1.0)
+}
+
+/// Height correlated masking and shadowing of the microfacets seen from `wo` and `wi`
+fn ggx_g(n: Vec3, wo: Vec3, wi: Vec3, alpha: f32) -> f32 {
+    1.0 / (1.0 + ggx_lambda(n, wo, alpha) + ggx_lambda(n, wi, alpha))
+}
+
+/// Microfacet normal drawn with density `D(h) (n . h)`
+fn ggx_normal(n: Vec3, alpha: f32, (u1, u2): (f32, f32)) -> Vec3 {
+    let cos_theta = ((1.0 - u1) / (1.0 + (alpha * alpha - 1.0) * u1)).sqrt();
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * u2;
+    let (tangent, bitangent) = orthonormal_basis(n);
+    sin_theta * phi.cos() * tangent + sin_theta * phi.sin() * bitangent + cos_theta * n
+}
+
+/// Microfacet reflection without the Fresnel factor, for `wo` and `wi` on the side of `n`
+fn microfacet_reflection(wo: Vec3, wi: Vec3, n: Vec3, alpha: f32) -> f32 {
+    let h = (wo + wi).normalize();
+    ggx_d(n, h, alpha) * ggx_g(n, wo, wi, alpha) / (4.0 * wo.dot(&n) * wi.dot(&n))
+}
+
+/// Density of reflecting `wo` into `wi` off a microfacet drawn by `ggx_normal`
+fn reflection_pdf(wo: Vec3, wi: Vec3, n: Vec3, alpha: f32) -> f32 {
+    let h = (wo + wi).normalize();
+    ggx_d(n, h, alpha) * n.dot(&h) / (4.0 * wo.dot(&h).abs())
+}
+
+/// Half vector of refraction from `wi` into `wo`, facing `n`, where `eta` is the ratio of
+/// the indices of refraction on the side of `wi` to the side of `wo`
+fn refraction_half_vector(wo: Vec3, wi: Vec3, n: Vec3, eta: f32) -> Vec3 {
+    let h = (wo + eta * wi).normalize();
+    if h.dot(&n) < 0.0 {
+        -h
+    } else {
+        h
+    }
+}
+
+/// Rough glass transmission from `wi` below the surface to `wo` above it, without the
+/// cosine term, in the form that transports radiance
+fn glass_transmission(wo: Vec3, wi: Vec3, n: Vec3, alpha: f32, eta: f32) -> f32 {
+    let h = refraction_half_vector(wo, wi, n, eta);
+    let (o, i) = (wo.dot(&h), wi.dot(&h));
+    if o * i >= 0.0 {
+        return 0.0;
+    }
+    let denominator = o + eta * i;
+    (1.0 - fresnel_dielectric(o, eta))
+        * ggx_d(n, h, alpha)
+        * ggx_g(n, wo, wi, alpha)
+        * o.abs()
+        * i.abs()
+        / (wo.dot(&n) * wi.dot(&n).abs() * denominator * denominator)
+}
+
+/// Density with which the glass lobe picks `wi`, reflected or refracted
+fn glass_pdf(wo: Vec3, wi: Vec3, n: Vec3, alpha: f32, eta: f32) -> f32 {
+    if wi.dot(&n) > 0.0 {
+        let h = (wo + wi).normalize();
+        return fresnel_dielectric(wo.dot(&h), eta) * reflection_pdf(wo, wi, n, alpha);
+    }
+    let h = refraction_half_vector(wo, wi, n, eta);
+    let (o, i) = (wo.dot(&h), wi.dot(&h));
+    if o * i >= 0.0 {
+        return 0.0;
+    }
+    let denominator = o + eta * i;
+    (1.0 - fresnel_dielectric(o, eta)) * ggx_d(n, h, alpha) * n.dot(&h) * eta * eta * i.abs()
+        / (denominator * denominator)
+}
+
+/// Unpolarized Fresnel reflectance of a dielectric boundary for light meeting it at
+/// `cos_i` from the normal, where `eta` is the ratio of the index of refraction on the far
+/// side to that on the near side
+fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+    let cos_i = cos_i.abs().min(1.0);
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    0.5 * (parallel * parallel + perpendicular * perpendicular)
+}
+
+/// Schlick's approximation of the Fresnel reflectance with reflectance `f0` at normal
+/// incidence
+fn schlick(f0: Color, cos: f32) -> Color {
+    let weight = (1.0 - cos.clamp(0.0, 1.0)).powi(5);
+    f0 + (Color::repeat(1.0) - f0) * weight
+}
+
+fn schlick_scalar(f0: f32, cos: f32) -> f32 {
+    f0 + (1.0 - f0) * (1.0 - cos.clamp(0.0, 1.0)).powi(5)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bsdf::{Bsdf, BsdfModel, Sampled};
+    use crate::rng::seeded_rng;
+    use rand::Rng;
+
+    /// Surfaces as `(roughness, metallic, transmission)`
+    const SURFACES: [(f32, f32, f32); 5] = [
+        (0.5, 0.0, 0.0),
+        (0.3, 1.0, 0.0),
+        (0.8, 0.5, 0.0),
+        (0.4, 0.0, 1.0),
+        (0.6, 0.0, 0.5),
+    ];
+
+    fn principled((roughness, metallic, transmission): (f32, f32, f32)) -> Principled {
+        Principled {
+            base_color: Color::new(0.8, 0.5, 0.3),
+            metallic,
+            roughness,
+            specular: 0.5,
+            sheen: 0.2,
+            clearcoat: 0.0,
+            transmission,
+            ior: 1.5,
+        }
+    }
+
+    fn normal() -> Vec3 {
+        Vec3::new(0.0, 0.0, 1.0)
+    }
+
+    /// Views from outside and, for the glass, from inside the surface
+    fn views() -> [Vec3; 3] {
+        [
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(0.6, 0.0, 0.8),
+            Vec3::new(0.3, 0.4, -0.866).normalize(),
+        ]
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() <= 1.0e-4 * a.abs().max(b.abs()).max(1.0)
+    }
+
+    #[test]
+    fn sample_weight_is_eval_over_pdf() {
+        let mut rng = seeded_rng(1);
+        for surface in SURFACES {
+            let bsdf = Bsdf {
+                normal: normal(),
+                model: BsdfModel::Principled(principled(surface)),
+            };
+            for wo in views() {
+                for _ in 0..1000 {
+                    let u = [rng.gen(), rng.gen(), rng.gen()];
+                    let sample = match bsdf.sample(wo, u) {
+                        Sampled::Scattered(sample) => sample,
+                        Sampled::Absorbed => continue,
+                    };
+                    let pdf = bsdf.pdf(wo, sample.wi);
+                    let expected = bsdf.eval(wo, sample.wi) / pdf;
+                    assert!(pdf > 0.0 && close(sample.pdf, pdf), "{:?}", surface);
+                    for c in 0..3 {
+                        assert!(
+                            close(sample.weight[c], expected[c]),
+                            "{:?} {:?} {:?}",
+                            surface,
+                            sample.weight,
+                            expected
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn pdf_integrates_to_one() {
+        let mut rng = seeded_rng(2);
+        // Midpoint rule in spherical coordinates, whose rows stay narrow at the poles where
+        // the lobes of views along the normal peak
+        let (rows, columns) = (400, 200);
+        let (d_theta, d_phi) = (PI / rows as f32, 2.0 * PI / columns as f32);
+        for surface in SURFACES {
+            let principled = principled(surface);
+            for wo in views() {
+                if wo.z < 0.0 && surface.2 == 0.0 {
+                    continue;
+                }
+                let mut integral = 0.0;
+                for row in 0..rows {
+                    let theta = (row as f32 + 0.5) * d_theta;
+                    let (r, z) = (theta.sin(), theta.cos());
+                    for column in 0..columns {
+                        let phi = (column as f32 + 0.5) * d_phi;
+                        let wi = Vec3::new(r * phi.cos(), r * phi.sin(), z);
+                        integral += principled.pdf(wo, wi, normal()) * r * d_theta * d_phi;
+                    }
+                }
+
+                // Microfacet normals are sampled without regard to the view, so some
+                // reflections leave below the surface and are dropped. The density only
+                // covers the samples that are kept.
+                let count = 10000;
+                let kept = (0..count)
+                    .filter(|_| {
+                        let u = [rng.gen(), rng.gen(), rng.gen()];
+                        principled.sample(wo, normal(), u).is_some()
+                    })
+                    .count() as f32
+                    / count as f32;
+                assert!(kept > 0.8, "{:?} seen from {:?}: {}", surface, wo, kept);
+                assert!(
+                    (integral - kept).abs() < 0.02,
+                    "{:?} seen from {:?}: {} for {} of the samples",
+                    surface,
+                    wo,
+                    integral,
+                    kept
+                );
+            }
+        }
+    }
+}
@@ -41,9 +41,6 @@ impl Ray {
         };
         let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
 
-        let random_range = Uniform::new(0.0, 1.0);
-        let random_float = random_range.sample(&mut rand::thread_rng());
-
         if refraction_ratio * sin_theta > 1.0
         // || self.reflactance(cos_theta, refraction_index) > random_float
         {
@@ -0,0 +1,497 @@
+use crate::aov::AovSample;
+use crate::camera::Camera;
+use crate::film::{Film, FilmPixel, TileSplats};
+use crate::filter::Filter;
+use crate::integrator::Integrator;
+use crate::sampler::{Sampler, SamplerType};
+use crate::scene::Scene;
+use crate::tile::{tiles, Tile, TileOrder};
+use crate::vec3::*;
+
+use image::{ImageBuffer, Rgb, RgbImage};
+use indicatif::{ProgressBar, ProgressStyle};
+use rayon::prelude::*;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Rendered 8-bit RGB image, with the origin in the top left corner
+pub type Image = RgbImage;
+
+/// Rendered 16-bit RGB image
+pub type Image16 = ImageBuffer<Rgb<u16>, Vec<u16>>;
+
+/// Parameters of a single render
+#[derive(Copy, Clone, Debug)]
+pub struct Settings {
+    pub width: u32,
+    pub height: u32,
+    pub samples_per_pixel: u32,
+    pub max_depth: u32,
+    pub integrator: Integrator,
+    /// Width and height of the square blocks of pixels the image is split into
+    pub tile_size: u32,
+    pub tile_order: TileOrder,
+    /// Seed of the random numbers used while rendering; the same seed gives the same image
+    pub seed: u64,
+    pub sampler: SamplerType,
+    /// Reconstruction filter that spreads each sample over the pixels around it
+    pub filter: Filter,
+    /// Record the arbitrary output variables of every pixel alongside the image
+    pub aovs: bool,
+    /// Spend samples only where the image is still noisy, with `samples_per_pixel` as the
+    /// most any pixel receives
+    pub adaptive: Option<Adaptive>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            width: 400,
+            height: 400,
+            samples_per_pixel: 10,
+            max_depth: 50,
+            integrator: Integrator::Phong,
+            tile_size: 16,
+            tile_order: TileOrder::Scanline,
+            seed: 0,
+            sampler: SamplerType::MultiJittered,
+            filter: Filter::default(),
+            aovs: false,
+            adaptive: None,
+        }
+    }
+}
+
+/// Renders scenes to images using all available cores, one tile at a time per core
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Renderer {
+    progress: bool,
+}
+
+impl Renderer {
+    pub fn new() -> Renderer {
+        Renderer { progress: false }
+    }
+
+    /// Show a progress bar on the terminal while rendering
+    pub fn with_progress(mut self, progress: bool) -> Renderer {
+        self.progress = progress;
+        self
+    }
+
+    pub fn render(&self, world: &Scene, cam: &Camera, settings: &Settings) -> Image {
+        self.render_film(world, cam, settings).to_image()
+    }
+
+    /// Render to a floating point film, which also records how many samples each pixel
+    /// received
+    pub fn render_film(&self, world: &Scene, cam: &Camera, settings: &Settings) -> Film {
+        let tiles = self.tiles(settings);
+        let film = Mutex::new(new_film(settings));
+        let pb = self.progress_bar(tiles.len() as u64);
+
+        match settings.adaptive {
+            None => {
+                let samples = settings.samples_per_pixel;
+                self.render_pass(world, cam, settings, &tiles, &film, &|_| samples, &pb);
+            }
+            Some(adaptive) => {
+                let max = settings.samples_per_pixel;
+                let mut added = 1;
+                while added > 0 {
+                    pb.reset();
+                    added = self.render_pass(
+                        world,
+                        cam,
+                        settings,
+                        &tiles,
+                        &film,
+                        &|pixel| adaptive.samples(pixel, max, ADAPTIVE_PASS_SAMPLES),
+                        &pb,
+                    );
+                }
+            }
+        }
+        pb.finish();
+
+        film.into_inner().unwrap()
+    }
+
+    /// Render successive passes of `progressive.samples_per_pass` samples per pixel until
+    /// the budget runs out. `flush` is called with the film accumulated so far at most once
+    /// every `progressive.flush_interval`, and the final film is returned.
+    pub fn render_progressive<F: FnMut(&Film)>(
+        &self,
+        world: &Scene,
+        cam: &Camera,
+        settings: &Settings,
+        progressive: &Progressive,
+        mut flush: F,
+    ) -> Film {
+        let tiles = self.tiles(settings);
+        let film = Mutex::new(new_film(settings));
+        let budget = progressive.budget.or_samples(settings.samples_per_pixel);
+        let per_pass = progressive.samples_per_pass.max(1);
+        let max = budget.samples_per_pixel.unwrap_or(u32::MAX);
+
+        let pb = self.progress_bar(tiles.len() as u64);
+        let start = Instant::now();
+        let mut last_flush = start;
+        let mut samples = 0;
+
+        loop {
+            let pass_samples = per_pass.min(max - samples);
+            pb.reset();
+            pb.set_message(&format!("{} spp", samples + pass_samples));
+            let added = match settings.adaptive {
+                None => {
+                    self.render_pass(world, cam, settings, &tiles, &film, &|_| pass_samples, &pb)
+                }
+                Some(adaptive) => self.render_pass(
+                    world,
+                    cam,
+                    settings,
+                    &tiles,
+                    &film,
+                    &|pixel| adaptive.samples(pixel, max, per_pass),
+                    &pb,
+                ),
+            };
+            samples += pass_samples;
+
+            let film = film.lock().unwrap();
+            // Adaptive renders also end once every pixel has converged
+            if added == 0 || budget.is_spent(&film, samples, start.elapsed()) {
+                break;
+            }
+            if last_flush.elapsed() >= progressive.flush_interval {
+                flush(&film);
+                last_flush = Instant::now();
+            }
+        }
+        pb.finish();
+
+        film.into_inner().unwrap()
+    }
+
+    fn tiles(&self, settings: &Settings) -> Vec<Tile> {
+        tiles(
+            settings.width,
+            settings.height,
+            settings.tile_size,
+            settings.tile_order,
+        )
+    }
+
+    fn progress_bar(&self, tiles: u64) -> ProgressBar {
+        let pb = if self.progress {
+            ProgressBar::new(tiles)
+        } else {
+            ProgressBar::hidden()
+        };
+        pb.set_style(
+            ProgressStyle::default_bar()
+                .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} tiles {msg} ({eta})")
+                .progress_chars("=> "),
+        );
+        pb
+    }
+
+    /// Add more samples to the pixels of the film, one tile at a time, with `samples`
+    /// deciding how many each pixel gets. Returns the number of samples taken.
+    #[allow(clippy::too_many_arguments)]
+    fn render_pass(
+        &self,
+        world: &Scene,
+        cam: &Camera,
+        settings: &Settings,
+        tiles: &[Tile],
+        film: &Mutex<Film>,
+        samples: &(dyn Fn(&FilmPixel) -> u32 + Sync),
+        pb: &ProgressBar,
+    ) -> u64 {
+        let added = AtomicU64::new(0);
+        let finished = Mutex::new(Vec::new());
+        // Each worker takes the next tile in order until none are left, so tiles start
+        // rendering in the requested order
+        let next = AtomicUsize::new(0);
+        (0..rayon::current_num_threads())
+            .into_par_iter()
+            .for_each(|_| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let tile = match tiles.get(index) {
+                    Some(tile) => tile,
+                    None => break,
+                };
+
+                let ranges: Vec<(u32, u32)> = {
+                    let film = film.lock().unwrap();
+                    tile.pixels()
+                        .map(|(x, y)| {
+                            let pixel = film.pixel(x, y);
+                            (pixel.samples, samples(pixel))
+                        })
+                        .collect()
+                };
+                let count: u64 = ranges.iter().map(|&(_, count)| count as u64).sum();
+                if count == 0 {
+                    pb.inc(1);
+                    continue;
+                }
+                added.fetch_add(count, Ordering::Relaxed);
+
+                let mut sampler = settings
+                    .sampler
+                    .build(settings.seed, settings.samples_per_pixel);
+                let mut splats =
+                    TileSplats::new(*tile, &settings.filter, settings.width, settings.height);
+
+                let samples: Vec<Vec<(Color, Option<AovSample>)>> = tile
+                    .pixels()
+                    .zip(ranges)
+                    .map(|((x, y), (first, count))| {
+                        (first..first + count)
+                            .map(|sample| {
+                                sampler.start_sample(x, y, sample);
+                                let (jitter_x, jitter_y) = sampler.next_2d();
+                                let (film_x, film_y) = (x as f32 + jitter_x, y as f32 + jitter_y);
+                                let (color, aov) = render_sample(
+                                    world,
+                                    cam,
+                                    settings,
+                                    film_x,
+                                    film_y,
+                                    sampler.as_mut(),
+                                );
+                                splats.add(film_x, film_y, color, &settings.filter);
+                                (color, aov)
+                            })
+                            .collect()
+                    })
+                    .collect();
+
+                let mut film = film.lock().unwrap();
+                for ((x, y), samples) in tile.pixels().zip(samples) {
+                    for (color, aov) in samples {
+                        film.pixel_mut(x, y).add(color);
+                        if let (Some(aov), Some(pixel)) = (aov, film.aov_pixel_mut(x, y)) {
+                            pixel.add(&aov);
+                        }
+                    }
+                }
+                drop(film);
+                finished.lock().unwrap().push(splats);
+                pb.inc(1);
+            });
+
+        // Splats of neighbouring tiles overlap, so they are merged in a fixed order to
+        // keep the image independent of which thread finished first
+        let mut finished = finished.into_inner().unwrap();
+        finished.sort_by_key(|splats| (splats.tile.y, splats.tile.x));
+        let mut film = film.lock().unwrap();
+        for splats in &finished {
+            film.merge(splats);
+        }
+
+        added.into_inner()
+    }
+}
+
+/// Conditions that end a progressive render, which stops as soon as any of them is met
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Budget {
+    /// Wall-clock time, checked after each pass
+    pub time: Option<Duration>,
+    pub samples_per_pixel: Option<u32>,
+    /// Largest relative standard error allowed in any pixel, see `FilmPixel::relative_error`
+    pub noise: Option<f32>,
+}
+
+/// Noise estimates are not trusted until every pixel has this many samples
+const MIN_NOISE_SAMPLES: u32 = 8;
+
+impl Budget {
+    /// The budget itself, or one that stops after `samples_per_pixel` if no limit is set
+    fn or_samples(self, samples_per_pixel: u32) -> Budget {
+        if self.time.is_none() && self.samples_per_pixel.is_none() && self.noise.is_none() {
+            Budget {
+                samples_per_pixel: Some(samples_per_pixel),
+                ..self
+            }
+        } else {
+            self
+        }
+    }
+
+    fn is_spent(&self, film: &Film, samples: u32, elapsed: Duration) -> bool {
+        self.time.is_some_and(|time| elapsed >= time)
+            || self
+                .samples_per_pixel
+                .is_some_and(|target| samples >= target)
+            || self.noise.is_some_and(|noise| {
+                samples >= MIN_NOISE_SAMPLES && film.max_relative_error() <= noise
+            })
+    }
+}
+
+/// Samples added per pass to pixels that have not converged yet
+const ADAPTIVE_PASS_SAMPLES: u32 = 4;
+
+/// Adaptive sampling: every pixel first receives `min_samples`, after which only pixels
+/// whose relative error is still above `threshold` get more
+#[derive(Copy, Clone, Debug)]
+pub struct Adaptive {
+    pub min_samples: u32,
+    pub threshold: f32,
+}
+
+impl Default for Adaptive {
+    fn default() -> Self {
+        Adaptive {
+            min_samples: 8,
+            threshold: 0.05,
+        }
+    }
+}
+
+impl Adaptive {
+    /// Samples to add to a pixel in the next pass, taking at most `per_pass` and never
+    /// going over `max` in total
+    fn samples(&self, pixel: &FilmPixel, max: u32, per_pass: u32) -> u32 {
+        let min = self.min_samples.max(2).min(max);
+        if pixel.samples < min {
+            min - pixel.samples
+        } else if pixel.samples < max && pixel.relative_error() > self.threshold {
+            per_pass.min(max - pixel.samples)
+        } else {
+            0
+        }
+    }
+}
+
+/// Parameters of `Renderer::render_progressive`
+#[derive(Copy, Clone, Debug)]
+pub struct Progressive {
+    pub budget: Budget,
+    pub samples_per_pass: u32,
+    /// Minimum time between two calls of the flush callback
+    pub flush_interval: Duration,
+}
+
+impl Default for Progressive {
+    fn default() -> Self {
+        Progressive {
+            budget: Budget::default(),
+            samples_per_pass: 4,
+            flush_interval: Duration::from_secs(10),
+        }
+    }
+}
+
+fn new_film(settings: &Settings) -> Film {
+    if settings.aovs {
+        Film::with_aovs(settings.width, settings.height)
+    } else {
+        Film::new(settings.width, settings.height)
+    }
+}
+
+/// Color seen through the film position `(film_x, film_y)`, measured in pixels from the
+/// top left corner, with `sampler` already started on the sample. The output variables
+/// of the camera ray are also returned if the settings ask for them.
+fn render_sample(
+    world: &Scene,
+    cam: &Camera,
+    settings: &Settings,
+    film_x: f32,
+    film_y: f32,
+    sampler: &mut dyn Sampler,
+) -> (Color, Option<AovSample>) {
+    let (width, height) = (settings.width as f32, settings.height as f32);
+
+    // Images one pixel wide or tall still span the film from 0 to 1
+    let u = film_x / (width.max(2.0) - 1.0);
+    let v = (height - film_y) / (height.max(2.0) - 1.0);
+    let r = cam.get_ray(u, v, sampler);
+    let depth = settings.max_depth as i32;
+    if settings.aovs {
+        let (radiance, hit) = settings
+            .integrator
+            .radiance_with_hit(r, world, depth, sampler);
+        (radiance.total(), Some(AovSample { hit, radiance }))
+    } else {
+        let radiance = settings.integrator.radiance(r, world, depth, sampler);
+        (radiance.total(), None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::CameraSettings;
+    use crate::examples::builtin_scene;
+    use crate::filter::FilterType;
+    use crate::rng::seeded_rng;
+
+    /// Small path traced render of the `simple` scene, with a filter wide enough that
+    /// neighbouring tiles splat onto the same pixels
+    fn render(seed: u64, threads: usize) -> Film {
+        let scene = builtin_scene("simple", &mut seeded_rng(0)).unwrap();
+        let settings = Settings {
+            width: 24,
+            height: 16,
+            samples_per_pixel: 4,
+            max_depth: 8,
+            integrator: Integrator::Path,
+            tile_size: 8,
+            seed,
+            filter: Filter::new(FilterType::Gaussian, 2.0),
+            aovs: true,
+            ..Settings::default()
+        };
+        let camera = CameraSettings::default().build(24.0 / 16.0);
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap()
+            .install(|| Renderer::new().render_film(&scene, &camera, &settings))
+    }
+
+    #[test]
+    fn render_is_independent_of_thread_count() {
+        assert_eq!(render(5, 1), render(5, 4));
+    }
+
+    #[test]
+    fn render_depends_only_on_seed() {
+        assert_eq!(render(5, 4), render(5, 4));
+        assert_ne!(render(5, 4), render(6, 4));
+    }
+
+    #[test]
+    fn single_pixel_rows_and_columns_render() {
+        let scene = builtin_scene("simple", &mut seeded_rng(0)).unwrap();
+        for (width, height) in [(1, 1), (1, 3), (3, 1)] {
+            let settings = Settings {
+                width,
+                height,
+                samples_per_pixel: 2,
+                max_depth: 4,
+                ..Settings::default()
+            };
+            let camera = CameraSettings::default().build(width as f32 / height as f32);
+            let film = Renderer::new().render_film(&scene, &camera, &settings);
+            for pixel in film.pixels() {
+                let color = pixel.color();
+                assert!(
+                    color.iter().all(|c| c.is_finite()),
+                    "{}x{} image has pixel {:?}",
+                    width,
+                    height,
+                    color
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,26 @@
+use rand::SeedableRng;
+use rand_pcg::Pcg64Mcg;
+
+/// Random number generator used for every sampling decision
+pub type SampleRng = Pcg64Mcg;
+
+/// Generator seeded from a single number
+pub fn seeded_rng(seed: u64) -> SampleRng {
+    SampleRng::seed_from_u64(seed)
+}
+
+/// Generator for one sample of one pixel. Every sample gets its own stream derived from
+/// the render seed, so the image does not depend on how the work is split across threads.
+pub fn sample_rng(seed: u64, pixel: u64, sample: u64) -> SampleRng {
+    let high = splitmix64(seed ^ splitmix64(pixel));
+    let low = splitmix64(high ^ splitmix64(sample.wrapping_add(0x9e37_79b9_7f4a_7c15)));
+    SampleRng::new(((high as u128) << 64) | low as u128)
+}
+
+/// Finalizer of the SplitMix64 generator, which turns nearby inputs into unrelated outputs
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
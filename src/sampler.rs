@@ -0,0 +1,565 @@
+//! Sample generators that provide the random numbers used while rendering.
+//!
+//! Every camera sample asks its sampler for a sequence of dimensions: two for the position
+//! within the pixel, two for the lens, then a few per bounce for light selection,
+//! scattering and Russian roulette. Better distributed samples in those dimensions reduce
+//! noise at the same sample count.
+
+use crate::rng::{sample_rng, SampleRng};
+
+use rand::Rng;
+use std::fmt;
+use std::str::FromStr;
+
+/// Source of sample values in `[0, 1)`.
+///
+/// The values of a sample depend only on the seed, the pixel, the sample index and the
+/// order in which dimensions are requested, so a pixel renders the same way on any thread.
+pub trait Sampler: Send {
+    /// Start the `index`th sample of the pixel at `(x, y)`
+    fn start_sample(&mut self, x: u32, y: u32, index: u32);
+
+    fn next_1d(&mut self) -> f32;
+
+    fn next_2d(&mut self) -> (f32, f32);
+}
+
+/// Kind of sampler used for a render
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SamplerType {
+    /// Uncorrelated random numbers
+    Independent,
+    /// One jittered sample in each cell of a grid over every dimension pair
+    Stratified,
+    /// Correlated multi-jittered samples, stratified both over the grid and along each axis
+    MultiJittered,
+    /// Halton sequence, randomized per pixel
+    Halton,
+    /// Owen-scrambled Sobol points, drawn independently for every dimension pair
+    Sobol,
+}
+
+impl SamplerType {
+    /// Sampler whose stratified patterns are laid out for `samples_per_pixel` samples
+    pub fn build(self, seed: u64, samples_per_pixel: u32) -> Box<dyn Sampler> {
+        let samples_per_pixel = samples_per_pixel.max(1);
+        match self {
+            SamplerType::Independent => Box::new(IndependentSampler::new(seed)),
+            SamplerType::Stratified => Box::new(StratifiedSampler::new(seed, samples_per_pixel)),
+            SamplerType::MultiJittered => {
+                Box::new(MultiJitteredSampler::new(seed, samples_per_pixel))
+            }
+            SamplerType::Halton => Box::new(HaltonSampler::new(seed)),
+            SamplerType::Sobol => Box::new(SobolSampler::new(seed)),
+        }
+    }
+}
+
+impl FromStr for SamplerType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "independent" => Ok(SamplerType::Independent),
+            "stratified" => Ok(SamplerType::Stratified),
+            "cmj" => Ok(SamplerType::MultiJittered),
+            "halton" => Ok(SamplerType::Halton),
+            "sobol" => Ok(SamplerType::Sobol),
+            _ => Err(format!(
+                "unknown sampler `{}`, expected `independent`, `stratified`, `cmj`, `halton` or `sobol`",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for SamplerType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SamplerType::Independent => write!(f, "independent"),
+            SamplerType::Stratified => write!(f, "stratified"),
+            SamplerType::MultiJittered => write!(f, "cmj"),
+            SamplerType::Halton => write!(f, "halton"),
+            SamplerType::Sobol => write!(f, "sobol"),
+        }
+    }
+}
+
+/// Position of the current sample, shared by the samplers below
+#[derive(Copy, Clone, Debug, Default)]
+struct SampleState {
+    /// Hash of the seed and pixel
+    pixel: u32,
+    index: u32,
+    dimension: u32,
+}
+
+impl SampleState {
+    fn start(&mut self, seed: u64, x: u32, y: u32, index: u32) {
+        self.pixel = hash(&[seed as u32, (seed >> 32) as u32, x, y]);
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    /// Scrambling key of the next dimension, advancing past `count` dimensions
+    fn next_key(&mut self, count: u32) -> u32 {
+        let key = hash(&[self.pixel, self.dimension]);
+        self.dimension += count;
+        key
+    }
+}
+
+/// Plain random numbers from a generator seeded per sample
+pub struct IndependentSampler {
+    seed: u64,
+    rng: SampleRng,
+}
+
+impl IndependentSampler {
+    pub fn new(seed: u64) -> IndependentSampler {
+        IndependentSampler {
+            seed,
+            rng: sample_rng(seed, 0, 0),
+        }
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn start_sample(&mut self, x: u32, y: u32, index: u32) {
+        let pixel = (y as u64) << 32 | x as u64;
+        self.rng = sample_rng(self.seed, pixel, index as u64);
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        self.rng.gen()
+    }
+
+    fn next_2d(&mut self) -> (f32, f32) {
+        (self.rng.gen(), self.rng.gen())
+    }
+}
+
+/// Jittered grid of `samples_per_pixel` cells, visited in a different random order for
+/// each dimension pair so that dimensions stay uncorrelated
+pub struct StratifiedSampler {
+    seed: u64,
+    samples_per_pixel: u32,
+    state: SampleState,
+}
+
+impl StratifiedSampler {
+    pub fn new(seed: u64, samples_per_pixel: u32) -> StratifiedSampler {
+        StratifiedSampler {
+            seed,
+            samples_per_pixel,
+            state: SampleState::default(),
+        }
+    }
+
+    /// Cell of the pattern used by the current sample, and a key that changes once all
+    /// cells have been used
+    fn cell(&mut self) -> (u32, u32) {
+        let key = self.state.next_key(1);
+        let round = self.state.index / self.samples_per_pixel;
+        let key = hash(&[key, round]);
+        let cell = permute(
+            self.state.index % self.samples_per_pixel,
+            self.samples_per_pixel,
+            key,
+        );
+        (cell, key)
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.state.start(self.seed, x, y, index);
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        let (cell, key) = self.cell();
+        let jitter = random_float(self.state.index, key);
+        ((cell as f32 + jitter) / self.samples_per_pixel as f32).min(ONE_MINUS_EPSILON)
+    }
+
+    fn next_2d(&mut self) -> (f32, f32) {
+        let (cell, key) = self.cell();
+        // Grid as close to square as possible, with some cells left empty if the sample
+        // count is not a product of the two sides
+        let columns = (self.samples_per_pixel as f32).sqrt().ceil() as u32;
+        let rows = self.samples_per_pixel.div_ceil(columns);
+        let jitter_x = random_float(self.state.index, key.wrapping_mul(0xa399_d265));
+        let jitter_y = random_float(self.state.index, key.wrapping_mul(0x711a_d6a5));
+        (
+            (((cell % columns) as f32 + jitter_x) / columns as f32).min(ONE_MINUS_EPSILON),
+            (((cell / columns) as f32 + jitter_y) / rows as f32).min(ONE_MINUS_EPSILON),
+        )
+    }
+}
+
+/// Correlated multi-jittered sampling (Kensler 2013): a jittered grid whose samples are
+/// also spread evenly along each axis
+pub struct MultiJitteredSampler {
+    seed: u64,
+    samples_per_pixel: u32,
+    state: SampleState,
+}
+
+impl MultiJitteredSampler {
+    pub fn new(seed: u64, samples_per_pixel: u32) -> MultiJitteredSampler {
+        MultiJitteredSampler {
+            seed,
+            samples_per_pixel,
+            state: SampleState::default(),
+        }
+    }
+
+    fn key(&mut self) -> u32 {
+        let key = self.state.next_key(1);
+        hash(&[key, self.state.index / self.samples_per_pixel])
+    }
+}
+
+impl Sampler for MultiJitteredSampler {
+    fn start_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.state.start(self.seed, x, y, index);
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        let n = self.samples_per_pixel;
+        let key = self.key();
+        let s = permute(self.state.index % n, n, key.wrapping_mul(0x6828_1d4d));
+        let jitter = random_float(s, key.wrapping_mul(0x967a_889b));
+        ((s as f32 + jitter) / n as f32).min(ONE_MINUS_EPSILON)
+    }
+
+    fn next_2d(&mut self) -> (f32, f32) {
+        let n = self.samples_per_pixel;
+        let key = self.key();
+        let m = ((n as f32).sqrt() as u32).max(1);
+        let rows = n.div_ceil(m);
+
+        let s = permute(self.state.index % n, n, key.wrapping_mul(0x5163_3e2d));
+        let sx = permute(s % m, m, key.wrapping_mul(0xa511_e9b3));
+        let sy = permute(s / m, rows, key.wrapping_mul(0x63d8_3595));
+        let jx = random_float(s, key.wrapping_mul(0xa399_d265));
+        let jy = random_float(s, key.wrapping_mul(0x711a_d6a5));
+        (
+            (((s % m) as f32 + (sy as f32 + jx) / rows as f32) / m as f32).min(ONE_MINUS_EPSILON),
+            (((s / m) as f32 + (sx as f32 + jy) / m as f32) / rows as f32).min(ONE_MINUS_EPSILON),
+        )
+    }
+}
+
+/// Bases of the Halton dimensions. Later dimensions fall back to random numbers, since
+/// Halton points in large bases are badly correlated at practical sample counts.
+const HALTON_PRIMES: [u32; 16] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53];
+
+/// Halton sequence with a random shift per pixel and dimension (Cranley-Patterson
+/// rotation), so neighbouring pixels do not share the same pattern
+pub struct HaltonSampler {
+    seed: u64,
+    state: SampleState,
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u64) -> HaltonSampler {
+        HaltonSampler {
+            seed,
+            state: SampleState::default(),
+        }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.state.start(self.seed, x, y, index);
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        let dimension = self.state.dimension as usize;
+        let key = self.state.next_key(1);
+        let value = match HALTON_PRIMES.get(dimension) {
+            Some(&base) => radical_inverse(base, self.state.index),
+            None => random_float(self.state.index, key),
+        };
+        let shifted = value + random_float(0, key);
+        (shifted - shifted.floor()).min(ONE_MINUS_EPSILON)
+    }
+
+    fn next_2d(&mut self) -> (f32, f32) {
+        (self.next_1d(), self.next_1d())
+    }
+}
+
+/// Padded Owen-scrambled Sobol points (Burley 2020): each dimension pair uses the first
+/// two Sobol dimensions with its own shuffle and scramble, which keeps every pair well
+/// stratified for any number of samples
+pub struct SobolSampler {
+    seed: u64,
+    state: SampleState,
+}
+
+impl SobolSampler {
+    pub fn new(seed: u64) -> SobolSampler {
+        SobolSampler {
+            seed,
+            state: SampleState::default(),
+        }
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.state.start(self.seed, x, y, index);
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        let key = self.state.next_key(1);
+        let index = owen_scramble(self.state.index, key);
+        to_float(owen_scramble(index.reverse_bits(), hash(&[key, 1])))
+    }
+
+    fn next_2d(&mut self) -> (f32, f32) {
+        let key = self.state.next_key(2);
+        let index = owen_scramble(self.state.index, key);
+        (
+            to_float(owen_scramble(index.reverse_bits(), hash(&[key, 1]))),
+            to_float(owen_scramble(
+                sobol_second_dimension(index),
+                hash(&[key, 2]),
+            )),
+        )
+    }
+}
+
+/// Largest `f32` below one
+const ONE_MINUS_EPSILON: f32 = 1.0 - f32::EPSILON / 2.0;
+
+fn to_float(bits: u32) -> f32 {
+    ((bits >> 8) as f32 / (1u32 << 24) as f32).min(ONE_MINUS_EPSILON)
+}
+
+/// Digits of `index` in base `base`, mirrored around the decimal point
+fn radical_inverse(base: u32, mut index: u32) -> f32 {
+    let inverse_base = 1.0 / base as f64;
+    let mut reversed = 0u64;
+    let mut scale = 1.0;
+    while index > 0 {
+        reversed = reversed * base as u64 + (index % base) as u64;
+        scale *= inverse_base;
+        index /= base;
+    }
+    ((reversed as f64 * scale) as f32).min(ONE_MINUS_EPSILON)
+}
+
+/// Second dimension of the Sobol sequence, as bits of a fixed point fraction
+fn sobol_second_dimension(mut index: u32) -> u32 {
+    let mut direction = 1 << 31;
+    let mut value = 0;
+    while index != 0 {
+        if index & 1 != 0 {
+            value ^= direction;
+        }
+        index >>= 1;
+        direction ^= direction >> 1;
+    }
+    value
+}
+
+/// Nested uniform scrambling of a fixed point fraction, which flips each bit based on
+/// all the bits above it (Laine-Karras hash applied to the reversed bits)
+fn owen_scramble(value: u32, key: u32) -> u32 {
+    let mut x = value.reverse_bits();
+    x = x.wrapping_add(key);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x.reverse_bits()
+}
+
+/// Element `i` of a random permutation of `0..length` chosen by `key` (Kensler 2013)
+fn permute(mut i: u32, length: u32, key: u32) -> u32 {
+    if length <= 1 {
+        return 0;
+    }
+    let mut w = length - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= key;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= key >> 16;
+        i ^= (i & w) >> 4;
+        i ^= key >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= key >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | key >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < length {
+            break;
+        }
+    }
+    (i.wrapping_add(key)) % length
+}
+
+/// Random number in `[0, 1)` chosen by `i` and `key` (Kensler 2013)
+fn random_float(mut i: u32, key: u32) -> f32 {
+    i ^= key;
+    i ^= i >> 17;
+    i ^= i >> 10;
+    i = i.wrapping_mul(0xb365_34e5);
+    i ^= i >> 12;
+    i ^= i >> 21;
+    i = i.wrapping_mul(0x93fc_4795);
+    i ^= 0xdf6e_307f;
+    i ^= i >> 17;
+    i = i.wrapping_mul(1 | key >> 18);
+    to_float(i)
+}
+
+/// Combine several words into one well-mixed word
+fn hash(words: &[u32]) -> u32 {
+    let mut h: u32 = 0x811c_9dc5;
+    for &word in words {
+        h ^= word;
+        h = h.wrapping_mul(0x0100_0193);
+        h ^= h >> 15;
+        h = h.wrapping_mul(0x2c1b_3c6d);
+        h ^= h >> 12;
+    }
+    h
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TYPES: [SamplerType; 5] = [
+        SamplerType::Independent,
+        SamplerType::Stratified,
+        SamplerType::MultiJittered,
+        SamplerType::Halton,
+        SamplerType::Sobol,
+    ];
+
+    /// Values of the first dimensions of sample `index` of pixel `(x, y)`, alternating
+    /// between one and two dimensions at a time
+    fn values(sampler: &mut dyn Sampler, x: u32, y: u32, index: u32) -> Vec<f32> {
+        sampler.start_sample(x, y, index);
+        let mut values = Vec::new();
+        for _ in 0..10 {
+            values.push(sampler.next_1d());
+            let (u, v) = sampler.next_2d();
+            values.extend([u, v]);
+        }
+        values
+    }
+
+    #[test]
+    fn values_are_in_unit_interval() {
+        for sampler_type in TYPES {
+            for samples_per_pixel in [1, 5, 12, 16, 33] {
+                let mut sampler = sampler_type.build(3, samples_per_pixel);
+                for (x, y) in [(0, 0), (7, 3), (1023, 511)] {
+                    for index in 0..2 * samples_per_pixel {
+                        for value in values(sampler.as_mut(), x, y, index) {
+                            assert!(
+                                (0.0..1.0).contains(&value),
+                                "{} with {} spp gave {}",
+                                sampler_type,
+                                samples_per_pixel,
+                                value
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Whether the cell indices cover each of `count` cells exactly once
+    fn one_per_stratum(values: impl Iterator<Item = u32>, count: u32) -> bool {
+        let mut seen = vec![false; count as usize];
+        for cell in values {
+            if cell >= count || seen[cell as usize] {
+                return false;
+            }
+            seen[cell as usize] = true;
+        }
+        seen.iter().all(|&seen| seen)
+    }
+
+    #[test]
+    fn stratified_patterns_fill_every_stratum() {
+        for sampler_type in [SamplerType::Stratified, SamplerType::MultiJittered] {
+            // A square count, and one whose grid is not square
+            for n in [16, 12] {
+                // The stratified grid rounds its side up and the multi-jittered one down
+                let columns = match sampler_type {
+                    SamplerType::Stratified => (n as f32).sqrt().ceil() as u32,
+                    _ => (n as f32).sqrt() as u32,
+                };
+                let rows = n / columns;
+                let mut sampler = sampler_type.build(9, n);
+                // The pattern is used again, differently shuffled, after every `n` samples
+                for round in 0..2 {
+                    let samples: Vec<(f32, (f32, f32))> = (round * n..(round + 1) * n)
+                        .map(|index| {
+                            sampler.start_sample(5, 2, index);
+                            (sampler.next_1d(), sampler.next_2d())
+                        })
+                        .collect();
+                    let cell = |value: f32, count: u32| (value * count as f32) as u32;
+
+                    assert!(
+                        one_per_stratum(samples.iter().map(|&(u, _)| cell(u, n)), n),
+                        "{} 1D",
+                        sampler_type
+                    );
+                    let cells = samples
+                        .iter()
+                        .map(|&(_, (u, v))| cell(v, rows) * columns + cell(u, columns));
+                    assert!(one_per_stratum(cells, n), "{} 2D", sampler_type);
+                    if sampler_type == SamplerType::MultiJittered {
+                        // Also evenly spread along each axis
+                        let xs = samples.iter().map(|&(_, (u, _))| cell(u, n));
+                        let ys = samples.iter().map(|&(_, (_, v))| cell(v, n));
+                        assert!(one_per_stratum(xs, n) && one_per_stratum(ys, n));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn sobol_is_deterministic_for_a_seed() {
+        let sequence = |seed: u64| -> Vec<f32> {
+            let mut sampler = SamplerType::Sobol.build(seed, 16);
+            (0..16)
+                .flat_map(|index| values(sampler.as_mut(), 4, 9, index))
+                .collect()
+        };
+        assert_eq!(sequence(11), sequence(11));
+        assert_ne!(sequence(11), sequence(12));
+
+        // Reusing a sampler gives the same values as a fresh one
+        let mut sampler = SamplerType::Sobol.build(11, 16);
+        let first = values(sampler.as_mut(), 4, 9, 3);
+        values(sampler.as_mut(), 8, 1, 7);
+        assert_eq!(values(sampler.as_mut(), 4, 9, 3), first);
+    }
+}
@@ -1,16 +1,28 @@
+use crate::background::Background;
 use crate::hittable::bvh::BoundingBox;
 use crate::hittable::sphere::Sphere;
-use crate::hittable::{aabb::AABB, bvh::BVH, HitRecord, Hittable};
-use crate::light::{Light, PointLight};
+use crate::hittable::triangle::SimpleTriangle;
+use crate::hittable::{aabb::AABB, bvh::BVH, HitRecord, Hittable, Primitive};
+use crate::light::{EmissiveTriangle, Light, LightHit, MeshLight, PointLight};
+use crate::material::apply_normal_maps;
 use crate::ray::Ray;
+use crate::rng::seeded_rng;
 
 use crate::vec3::*;
 
 #[derive(Clone)]
 pub struct Scene {
-    pub lights: Vec<PointLight>,
+    pub lights: Vec<Light>,
     objects: Vec<Box<dyn Hittable>>,
-    background: (Color, Color),
+    background: Background,
+    /// Identifier given to the next object added
+    next_id: u32,
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Scene::new()
+    }
 }
 
 impl Scene {
@@ -18,42 +30,137 @@ impl Scene {
         Scene {
             lights: Vec::new(),
             objects: Vec::new(),
-            background: (Color::new(1.0, 1.0, 1.0), Color::new(0.5, 0.7, 1.0)),
+            background: Background::default(),
+            next_id: 0,
         }
     }
 
+    /// Number of top-level objects in the scene
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
     pub fn clear(&mut self) {
         self.objects = Vec::new();
+        self.next_id = 0;
+        self.lights.retain(|light| !matches!(light, Light::Mesh(_)));
+    }
+
+    /// Add an object to the scene. Its emissive triangles are also registered as a light so
+    /// that they can be sampled directly.
+    pub fn add<T: Hittable + Clone + 'static>(&mut self, object: T) {
+        let emitters: Vec<EmissiveTriangle> = object
+            .primitives()
+            .into_iter()
+            .filter_map(|primitive| match primitive {
+                Primitive::SimpleTriangle(t) if t.material.is_emissive() => {
+                    let facing = (t.p2 - t.p1).cross(&(t.p3 - t.p1));
+                    Some(EmissiveTriangle::new(t, facing))
+                }
+                Primitive::Triangle(t) if t.material.is_emissive() => {
+                    let facing = t.n1 + t.n2 + t.n3;
+                    Some(EmissiveTriangle::new(
+                        SimpleTriangle::new(t.p1, t.p2, t.p3, t.material).with_uvs(t.uvs),
+                        facing,
+                    ))
+                }
+                _ => None,
+            })
+            .collect();
+        if let Some(light) = MeshLight::new(emitters) {
+            self.add_light(light);
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+        self.objects.push(Box::new(SceneObject { id, object }));
+    }
+
+    /// Primitives of each object, in the order the objects were added, also once the scene
+    /// has been accelerated
+    pub fn groups(&self) -> Vec<Vec<Primitive>> {
+        let mut objects: Vec<(u32, Vec<Primitive>)> = self
+            .objects
+            .iter()
+            .flat_map(|object| object.objects())
+            .collect();
+        objects.sort_by_key(|(id, _)| *id);
+        objects
+            .into_iter()
+            .map(|(_, primitives)| primitives)
+            .collect()
+    }
+
+    /// What rays that leave the scene see, a white to blue gradient by default
+    pub fn background(&self) -> &Background {
+        &self.background
     }
 
-    pub fn add<T: Hittable + 'static>(&mut self, object: T) {
-        self.objects.push(Box::new(object));
+    pub fn set_background(&mut self, background: Background) {
+        self.background = background;
     }
 
-    pub fn add_light(&mut self, light: PointLight) {
-        self.lights.push(light);
+    pub fn add_light<L: Into<Light>>(&mut self, light: L) {
+        self.lights.push(light.into());
+    }
+
+    /// Closest intersection of a ray with the surface of a light
+    pub fn hit_light(&self, r: Ray, t_min: f32, t_max: f32) -> Option<LightHit> {
+        let mut closest = t_max;
+        let mut closest_hit = None;
+
+        for light in &self.lights {
+            if let Some(hit) = light.hit(r, t_min, closest) {
+                closest = hit.t;
+                closest_hit = Some(hit);
+            }
+        }
+        closest_hit
     }
 
-    pub fn accelerate(&mut self, t0: f32, t1: f32) {
+    /// Put the objects that have a bounding box into a BVH, leaving the others to be tested
+    /// one by one
+    pub fn accelerate(&mut self, t0: f32, t1: f32) -> Accelerated {
         let mut nodes: Vec<Box<dyn Hittable>> = Vec::new();
         let mut extra: Vec<Box<dyn Hittable>> = Vec::new();
         for prim in self.objects.clone() {
-            match prim.bounding_box(0.0, std::f32::MAX) {
+            match prim.bounding_box(0.0, f32::MAX) {
                 Some(_) => nodes.push(prim),
                 None => extra.push(prim),
             }
         }
-        println!("Found {} non-boundable objects", extra.len());
-        println!("Adding {} hittables to BVH", nodes.len());
-        let bvh = BVH::build(nodes, t0, t1);
+        let accelerated = Accelerated {
+            bounded: nodes.len(),
+            unbounded: extra.len(),
+        };
+        if nodes.is_empty() {
+            return accelerated;
+        }
+        // The hierarchy does not change what rays hit, so a fixed seed is enough to keep
+        // renders reproducible
+        let bvh = BVH::build(nodes, t0, t1, &mut seeded_rng(0));
         self.objects = Vec::new();
         self.objects.push(bvh);
         self.objects.append(&mut extra);
+        accelerated
     }
 }
 
+/// How `Scene::accelerate` split the objects of a scene
+#[derive(Copy, Clone, Debug)]
+pub struct Accelerated {
+    /// Objects put into the BVH
+    pub bounded: usize,
+    /// Objects without a bounding box, such as planes
+    pub unbounded: usize,
+}
+
 impl Hittable for Scene {
-    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
         let mut closest = t_max;
         let mut closest_hit = None;
 
@@ -69,7 +176,7 @@ impl Hittable for Scene {
                 None => continue,
             }
         }
-        closest_hit
+        closest_hit.map(apply_normal_maps)
     }
 
     fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB> {
@@ -90,4 +197,39 @@ impl Hittable for Scene {
         }
         Some(output_box)
     }
+
+    fn primitives(&self) -> Vec<Primitive> {
+        self.objects
+            .iter()
+            .flat_map(|object| object.primitives())
+            .collect()
+    }
+}
+
+/// Object added to a scene, which marks its hits with the object's identifier
+#[derive(Clone)]
+struct SceneObject<T> {
+    id: u32,
+    object: T,
+}
+
+impl<T: Hittable + Clone + 'static> Hittable for SceneObject<T> {
+    fn hit(&self, r: Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        self.object.hit(r, t_min, t_max).map(|rec| HitRecord {
+            object_id: self.id,
+            ..rec
+        })
+    }
+
+    fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB> {
+        self.object.bounding_box(t0, t1)
+    }
+
+    fn primitives(&self) -> Vec<Primitive> {
+        self.object.primitives()
+    }
+
+    fn objects(&self) -> Vec<(u32, Vec<Primitive>)> {
+        vec![(self.id, self.object.primitives())]
+    }
 }
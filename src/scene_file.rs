@@ -0,0 +1,1664 @@
+//! Declarative scene descriptions
+//!
+//! Scenes can be written as TOML or JSON documents. Both use the same layout:
+//!
+//! ```toml
+//! version = 1
+//!
+//! [camera]
+//! lookfrom = [0.0, 0.0, 2.0]
+//! lookat = [0.0, 0.0, -1.0]
+//! vfov = 60.0
+//!
+//! [materials.ground]
+//! type = "lambertian"
+//! albedo = [0.8, 0.8, 0.0]
+//!
+//! [[spheres]]
+//! center = [0.0, -100.5, -1.0]
+//! radius = 100.0
+//! material = "ground"
+//!
+//! [[meshes]]
+//! file = "../objs/teapot.obj"
+//! material = "ground"
+//! translate = [0.0, -2.0, -10.0]
+//! scale = 0.5
+//!
+//! [[lights]]
+//! type = "point"
+//! position = [-10.0, -10.0, -10.0]
+//! color = [1.0, 1.0, 1.0]
+//!
+//! [[lights]]
+//! type = "rectangle"
+//! center = [0.0, 5.0, -1.0]
+//! u = [2.0, 0.0, 0.0]
+//! v = [0.0, 0.0, 2.0]
+//! color = [4.0, 4.0, 4.0]
+//! ```
+//!
+//! Triangles list their three `vertices`, optionally with per-vertex `normals` for smooth
+//! shading and `uvs` surface coordinates for textures. Meshes take their materials from
+//! the `.mtl` library their OBJ file names, if any; the mesh's `material` covers the faces
+//! the library gives no material.
+//!
+//! Besides `point` and `rectangle` lights, `disk` (`center`, `normal`, `radius`) and
+//! `sphere` (`center`, `radius`) area lights are available. Geometry can also glow by
+//! using an `emissive` material (`color`, `strength`); emissive triangles and meshes are
+//! sampled as lights automatically. Besides `lambertian`, `metal`, `dielectric` and
+//! `emissive`, materials can be `phong` (`albedo`, with optional `ambient`, `diffuse`,
+//! `specular` and `shininess` coefficients) or `principled`, a metallic-roughness
+//! material with a `base_color` (default a light gray), `metallic` (0) and `roughness`
+//! (0.5), which can both be textures, and optional `specular` reflectance of dielectrics
+//! (0.5, meaning 4 %), `sheen`, `clearcoat`, `transmission` and `ior` (1.5):
+//!
+//! ```toml
+//! [materials.copper]
+//! type = "principled"
+//! base_color = [0.95, 0.64, 0.54]
+//! metallic = 1.0
+//! roughness = 0.3
+//! ```
+//!
+//! Material colors (`albedo`, and `color` of emissive materials) and the `fuzz` of metals
+//! are either plain values or texture tables:
+//!
+//! ```toml
+//! [materials.floor]
+//! type = "lambertian"
+//! albedo = { type = "checker", even = [0.9, 0.9, 0.9], odd = { type = "image", file = "wood.png" }, scale = 8.0 }
+//! ```
+//!
+//! Textures can be an `image` (`file`, with optional `wrap` of `repeat`, `mirror` or
+//! `clamp`, and `color_space` of `srgb` or `linear`), a `checker` (`even`, `odd` and `scale` squares per unit of `uv`), `noise`
+//! (`low`, `high`, `scale`, `octaves`), `marble` (`base`, `vein`, `scale`, `turbulence`,
+//! `octaves`) or a `gradient` (`start`, `end` and the `axis`, `u` or `v`).
+//!
+//! Any material can be given surface detail by wrapping it in a `normal_mapped` material
+//! (`material`, a tangent space `normal_map` texture and its `strength`) or a
+//! `bump_mapped` one (`material`, a height texture `bump_map` and the `scale` of a height
+//! of one in scene units). Normal and height maps should use `color_space = "linear"`:
+//!
+//! ```toml
+//! [materials.bricks]
+//! type = "normal_mapped"
+//! material = { type = "lambertian", albedo = { type = "image", file = "bricks.png" } }
+//! normal_map = { type = "image", file = "bricks_normal.png", color_space = "linear" }
+//! ```
+//!
+//! The `background` table is either `solid` (`color`), `gradient` (`bottom`, `top`) or an
+//! `environment` map (`file` naming an equirectangular `.hdr` or `.exr` image, with
+//! optional `rotation` in degrees and `intensity`).
+//!
+//! Paths are resolved relative to the directory containing the scene file. Any `Scene` can
+//! be written back out with [`save`], which describes every primitive inline so that
+//! procedurally generated scenes can be reloaded exactly. Meshes are written as `groups`,
+//! each a list of `triangles` that is added to the scene as one object, so that an emissive
+//! mesh is still sampled as a single light.
+
+use crate::background::{Background, EnvironmentMap};
+use crate::camera::{CameraSettings, Projection};
+use crate::hittable::mesh::TriangleMesh;
+use crate::hittable::plane::Plane;
+use crate::hittable::sphere::Sphere;
+use crate::hittable::triangle::{SimpleTriangle, Triangle};
+use crate::hittable::{Hittable, Primitive};
+use crate::light::{AreaLight, AreaShape, Light, PointLight};
+use crate::material::Material;
+use crate::scene::Scene;
+use crate::texture::{
+    constant, CheckerTexture, ColorSpace, GradientAxis, GradientTexture, ImageTexture,
+    MarbleTexture, NoiseTexture, Texture, TextureKind, WrapMode,
+};
+use crate::vec3::*;
+
+use na::{Matrix4, Rotation3};
+use serde::de::value::MapAccessDeserializer;
+use serde::de::{Deserializer, Error as _, MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Serialize, Serializer};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Newest version of the scene format understood by this build
+pub const FORMAT_VERSION: u32 = 1;
+
+/// A scene and the camera it was authored with
+pub struct LoadedScene {
+    pub scene: Scene,
+    pub camera: Option<CameraSettings>,
+}
+
+/// Error produced while loading a scene file, with a position when one is known
+#[derive(Debug)]
+pub struct SceneError {
+    pub path: PathBuf,
+    pub position: Option<(usize, usize)>,
+    pub message: String,
+}
+
+impl SceneError {
+    fn new(path: &Path, message: String) -> SceneError {
+        SceneError {
+            path: path.to_path_buf(),
+            position: None,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.position {
+            Some((line, column)) => write!(
+                f,
+                "{}:{}:{}: {}",
+                self.path.display(),
+                line,
+                column,
+                self.message
+            ),
+            None => write!(f, "{}: {}", self.path.display(), self.message),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+/// Load a `.toml` or `.json` scene file
+pub fn load(path: &Path) -> Result<LoadedScene, SceneError> {
+    let source = read_to_string(path).map_err(|err| SceneError::new(path, err.to_string()))?;
+    let format = Format::from_path(path)?;
+
+    // Material names have to be known before references to them can be checked, so the
+    // material table is parsed on its own first. Files are already found relative to the
+    // scene file then, since materials name images.
+    let base_dir = path.parent().unwrap_or_else(|| Path::new("")).to_path_buf();
+    let materials = {
+        let _guard = ContextGuard::enter(LoadContext {
+            materials: None,
+            base_dir: base_dir.clone(),
+        });
+        format.parse::<MaterialTable>(path, &source)?.materials
+    };
+    let context = LoadContext {
+        materials: Some(materials.keys().cloned().collect()),
+        base_dir,
+    };
+    let desc = {
+        let _guard = ContextGuard::enter(context);
+        format.parse::<SceneDesc>(path, &source)?
+    };
+
+    desc.build(path)
+}
+
+/// Write a scene and the camera it should be viewed with to a `.toml` or `.json` file
+pub fn save(scene: &Scene, camera: Option<&CameraSettings>, path: &Path) -> Result<(), SceneError> {
+    let format = Format::from_path(path)?;
+    let source = format.write(&SceneDesc::from_scene(scene, camera), path)?;
+    std::fs::write(path, source).map_err(|err| SceneError::new(path, err.to_string()))
+}
+
+#[derive(Copy, Clone)]
+enum Format {
+    Toml,
+    Json,
+}
+
+impl Format {
+    fn from_path(path: &Path) -> Result<Format, SceneError> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Ok(Format::Toml),
+            Some("json") => Ok(Format::Json),
+            _ => Err(SceneError::new(
+                path,
+                "scene files must have a `.toml` or `.json` extension".to_string(),
+            )),
+        }
+    }
+
+    fn parse<T: for<'de> Deserialize<'de>>(
+        self,
+        path: &Path,
+        source: &str,
+    ) -> Result<T, SceneError> {
+        match self {
+            Format::Toml => toml::from_str(source).map_err(|err| SceneError {
+                path: path.to_path_buf(),
+                position: err.span().map(|span| line_column(source, span.start)),
+                message: err.message().to_string(),
+            }),
+            Format::Json => serde_json::from_str(source).map_err(|err| {
+                let position = (err.line(), err.column());
+                let suffix = format!(" at line {} column {}", position.0, position.1);
+                let message = err.to_string();
+                SceneError {
+                    path: path.to_path_buf(),
+                    position: if position.0 > 0 { Some(position) } else { None },
+                    message: message.trim_end_matches(&suffix).to_string(),
+                }
+            }),
+        }
+    }
+}
+
+impl Format {
+    fn write(self, desc: &SceneDesc, path: &Path) -> Result<String, SceneError> {
+        let source = match self {
+            Format::Toml => toml::Value::try_from(desc)
+                .and_then(|mut value| {
+                    shorten_floats(&mut value);
+                    toml::to_string(&value)
+                })
+                .map_err(|err| err.to_string()),
+            Format::Json => serde_json::to_string_pretty(desc).map_err(|err| err.to_string()),
+        };
+        source.map_err(|err| SceneError::new(path, err))
+    }
+}
+
+/// TOML stores every float as an `f64`, which would print `0.1_f32` as `0.10000000149011612`.
+/// Replace each value with the shortest `f64` that still parses back to the same `f32`.
+fn shorten_floats(value: &mut toml::Value) {
+    match value {
+        toml::Value::Float(f) => {
+            let single = *f as f32;
+            if single as f64 == *f {
+                *f = single.to_string().parse().unwrap_or(*f);
+            }
+        }
+        toml::Value::Array(array) => array.iter_mut().for_each(shorten_floats),
+        toml::Value::Table(table) => table.iter_mut().for_each(|(_, v)| shorten_floats(v)),
+        _ => {}
+    }
+}
+
+/// One-based line and column of a byte offset
+fn line_column(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset.min(source.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.chars().rev().take_while(|&c| c != '\n').count() + 1;
+    (line, column)
+}
+
+/// State consulted while deserializing references to materials and files. Checking
+/// references inside `Deserialize` lets the parsers attach a position to the error.
+struct LoadContext {
+    /// Names of the materials, or `None` while the material table itself is read
+    materials: Option<HashSet<String>>,
+    base_dir: PathBuf,
+}
+
+thread_local! {
+    static CONTEXT: RefCell<Option<LoadContext>> = const { RefCell::new(None) };
+}
+
+struct ContextGuard;
+
+impl ContextGuard {
+    fn enter(context: LoadContext) -> ContextGuard {
+        CONTEXT.with(|c| *c.borrow_mut() = Some(context));
+        ContextGuard
+    }
+}
+
+impl Drop for ContextGuard {
+    fn drop(&mut self) {
+        CONTEXT.with(|c| *c.borrow_mut() = None);
+    }
+}
+
+#[derive(Deserialize)]
+struct MaterialTable {
+    #[serde(default)]
+    materials: BTreeMap<String, MaterialDesc>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct SceneDesc {
+    version: Version,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    camera: Option<CameraDesc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    background: Option<BackgroundDesc>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    materials: BTreeMap<String, MaterialDesc>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    spheres: Vec<SphereDesc>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    planes: Vec<PlaneDesc>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    triangles: Vec<TriangleDesc>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    meshes: Vec<MeshDesc>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    groups: Vec<GroupDesc>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    lights: Vec<LightDesc>,
+}
+
+struct Version;
+
+impl<'de> Deserialize<'de> for Version {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let version = u32::deserialize(deserializer)?;
+        if version == 0 || version > FORMAT_VERSION {
+            return Err(D::Error::custom(format!(
+                "unsupported scene format version {}, this build reads version {}",
+                version, FORMAT_VERSION
+            )));
+        }
+        Ok(Version)
+    }
+}
+
+impl Serialize for Version {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        FORMAT_VERSION.serialize(serializer)
+    }
+}
+
+/// Name of an entry in the `materials` table
+struct MaterialRef(String);
+
+impl<'de> Deserialize<'de> for MaterialRef {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        let known = CONTEXT.with(|c| {
+            c.borrow()
+                .as_ref()
+                .and_then(|context| context.materials.as_ref())
+                .is_none_or(|materials| materials.contains(&name))
+        });
+        if !known {
+            return Err(D::Error::custom(format!("unknown material `{}`", name)));
+        }
+        Ok(MaterialRef(name))
+    }
+}
+
+impl Serialize for MaterialRef {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+/// Path to an existing file, relative to the scene file
+struct FileRef(PathBuf);
+
+impl<'de> Deserialize<'de> for FileRef {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let file = String::deserialize(deserializer)?;
+        let path = CONTEXT.with(|c| match c.borrow().as_ref() {
+            Some(context) => context.base_dir.join(&file),
+            None => PathBuf::from(&file),
+        });
+        if !path.is_file() {
+            return Err(D::Error::custom(format!(
+                "file `{}` does not exist",
+                path.display()
+            )));
+        }
+        Ok(FileRef(path))
+    }
+}
+
+impl Serialize for FileRef {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct CameraDesc {
+    projection: Option<Projection>,
+    lookfrom: Option<[f32; 3]>,
+    lookat: Option<[f32; 3]>,
+    vup: Option<[f32; 3]>,
+    vfov: Option<f32>,
+    aperture: Option<f32>,
+    focal_length: Option<f32>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum BackgroundDesc {
+    Solid {
+        color: [f32; 3],
+    },
+    Gradient {
+        bottom: [f32; 3],
+        top: [f32; 3],
+    },
+    /// Equirectangular `.hdr` or `.exr` image
+    Environment {
+        file: FileRef,
+        /// Rotation in degrees about the vertical axis
+        #[serde(default)]
+        rotation: f32,
+        #[serde(default = "one")]
+        intensity: f32,
+    },
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum MaterialDesc {
+    Lambertian {
+        albedo: TextureDesc,
+    },
+    Metal {
+        albedo: TextureDesc,
+        #[serde(default)]
+        fuzz: ScalarDesc,
+    },
+    Dielectric {
+        #[serde(default = "white_texture")]
+        albedo: TextureDesc,
+        refraction_index: f32,
+    },
+    Emissive {
+        #[serde(default = "white_texture")]
+        color: TextureDesc,
+        #[serde(default = "one")]
+        strength: f32,
+    },
+    Phong {
+        albedo: TextureDesc,
+        #[serde(default = "default_ambient")]
+        ambient: f32,
+        #[serde(default = "default_diffuse")]
+        diffuse: f32,
+        #[serde(default = "default_specular")]
+        specular: f32,
+        #[serde(default = "default_shininess")]
+        shininess: f32,
+    },
+    Principled {
+        #[serde(default = "default_base_color")]
+        base_color: TextureDesc,
+        #[serde(default)]
+        metallic: ScalarDesc,
+        #[serde(default = "default_roughness")]
+        roughness: ScalarDesc,
+        #[serde(default = "default_reflectance")]
+        specular: f32,
+        #[serde(default)]
+        sheen: f32,
+        #[serde(default)]
+        clearcoat: f32,
+        #[serde(default)]
+        transmission: f32,
+        #[serde(default = "default_ior")]
+        ior: f32,
+    },
+    NormalMapped {
+        material: Box<MaterialDesc>,
+        normal_map: TextureDesc,
+        #[serde(default = "one")]
+        strength: f32,
+    },
+    BumpMapped {
+        material: Box<MaterialDesc>,
+        bump_map: ScalarDesc,
+        scale: f32,
+    },
+}
+
+/// Color written as a plain `[r, g, b]` array or as a texture table
+#[derive(Serialize)]
+#[serde(untagged)]
+enum TextureDesc {
+    Constant([f32; 3]),
+    Pattern(Box<PatternDesc>),
+}
+
+/// Number written as is or as a texture table, whose channels are averaged
+#[derive(Serialize)]
+#[serde(untagged)]
+enum ScalarDesc {
+    Constant(f32),
+    Pattern(Box<PatternDesc>),
+}
+
+impl Default for ScalarDesc {
+    fn default() -> Self {
+        ScalarDesc::Constant(0.0)
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum PatternDesc {
+    Image {
+        file: FileRef,
+        #[serde(default = "default_wrap")]
+        wrap: WrapMode,
+        #[serde(default = "default_color_space")]
+        color_space: ColorSpace,
+    },
+    Checker {
+        even: TextureDesc,
+        odd: TextureDesc,
+        /// Squares per unit of `u` and `v`
+        #[serde(default = "one")]
+        scale: f32,
+    },
+    Noise {
+        #[serde(default = "black")]
+        low: [f32; 3],
+        #[serde(default = "white")]
+        high: [f32; 3],
+        #[serde(default = "one")]
+        scale: f32,
+        #[serde(default = "default_octaves")]
+        octaves: u32,
+    },
+    Marble {
+        #[serde(default = "white")]
+        base: [f32; 3],
+        #[serde(default = "black")]
+        vein: [f32; 3],
+        #[serde(default = "one")]
+        scale: f32,
+        #[serde(default = "default_turbulence")]
+        turbulence: f32,
+        #[serde(default = "default_octaves")]
+        octaves: u32,
+    },
+    Gradient {
+        start: [f32; 3],
+        end: [f32; 3],
+        #[serde(default = "default_axis")]
+        axis: GradientAxis,
+    },
+}
+
+// Plain values and texture tables are told apart by their shape, which keeps the errors
+// about a malformed texture table intact, unlike an untagged enum
+impl<'de> Deserialize<'de> for TextureDesc {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct TextureVisitor;
+
+        impl<'de> Visitor<'de> for TextureVisitor {
+            type Value = TextureDesc;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "an `[r, g, b]` color or a texture table")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<TextureDesc, A::Error> {
+                let mut color = [0.0; 3];
+                for (i, channel) in color.iter_mut().enumerate() {
+                    *channel = seq
+                        .next_element()?
+                        .ok_or_else(|| A::Error::invalid_length(i, &self))?;
+                }
+                if seq.next_element::<f32>()?.is_some() {
+                    return Err(A::Error::invalid_length(4, &self));
+                }
+                Ok(TextureDesc::Constant(color))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<TextureDesc, A::Error> {
+                let pattern = PatternDesc::deserialize(MapAccessDeserializer::new(map))?;
+                Ok(TextureDesc::Pattern(Box::new(pattern)))
+            }
+        }
+
+        deserializer.deserialize_any(TextureVisitor)
+    }
+}
+
+impl<'de> Deserialize<'de> for ScalarDesc {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ScalarVisitor;
+
+        impl<'de> Visitor<'de> for ScalarVisitor {
+            type Value = ScalarDesc;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a number or a texture table")
+            }
+
+            fn visit_f64<E: serde::de::Error>(self, value: f64) -> Result<ScalarDesc, E> {
+                Ok(ScalarDesc::Constant(value as f32))
+            }
+
+            fn visit_i64<E: serde::de::Error>(self, value: i64) -> Result<ScalarDesc, E> {
+                Ok(ScalarDesc::Constant(value as f32))
+            }
+
+            fn visit_u64<E: serde::de::Error>(self, value: u64) -> Result<ScalarDesc, E> {
+                Ok(ScalarDesc::Constant(value as f32))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<ScalarDesc, A::Error> {
+                let pattern = PatternDesc::deserialize(MapAccessDeserializer::new(map))?;
+                Ok(ScalarDesc::Pattern(Box::new(pattern)))
+            }
+        }
+
+        deserializer.deserialize_any(ScalarVisitor)
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct SphereDesc {
+    center: [f32; 3],
+    radius: f32,
+    material: MaterialRef,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct PlaneDesc {
+    point: [f32; 3],
+    normal: [f32; 3],
+    material: MaterialRef,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct TriangleDesc {
+    vertices: [[f32; 3]; 3],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    normals: Option<[[f32; 3]; 3]>,
+    /// Surface coordinates of the vertices
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uvs: Option<[[f32; 2]; 3]>,
+    material: MaterialRef,
+}
+
+/// Triangles added to the scene as one mesh, which `save` writes for meshes so that
+/// emissive ones reload as a single light
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct GroupDesc {
+    triangles: Vec<TriangleDesc>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct MeshDesc {
+    file: FileRef,
+    material: MaterialRef,
+    #[serde(default)]
+    translate: [f32; 3],
+    /// Rotation in degrees about the x, y and z axes
+    #[serde(default)]
+    rotate: [f32; 3],
+    #[serde(default = "one")]
+    scale: f32,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum LightDesc {
+    Point {
+        position: [f32; 3],
+        #[serde(default = "white")]
+        color: [f32; 3],
+    },
+    /// Parallelogram spanned by the full edge vectors `u` and `v`
+    Rectangle {
+        center: [f32; 3],
+        u: [f32; 3],
+        v: [f32; 3],
+        #[serde(default = "white")]
+        color: [f32; 3],
+    },
+    Disk {
+        center: [f32; 3],
+        normal: [f32; 3],
+        radius: f32,
+        #[serde(default = "white")]
+        color: [f32; 3],
+    },
+    Sphere {
+        center: [f32; 3],
+        radius: f32,
+        #[serde(default = "white")]
+        color: [f32; 3],
+    },
+}
+
+fn white() -> [f32; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+fn black() -> [f32; 3] {
+    [0.0, 0.0, 0.0]
+}
+
+fn white_texture() -> TextureDesc {
+    TextureDesc::Constant(white())
+}
+
+fn one() -> f32 {
+    1.0
+}
+
+fn default_wrap() -> WrapMode {
+    WrapMode::Repeat
+}
+
+fn default_color_space() -> ColorSpace {
+    ColorSpace::Srgb
+}
+
+fn default_octaves() -> u32 {
+    5
+}
+
+fn default_turbulence() -> f32 {
+    5.0
+}
+
+fn default_axis() -> GradientAxis {
+    GradientAxis::U
+}
+
+fn default_ambient() -> f32 {
+    0.1
+}
+
+fn default_diffuse() -> f32 {
+    0.7
+}
+
+fn default_specular() -> f32 {
+    0.3
+}
+
+fn default_shininess() -> f32 {
+    40.0
+}
+
+fn default_base_color() -> TextureDesc {
+    TextureDesc::Constant([0.8, 0.8, 0.8])
+}
+
+fn default_roughness() -> ScalarDesc {
+    ScalarDesc::Constant(0.5)
+}
+
+fn default_reflectance() -> f32 {
+    0.5
+}
+
+fn default_ior() -> f32 {
+    1.5
+}
+
+fn vec3(v: [f32; 3]) -> Vec3 {
+    Vec3::new(v[0], v[1], v[2])
+}
+
+fn array(v: Vec3) -> [f32; 3] {
+    [v.x, v.y, v.z]
+}
+
+/// Vertex surface coordinates to export, omitted when they are the defaults
+fn uvs(uvs: &[Vec2; 3]) -> Option<[[f32; 2]; 3]> {
+    let exported = uvs.map(|uv| [uv.x, uv.y]);
+    if exported == [[0.0, 0.0], [1.0, 0.0], [0.0, 1.0]] {
+        None
+    } else {
+        Some(exported)
+    }
+}
+
+/// Normalize a hand-written direction. Vectors that are already unit length are kept as
+/// they are so that exported scenes reload bit-for-bit.
+fn unit(v: [f32; 3]) -> Vec3 {
+    let v = vec3(v);
+    if (v.norm() - 1.0).abs() < 1.0e-6 {
+        v
+    } else {
+        v.normalize()
+    }
+}
+
+impl SceneDesc {
+    fn build(self, path: &Path) -> Result<LoadedScene, SceneError> {
+        let mut materials: BTreeMap<String, Material> = BTreeMap::new();
+        for (name, desc) in &self.materials {
+            let material = desc
+                .to_material()
+                .map_err(|err| SceneError::new(path, format!("material `{}`: {}", name, err)))?;
+            materials.insert(name.clone(), material);
+        }
+        let material = |name: &MaterialRef| materials[&name.0].clone();
+
+        let mut scene = Scene::new();
+
+        for sphere in &self.spheres {
+            scene.add(Sphere::new(
+                vec3(sphere.center),
+                sphere.radius,
+                material(&sphere.material),
+            ));
+        }
+
+        for plane in &self.planes {
+            scene.add(Plane::new(
+                vec3(plane.point),
+                unit(plane.normal),
+                material(&plane.material),
+            ));
+        }
+
+        for triangle in &self.triangles {
+            let [p1, p2, p3] = triangle.vertices;
+            match triangle.normals {
+                Some([n1, n2, n3]) => {
+                    let mut t = Triangle::new(
+                        vec3(p1),
+                        vec3(p2),
+                        vec3(p3),
+                        unit(n1),
+                        unit(n2),
+                        unit(n3),
+                        material(&triangle.material),
+                    );
+                    if let Some(uvs) = triangle.uvs {
+                        t = t.with_uvs(uvs.map(Vec2::from));
+                    }
+                    scene.add(t)
+                }
+                None => {
+                    let mut t = SimpleTriangle::new(
+                        vec3(p1),
+                        vec3(p2),
+                        vec3(p3),
+                        material(&triangle.material),
+                    );
+                    if let Some(uvs) = triangle.uvs {
+                        t = t.with_uvs(uvs.map(Vec2::from));
+                    }
+                    scene.add(t)
+                }
+            }
+        }
+
+        for mesh in &self.meshes {
+            let file = mesh.file.0.to_string_lossy();
+            let mut triangles = TriangleMesh::load(&file, material(&mesh.material))
+                .map_err(|err| SceneError::new(path, err))?;
+            triangles.transform(&mesh.matrix());
+            scene.add(triangles);
+        }
+
+        for group in &self.groups {
+            let triangles = group
+                .triangles
+                .iter()
+                .map(|triangle| {
+                    let [p1, p2, p3] = triangle.vertices.map(vec3);
+                    // Flat shading unless the vertices have normals
+                    let face = (p2 - p1).cross(&(p3 - p1)).normalize();
+                    let [n1, n2, n3] = triangle.normals.map_or([face; 3], |n| n.map(unit));
+                    let t = Triangle::new(p1, p2, p3, n1, n2, n3, material(&triangle.material));
+                    match triangle.uvs {
+                        Some(uvs) => t.with_uvs(uvs.map(Vec2::from)),
+                        None => t,
+                    }
+                })
+                .collect();
+            scene.add(TriangleMesh::new(triangles));
+        }
+
+        for light in &self.lights {
+            scene.add_light(light.to_light());
+        }
+
+        if let Some(background) = &self.background {
+            scene.set_background(background.to_background(path)?);
+        }
+
+        let camera = match self.camera {
+            Some(desc) => {
+                let settings = desc.to_settings();
+                settings
+                    .validate()
+                    .map_err(|err| SceneError::new(path, format!("invalid camera: {}", err)))?;
+                Some(settings)
+            }
+            None => None,
+        };
+
+        Ok(LoadedScene { scene, camera })
+    }
+
+    fn from_scene(scene: &Scene, camera: Option<&CameraSettings>) -> SceneDesc {
+        let mut materials = MaterialNames::default();
+        let mut spheres = Vec::new();
+        let mut planes = Vec::new();
+        let mut triangles = Vec::new();
+        let mut groups = Vec::new();
+
+        for group in scene.groups() {
+            // Objects made of several triangles, such as meshes, stay together
+            if group.len() > 1 && group.iter().all(|p| matches!(p, Primitive::Triangle(_))) {
+                let triangles = group
+                    .into_iter()
+                    .filter_map(|primitive| match primitive {
+                        Primitive::Triangle(triangle) => Some(TriangleDesc {
+                            vertices: [array(triangle.p1), array(triangle.p2), array(triangle.p3)],
+                            normals: Some([
+                                array(triangle.n1),
+                                array(triangle.n2),
+                                array(triangle.n3),
+                            ]),
+                            uvs: uvs(&triangle.uvs),
+                            material: materials.name(&triangle.material),
+                        }),
+                        _ => None,
+                    })
+                    .collect();
+                groups.push(GroupDesc { triangles });
+                continue;
+            }
+
+            for primitive in group {
+                match primitive {
+                    Primitive::Sphere(sphere) => spheres.push(SphereDesc {
+                        center: array(sphere.center),
+                        radius: sphere.radius,
+                        material: materials.name(&sphere.material),
+                    }),
+                    Primitive::Plane(plane) => planes.push(PlaneDesc {
+                        point: array(plane.point),
+                        normal: array(plane.normal),
+                        material: materials.name(&plane.material),
+                    }),
+                    Primitive::SimpleTriangle(triangle) => triangles.push(TriangleDesc {
+                        vertices: [array(triangle.p1), array(triangle.p2), array(triangle.p3)],
+                        normals: None,
+                        uvs: uvs(&triangle.uvs),
+                        material: materials.name(&triangle.material),
+                    }),
+                    Primitive::Triangle(triangle) => triangles.push(TriangleDesc {
+                        vertices: [array(triangle.p1), array(triangle.p2), array(triangle.p3)],
+                        normals: Some([array(triangle.n1), array(triangle.n2), array(triangle.n3)]),
+                        uvs: uvs(&triangle.uvs),
+                        material: materials.name(&triangle.material),
+                    }),
+                }
+            }
+        }
+
+        let lights = scene
+            .lights
+            .iter()
+            .filter_map(LightDesc::from_light)
+            .collect();
+
+        SceneDesc {
+            version: Version,
+            camera: camera.map(CameraDesc::from_settings),
+            background: Some(BackgroundDesc::from_background(scene.background())),
+            materials: materials.table,
+            spheres,
+            planes,
+            triangles,
+            meshes: Vec::new(),
+            groups,
+            lights,
+        }
+    }
+}
+
+/// Assigns names to the distinct materials of a scene being exported
+#[derive(Default)]
+struct MaterialNames {
+    table: BTreeMap<String, MaterialDesc>,
+    /// Materials are deduplicated by their serialized form
+    names: HashMap<String, String>,
+}
+
+impl MaterialNames {
+    fn name(&mut self, material: &Material) -> MaterialRef {
+        let desc = MaterialDesc::from_material(material);
+        let key = serde_json::to_string(&desc).unwrap();
+        let next = self.names.len();
+        let name = self
+            .names
+            .entry(key)
+            .or_insert_with(|| format!("material_{}", next))
+            .clone();
+        self.table.entry(name.clone()).or_insert(desc);
+        MaterialRef(name)
+    }
+}
+
+impl MaterialDesc {
+    fn from_material(material: &Material) -> MaterialDesc {
+        match material {
+            Material::Lambertian { albedo } => MaterialDesc::Lambertian {
+                albedo: TextureDesc::from_texture(albedo),
+            },
+            Material::Metal { albedo, fuzz } => MaterialDesc::Metal {
+                albedo: TextureDesc::from_texture(albedo),
+                fuzz: ScalarDesc::from_texture(fuzz),
+            },
+            Material::Dielectric {
+                albedo,
+                refraction_index,
+            } => MaterialDesc::Dielectric {
+                albedo: TextureDesc::from_texture(albedo),
+                refraction_index: *refraction_index,
+            },
+            Material::Emissive { color, strength } => MaterialDesc::Emissive {
+                color: TextureDesc::from_texture(color),
+                strength: *strength,
+            },
+            Material::Phong {
+                albedo,
+                ambient,
+                diffuse,
+                specular,
+                shininess,
+            } => MaterialDesc::Phong {
+                albedo: TextureDesc::from_texture(albedo),
+                ambient: *ambient,
+                diffuse: *diffuse,
+                specular: *specular,
+                shininess: *shininess,
+            },
+            Material::Principled {
+                base_color,
+                metallic,
+                roughness,
+                specular,
+                sheen,
+                clearcoat,
+                transmission,
+                ior,
+            } => MaterialDesc::Principled {
+                base_color: TextureDesc::from_texture(base_color),
+                metallic: ScalarDesc::from_texture(metallic),
+                roughness: ScalarDesc::from_texture(roughness),
+                specular: *specular,
+                sheen: *sheen,
+                clearcoat: *clearcoat,
+                transmission: *transmission,
+                ior: *ior,
+            },
+            Material::NormalMapped {
+                material,
+                normal_map,
+                strength,
+            } => MaterialDesc::NormalMapped {
+                material: Box::new(MaterialDesc::from_material(material)),
+                normal_map: TextureDesc::from_texture(normal_map),
+                strength: *strength,
+            },
+            Material::BumpMapped {
+                material,
+                bump_map,
+                scale,
+            } => MaterialDesc::BumpMapped {
+                material: Box::new(MaterialDesc::from_material(material)),
+                bump_map: ScalarDesc::from_texture(bump_map),
+                scale: *scale,
+            },
+        }
+    }
+
+    fn to_material(&self) -> Result<Material, String> {
+        Ok(match self {
+            MaterialDesc::Lambertian { albedo } => Material::Lambertian {
+                albedo: albedo.to_texture()?,
+            },
+            MaterialDesc::Metal { albedo, fuzz } => Material::Metal {
+                albedo: albedo.to_texture()?,
+                fuzz: fuzz.to_texture()?,
+            },
+            MaterialDesc::Dielectric {
+                albedo,
+                refraction_index,
+            } => Material::Dielectric {
+                albedo: albedo.to_texture()?,
+                refraction_index: *refraction_index,
+            },
+            MaterialDesc::Emissive { color, strength } => Material::Emissive {
+                color: color.to_texture()?,
+                strength: *strength,
+            },
+            MaterialDesc::Phong {
+                albedo,
+                ambient,
+                diffuse,
+                specular,
+                shininess,
+            } => Material::Phong {
+                albedo: albedo.to_texture()?,
+                ambient: *ambient,
+                diffuse: *diffuse,
+                specular: *specular,
+                shininess: *shininess,
+            },
+            MaterialDesc::Principled {
+                base_color,
+                metallic,
+                roughness,
+                specular,
+                sheen,
+                clearcoat,
+                transmission,
+                ior,
+            } => Material::Principled {
+                base_color: base_color.to_texture()?,
+                metallic: metallic.to_texture()?,
+                roughness: roughness.to_texture()?,
+                specular: *specular,
+                sheen: *sheen,
+                clearcoat: *clearcoat,
+                transmission: *transmission,
+                ior: *ior,
+            },
+            MaterialDesc::NormalMapped {
+                material,
+                normal_map,
+                strength,
+            } => Material::NormalMapped {
+                material: Box::new(material.to_material()?),
+                normal_map: normal_map.to_texture()?,
+                strength: *strength,
+            },
+            MaterialDesc::BumpMapped {
+                material,
+                bump_map,
+                scale,
+            } => Material::BumpMapped {
+                material: Box::new(material.to_material()?),
+                bump_map: bump_map.to_texture()?,
+                scale: *scale,
+            },
+        })
+    }
+}
+
+impl TextureDesc {
+    fn from_texture(texture: &Arc<dyn Texture>) -> TextureDesc {
+        match texture.kind() {
+            TextureKind::Constant(constant) => TextureDesc::Constant(array(constant.color)),
+            kind => TextureDesc::Pattern(Box::new(PatternDesc::from_kind(kind))),
+        }
+    }
+
+    fn to_texture(&self) -> Result<Arc<dyn Texture>, String> {
+        match self {
+            TextureDesc::Constant(color) => Ok(constant(vec3(*color))),
+            TextureDesc::Pattern(pattern) => pattern.to_texture(),
+        }
+    }
+}
+
+impl ScalarDesc {
+    fn from_texture(texture: &Arc<dyn Texture>) -> ScalarDesc {
+        match texture.kind() {
+            // Only the average of the channels is ever used. Grey values are written as they
+            // are, since averaging them in `f32` can round off the last digit.
+            TextureKind::Constant(constant) => {
+                let c = constant.color;
+                if c.x == c.y && c.y == c.z {
+                    ScalarDesc::Constant(c.x)
+                } else {
+                    ScalarDesc::Constant((c.x + c.y + c.z) / 3.0)
+                }
+            }
+            kind => ScalarDesc::Pattern(Box::new(PatternDesc::from_kind(kind))),
+        }
+    }
+
+    fn to_texture(&self) -> Result<Arc<dyn Texture>, String> {
+        match self {
+            ScalarDesc::Constant(value) => Ok(constant(Color::repeat(*value))),
+            ScalarDesc::Pattern(pattern) => pattern.to_texture(),
+        }
+    }
+}
+
+impl PatternDesc {
+    fn from_kind(kind: TextureKind) -> PatternDesc {
+        match kind {
+            TextureKind::Constant(_) => unreachable!("constant textures are written as values"),
+            // Written as an absolute path so that the scene can be saved anywhere
+            TextureKind::Image(image) => PatternDesc::Image {
+                file: FileRef(
+                    image
+                        .path()
+                        .canonicalize()
+                        .unwrap_or_else(|_| image.path().to_path_buf()),
+                ),
+                wrap: image.wrap,
+                color_space: image.color_space(),
+            },
+            TextureKind::Checker(checker) => PatternDesc::Checker {
+                even: TextureDesc::from_texture(&checker.even),
+                odd: TextureDesc::from_texture(&checker.odd),
+                scale: checker.scale,
+            },
+            TextureKind::Noise(noise) => PatternDesc::Noise {
+                low: array(noise.low),
+                high: array(noise.high),
+                scale: noise.scale,
+                octaves: noise.octaves,
+            },
+            TextureKind::Marble(marble) => PatternDesc::Marble {
+                base: array(marble.base),
+                vein: array(marble.vein),
+                scale: marble.scale,
+                turbulence: marble.turbulence,
+                octaves: marble.octaves,
+            },
+            TextureKind::Gradient(gradient) => PatternDesc::Gradient {
+                start: array(gradient.start),
+                end: array(gradient.end),
+                axis: gradient.axis,
+            },
+        }
+    }
+
+    fn to_texture(&self) -> Result<Arc<dyn Texture>, String> {
+        Ok(match self {
+            PatternDesc::Image {
+                file,
+                wrap,
+                color_space,
+            } => Arc::new(ImageTexture::load(&file.0, *wrap, *color_space)?),
+            PatternDesc::Checker { even, odd, scale } => Arc::new(CheckerTexture::new(
+                even.to_texture()?,
+                odd.to_texture()?,
+                *scale,
+            )),
+            PatternDesc::Noise {
+                low,
+                high,
+                scale,
+                octaves,
+            } => Arc::new(NoiseTexture::new(vec3(*low), vec3(*high), *scale, *octaves)),
+            PatternDesc::Marble {
+                base,
+                vein,
+                scale,
+                turbulence,
+                octaves,
+            } => Arc::new(MarbleTexture::new(
+                vec3(*base),
+                vec3(*vein),
+                *scale,
+                *turbulence,
+                *octaves,
+            )),
+            PatternDesc::Gradient { start, end, axis } => {
+                Arc::new(GradientTexture::new(vec3(*start), vec3(*end), *axis))
+            }
+        })
+    }
+}
+
+impl LightDesc {
+    /// Description of a light, or `None` for lights derived from emissive geometry, which
+    /// is recreated when the geometry is loaded
+    fn from_light(light: &Light) -> Option<LightDesc> {
+        let desc = match light {
+            Light::Point(light) => LightDesc::Point {
+                position: array(light.position),
+                color: array(light.color),
+            },
+            Light::Area(light) => {
+                let color = array(light.color);
+                match light.shape {
+                    AreaShape::Rectangle { center, u, v } => LightDesc::Rectangle {
+                        center: array(center),
+                        u: array(u),
+                        v: array(v),
+                        color,
+                    },
+                    AreaShape::Disk {
+                        center,
+                        normal,
+                        radius,
+                    } => LightDesc::Disk {
+                        center: array(center),
+                        normal: array(normal),
+                        radius,
+                        color,
+                    },
+                    AreaShape::Sphere { center, radius } => LightDesc::Sphere {
+                        center: array(center),
+                        radius,
+                        color,
+                    },
+                }
+            }
+            Light::Mesh(_) => return None,
+        };
+        Some(desc)
+    }
+
+    fn to_light(&self) -> Light {
+        match *self {
+            LightDesc::Point { position, color } => {
+                PointLight::new(vec3(position), vec3(color)).into()
+            }
+            LightDesc::Rectangle {
+                center,
+                u,
+                v,
+                color,
+            } => AreaLight::rectangle(vec3(center), vec3(u), vec3(v), vec3(color)).into(),
+            LightDesc::Disk {
+                center,
+                normal,
+                radius,
+                color,
+            } => AreaLight::new(
+                AreaShape::Disk {
+                    center: vec3(center),
+                    normal: unit(normal),
+                    radius,
+                },
+                vec3(color),
+            )
+            .into(),
+            LightDesc::Sphere {
+                center,
+                radius,
+                color,
+            } => AreaLight::sphere(vec3(center), radius, vec3(color)).into(),
+        }
+    }
+}
+
+impl BackgroundDesc {
+    fn from_background(background: &Background) -> BackgroundDesc {
+        match background {
+            Background::Solid(color) => BackgroundDesc::Solid {
+                color: array(*color),
+            },
+            Background::Gradient { bottom, top } => BackgroundDesc::Gradient {
+                bottom: array(*bottom),
+                top: array(*top),
+            },
+            // Written as an absolute path so that the scene can be saved anywhere
+            Background::Environment(map) => BackgroundDesc::Environment {
+                file: FileRef(
+                    map.path()
+                        .canonicalize()
+                        .unwrap_or_else(|_| map.path().to_path_buf()),
+                ),
+                rotation: map.rotation,
+                intensity: map.intensity,
+            },
+        }
+    }
+
+    fn to_background(&self, path: &Path) -> Result<Background, SceneError> {
+        Ok(match self {
+            BackgroundDesc::Solid { color } => Background::Solid(vec3(*color)),
+            BackgroundDesc::Gradient { bottom, top } => Background::Gradient {
+                bottom: vec3(*bottom),
+                top: vec3(*top),
+            },
+            BackgroundDesc::Environment {
+                file,
+                rotation,
+                intensity,
+            } => Background::Environment(
+                EnvironmentMap::load(&file.0, *rotation, *intensity)
+                    .map_err(|err| SceneError::new(path, err))?,
+            ),
+        })
+    }
+}
+
+impl MeshDesc {
+    /// Scale, then rotate, then translate
+    fn matrix(&self) -> Matrix4<f32> {
+        let [rx, ry, rz] = self.rotate;
+        let rotation =
+            Rotation3::from_euler_angles(rx.to_radians(), ry.to_radians(), rz.to_radians());
+        Matrix4::new_translation(&vec3(self.translate))
+            * rotation.to_homogeneous()
+            * Matrix4::new_scaling(self.scale)
+    }
+}
+
+impl CameraDesc {
+    fn from_settings(settings: &CameraSettings) -> CameraDesc {
+        CameraDesc {
+            projection: Some(settings.projection),
+            lookfrom: Some(array(settings.lookfrom)),
+            lookat: Some(array(settings.lookat)),
+            vup: Some(array(settings.vup)),
+            vfov: Some(settings.vfov),
+            aperture: Some(settings.aperture),
+            focal_length: Some(settings.focal_length),
+        }
+    }
+
+    fn to_settings(&self) -> CameraSettings {
+        let mut settings = CameraSettings::default();
+        if let Some(projection) = self.projection {
+            settings.projection = projection;
+        }
+        if let Some(lookfrom) = self.lookfrom {
+            settings.lookfrom = vec3(lookfrom);
+        }
+        if let Some(lookat) = self.lookat {
+            settings.lookat = vec3(lookat);
+        }
+        if let Some(vup) = self.vup {
+            settings.vup = vec3(vup);
+        }
+        if let Some(vfov) = self.vfov {
+            settings.vfov = vfov;
+        }
+        if let Some(aperture) = self.aperture {
+            settings.aperture = aperture;
+        }
+        if let Some(focal_length) = self.focal_length {
+            settings.focal_length = focal_length;
+        }
+        settings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::examples::{self, random_scene};
+    use crate::film::Film;
+    use crate::integrator::Integrator;
+    use crate::render::{Renderer, Settings};
+    use crate::rng::seeded_rng;
+
+    /// Path in the temporary directory that no other test uses
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("traci_{}_{}", std::process::id(), name))
+    }
+
+    fn render(scene: &Scene, camera: &CameraSettings) -> Film {
+        let settings = Settings {
+            width: 24,
+            height: 16,
+            samples_per_pixel: 4,
+            integrator: Integrator::Path,
+            seed: 7,
+            ..Settings::default()
+        };
+        let camera = camera.build(settings.width as f32 / settings.height as f32);
+        Renderer::new().render_film(scene, &camera, &settings)
+    }
+
+    fn colors(film: &Film) -> Vec<Color> {
+        (0..film.height())
+            .flat_map(|y| (0..film.width()).map(move |x| (x, y)))
+            .map(|(x, y)| film.pixel(x, y).color())
+            .collect()
+    }
+
+    /// Square lamp made of two triangles above a ground sphere
+    fn emissive_mesh_scene() -> (Scene, CameraSettings) {
+        let mut scene = Scene::new();
+        scene.add(Sphere::new(
+            Point3::new(0.0, -100.5, -1.0),
+            100.0,
+            Material::Lambertian {
+                albedo: constant(Color::new(0.5, 0.5, 0.5)),
+            },
+        ));
+        let lamp = Material::Emissive {
+            color: constant(Color::new(1.0, 0.9, 0.8)),
+            strength: 4.0,
+        };
+        let down = Vec3::new(0.0, -1.0, 0.0);
+        let corners = [
+            Point3::new(-0.5, 1.0, -1.5),
+            Point3::new(0.5, 1.0, -1.5),
+            Point3::new(0.5, 1.0, -0.5),
+            Point3::new(-0.5, 1.0, -0.5),
+        ];
+        scene.add(TriangleMesh::new(vec![
+            Triangle::new(
+                corners[0],
+                corners[1],
+                corners[2],
+                down,
+                down,
+                down,
+                lamp.clone(),
+            ),
+            Triangle::new(corners[0], corners[2], corners[3], down, down, down, lamp),
+        ]));
+        let camera = CameraSettings {
+            lookfrom: Point3::new(0.0, 0.5, 2.0),
+            lookat: Point3::new(0.0, 0.0, -1.0),
+            aperture: 0.0,
+            ..CameraSettings::default()
+        };
+        (scene, camera)
+    }
+
+    #[test]
+    fn emissive_mesh_reloads_as_one_light() {
+        let (scene, camera) = emissive_mesh_scene();
+        assert_eq!(scene.lights.len(), 1);
+
+        let path = temp_path("emissive_mesh.toml");
+        save(&scene, Some(&camera), &path).unwrap();
+        let loaded = load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.scene.lights.len(), scene.lights.len());
+        assert_eq!(loaded.scene.len(), scene.len());
+        assert_eq!(
+            colors(&render(&loaded.scene, &loaded.camera.unwrap())),
+            colors(&render(&scene, &camera))
+        );
+    }
+
+    fn describe_primitive(primitive: &Primitive) -> String {
+        match primitive {
+            Primitive::Sphere(s) => format!("sphere {:?} {} {:?}", s.center, s.radius, s.material),
+            Primitive::Plane(p) => format!("plane {:?} {:?} {:?}", p.point, p.normal, p.material),
+            Primitive::SimpleTriangle(t) => format!(
+                "triangle {:?} {:?} {:?} {:?} {:?}",
+                t.p1, t.p2, t.p3, t.uvs, t.material
+            ),
+            Primitive::Triangle(t) => format!(
+                "triangle {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?}",
+                t.p1, t.p2, t.p3, t.n1, t.n2, t.n3, t.uvs, t.material
+            ),
+        }
+    }
+
+    /// Everything `save` writes about a scene, in a form that can be compared
+    fn describe(scene: &Scene) -> (Vec<String>, Vec<String>, String) {
+        let primitives = scene.primitives().iter().map(describe_primitive).collect();
+        let lights = scene
+            .lights
+            .iter()
+            .map(|light| serde_json::to_string(&LightDesc::from_light(light)).unwrap())
+            .collect();
+        let background =
+            serde_json::to_string(&BackgroundDesc::from_background(scene.background())).unwrap();
+        (primitives, lights, background)
+    }
+
+    #[test]
+    fn random_scene_round_trips() {
+        let mut scene = random_scene(20, &mut seeded_rng(3));
+        scene.add_light(examples::default_light());
+        scene.add_light(AreaLight::sphere(
+            Point3::new(0.0, 4.0, 0.0),
+            0.5,
+            Color::new(4.0, 4.0, 3.0),
+        ));
+        let camera = CameraSettings {
+            lookfrom: Point3::new(13.0, 2.0, 3.0),
+            vfov: 20.0,
+            ..CameraSettings::default()
+        };
+
+        for extension in ["toml", "json"] {
+            let path = temp_path(&format!("random.{}", extension));
+            save(&scene, Some(&camera), &path).unwrap();
+            let loaded = load(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+
+            assert_eq!(describe(&loaded.scene), describe(&scene), "{}", extension);
+            assert_eq!(
+                format!("{:?}", loaded.camera.unwrap()),
+                format!("{:?}", camera),
+                "{}",
+                extension
+            );
+        }
+    }
+
+    #[test]
+    fn image_files_are_found_next_to_the_scene_file() {
+        // The tests run from the crate root, so the scene directory is not the current one
+        let dir = temp_path("textured");
+        std::fs::create_dir_all(&dir).unwrap();
+        image::RgbImage::from_pixel(2, 2, image::Rgb([255, 255, 255]))
+            .save(dir.join("white.png"))
+            .unwrap();
+        let path = dir.join("tex.toml");
+        std::fs::write(
+            &path,
+            r#"
+version = 1
+
+[materials.painted]
+type = "lambertian"
+albedo = { type = "image", file = "white.png" }
+
+[[spheres]]
+center = [0.0, 0.0, -1.0]
+radius = 0.5
+material = "painted"
+"#,
+        )
+        .unwrap();
+
+        let loaded = load(&path);
+        std::fs::remove_dir_all(&dir).unwrap();
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.scene.primitives().len(), 1);
+    }
+}
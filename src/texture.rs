@@ -0,0 +1,503 @@
+//! Colors that vary over a surface.
+//!
+//! Materials look their parameters up in textures at every hit, using the surface
+//! coordinates `uv` of the hit and its position. Image, checker and gradient textures are
+//! laid out in `uv` space, while the noise based ones are solid textures defined
+//! throughout space, so they need no surface parameterization at all.
+
+use crate::background::{read_exr, read_hdr};
+use crate::vec3::*;
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+pub trait Texture: fmt::Debug + Send + Sync {
+    /// Color at surface coordinates `uv` of the point `point`
+    fn value(&self, uv: Vec2, point: Point3) -> Color;
+
+    /// Plain description of the texture, used to inspect and export scenes
+    fn kind(&self) -> TextureKind;
+
+    /// Average of the channels, for textures that drive a single parameter such as the
+    /// fuzz of a metal
+    fn scalar(&self, uv: Vec2, point: Point3) -> f32 {
+        let color = self.value(uv, point);
+        (color.x + color.y + color.z) / 3.0
+    }
+}
+
+/// Texture with the same color everywhere
+pub fn constant(color: Color) -> Arc<dyn Texture> {
+    Arc::new(ConstantTexture::new(color))
+}
+
+/// Texture with the same value in every channel everywhere
+pub fn constant_scalar(value: f32) -> Arc<dyn Texture> {
+    constant(Color::repeat(value))
+}
+
+/// Plain description of a texture
+#[derive(Clone, Debug)]
+pub enum TextureKind {
+    Constant(ConstantTexture),
+    Image(ImageTexture),
+    Checker(CheckerTexture),
+    Noise(NoiseTexture),
+    Marble(MarbleTexture),
+    Gradient(GradientTexture),
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct ConstantTexture {
+    pub color: Color,
+}
+
+impl ConstantTexture {
+    pub fn new(color: Color) -> ConstantTexture {
+        ConstantTexture { color }
+    }
+}
+
+impl Texture for ConstantTexture {
+    fn value(&self, _uv: Vec2, _point: Point3) -> Color {
+        self.color
+    }
+
+    fn kind(&self) -> TextureKind {
+        TextureKind::Constant(*self)
+    }
+}
+
+/// How image lookups outside of `[0, 1]` are brought back into the image
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WrapMode {
+    /// Tile the image
+    Repeat,
+    /// Tile the image, flipping every other copy so that the edges line up
+    Mirror,
+    /// Extend the border pixels
+    Clamp,
+}
+
+impl WrapMode {
+    /// Pixel index for the possibly out of range index `i` of an axis with `size` pixels
+    fn apply(self, i: i64, size: usize) -> usize {
+        let size = size as i64;
+        let wrapped = match self {
+            WrapMode::Repeat => i.rem_euclid(size),
+            WrapMode::Mirror => {
+                let period = i.rem_euclid(2 * size);
+                if period < size {
+                    period
+                } else {
+                    2 * size - 1 - period
+                }
+            }
+            WrapMode::Clamp => i.clamp(0, size - 1),
+        };
+        wrapped as usize
+    }
+}
+
+/// How the pixel values of 8 and 16-bit images are encoded
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorSpace {
+    /// Colors with the sRGB transfer curve applied, as images meant for display are stored
+    Srgb,
+    /// Values stored as they are, as in normal and height maps
+    Linear,
+}
+
+/// Image looked up with bilinear filtering, with `v = 0` at the bottom of the image
+#[derive(Clone)]
+pub struct ImageTexture {
+    path: PathBuf,
+    pub wrap: WrapMode,
+    color_space: ColorSpace,
+    width: usize,
+    height: usize,
+    /// Linear colors row by row, starting at the top
+    pixels: Arc<[Color]>,
+}
+
+impl ImageTexture {
+    /// Load an image. `.hdr` and `.exr` files always hold linear colors, while every other
+    /// format is decoded according to `color_space`.
+    pub fn load(
+        path: &Path,
+        wrap: WrapMode,
+        color_space: ColorSpace,
+    ) -> Result<ImageTexture, String> {
+        let (width, height, pixels) = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("hdr") => read_hdr(path),
+            Some("exr") => read_exr(path),
+            _ => read_ldr(path, color_space),
+        }
+        .map_err(|err| format!("{}: {}", path.display(), err))?;
+
+        if width == 0 || height == 0 {
+            return Err(format!("{}: image is empty", path.display()));
+        }
+
+        Ok(ImageTexture {
+            path: path.to_path_buf(),
+            wrap,
+            color_space,
+            width,
+            height,
+            pixels: pixels.into(),
+        })
+    }
+
+    /// File the image was loaded from
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn color_space(&self) -> ColorSpace {
+        self.color_space
+    }
+
+    fn texel(&self, x: i64, y: i64) -> Color {
+        let x = self.wrap.apply(x, self.width);
+        let y = self.wrap.apply(y, self.height);
+        self.pixels[y * self.width + x]
+    }
+}
+
+impl fmt::Debug for ImageTexture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ImageTexture")
+            .field("path", &self.path)
+            .field("wrap", &self.wrap)
+            .field("color_space", &self.color_space)
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .finish()
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, uv: Vec2, _point: Point3) -> Color {
+        // Pixel centers sit at half integer coordinates
+        let x = uv.x * self.width as f32 - 0.5;
+        let y = (1.0 - uv.y) * self.height as f32 - 0.5;
+        if !(x.is_finite() && y.is_finite()) {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let top = (1.0 - fx) * self.texel(x0, y0) + fx * self.texel(x0 + 1, y0);
+        let bottom = (1.0 - fx) * self.texel(x0, y0 + 1) + fx * self.texel(x0 + 1, y0 + 1);
+        (1.0 - fy) * top + fy * bottom
+    }
+
+    fn kind(&self) -> TextureKind {
+        TextureKind::Image(self.clone())
+    }
+}
+
+/// Alternating squares of two textures, `scale` squares per unit of `u` and `v`
+#[derive(Clone, Debug)]
+pub struct CheckerTexture {
+    pub even: Arc<dyn Texture>,
+    pub odd: Arc<dyn Texture>,
+    pub scale: f32,
+}
+
+impl CheckerTexture {
+    pub fn new(even: Arc<dyn Texture>, odd: Arc<dyn Texture>, scale: f32) -> CheckerTexture {
+        CheckerTexture { even, odd, scale }
+    }
+}
+
+impl Texture for CheckerTexture {
+    fn value(&self, uv: Vec2, point: Point3) -> Color {
+        let square = (uv.x * self.scale).floor() + (uv.y * self.scale).floor();
+        if square.rem_euclid(2.0) < 1.0 {
+            self.even.value(uv, point)
+        } else {
+            self.odd.value(uv, point)
+        }
+    }
+
+    fn kind(&self) -> TextureKind {
+        TextureKind::Checker(self.clone())
+    }
+}
+
+/// Fractal Brownian motion: Perlin noise summed over octaves of doubling frequency, used
+/// to blend between two colors
+#[derive(Copy, Clone, Debug)]
+pub struct NoiseTexture {
+    pub low: Color,
+    pub high: Color,
+    /// Frequency of the first octave, in features per unit of distance
+    pub scale: f32,
+    pub octaves: u32,
+}
+
+impl NoiseTexture {
+    pub fn new(low: Color, high: Color, scale: f32, octaves: u32) -> NoiseTexture {
+        NoiseTexture {
+            low,
+            high,
+            scale,
+            octaves,
+        }
+    }
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, _uv: Vec2, point: Point3) -> Color {
+        let t = 0.5 * (1.0 + fbm(self.scale * point, self.octaves));
+        lerp(self.low, self.high, t.clamp(0.0, 1.0))
+    }
+
+    fn kind(&self) -> TextureKind {
+        TextureKind::Noise(*self)
+    }
+}
+
+/// Bands of `vein` on `base` running across the x axis, bent by turbulence
+#[derive(Copy, Clone, Debug)]
+pub struct MarbleTexture {
+    pub base: Color,
+    pub vein: Color,
+    /// Frequency of the bands, in radians per unit of distance
+    pub scale: f32,
+    /// How far the turbulence bends the bands
+    pub turbulence: f32,
+    pub octaves: u32,
+}
+
+impl MarbleTexture {
+    pub fn new(
+        base: Color,
+        vein: Color,
+        scale: f32,
+        turbulence: f32,
+        octaves: u32,
+    ) -> MarbleTexture {
+        MarbleTexture {
+            base,
+            vein,
+            scale,
+            turbulence,
+            octaves,
+        }
+    }
+}
+
+impl Texture for MarbleTexture {
+    fn value(&self, _uv: Vec2, point: Point3) -> Color {
+        let p = self.scale * point;
+        let phase = p.x + self.turbulence * turbulence(p, self.octaves);
+        // Sharpened so that the veins are thin lines rather than broad stripes
+        let t = (0.5 * (1.0 + phase.sin())).powi(4);
+        lerp(self.base, self.vein, t)
+    }
+
+    fn kind(&self) -> TextureKind {
+        TextureKind::Marble(*self)
+    }
+}
+
+/// Surface coordinate a gradient runs along
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GradientAxis {
+    U,
+    V,
+}
+
+/// Linear blend from `start` at zero to `end` at one along a surface coordinate
+#[derive(Copy, Clone, Debug)]
+pub struct GradientTexture {
+    pub start: Color,
+    pub end: Color,
+    pub axis: GradientAxis,
+}
+
+impl GradientTexture {
+    pub fn new(start: Color, end: Color, axis: GradientAxis) -> GradientTexture {
+        GradientTexture { start, end, axis }
+    }
+}
+
+impl Texture for GradientTexture {
+    fn value(&self, uv: Vec2, _point: Point3) -> Color {
+        let t = match self.axis {
+            GradientAxis::U => uv.x,
+            GradientAxis::V => uv.y,
+        };
+        lerp(self.start, self.end, t.clamp(0.0, 1.0))
+    }
+
+    fn kind(&self) -> TextureKind {
+        TextureKind::Gradient(*self)
+    }
+}
+
+fn lerp(a: Color, b: Color, t: f32) -> Color {
+    (1.0 - t) * a + t * b
+}
+
+/// Decode an 8 or 16-bit image, undoing the sRGB transfer curve if it was applied
+fn read_ldr(path: &Path, color_space: ColorSpace) -> Result<(usize, usize, Vec<Color>), String> {
+    use image::DynamicImage::*;
+
+    let image = image::open(path).map_err(|err| err.to_string())?;
+    let decode = |c: f32| match color_space {
+        ColorSpace::Srgb => srgb_decode(c),
+        ColorSpace::Linear => c,
+    };
+    // Each bit depth is read as it is, since widening 8-bit channels to 16 bits shifts
+    // them up without filling the low byte and leaves white short of one
+    let (width, height, pixels) = match image {
+        ImageLuma16(_) | ImageLumaA16(_) | ImageRgb16(_) | ImageRgba16(_) => {
+            let image = image.to_rgb16();
+            let channel = |c: u16| decode(c as f32 / u16::MAX as f32);
+            let pixels = image
+                .pixels()
+                .map(|p| Color::new(channel(p[0]), channel(p[1]), channel(p[2])))
+                .collect();
+            (image.width(), image.height(), pixels)
+        }
+        _ => {
+            let image = image.to_rgb8();
+            let channel = |c: u8| decode(c as f32 / u8::MAX as f32);
+            let pixels = image
+                .pixels()
+                .map(|p| Color::new(channel(p[0]), channel(p[1]), channel(p[2])))
+                .collect();
+            (image.width(), image.height(), pixels)
+        }
+    };
+    Ok((width as usize, height as usize, pixels))
+}
+
+/// Inverse of the sRGB transfer curve
+fn srgb_decode(c: f32) -> f32 {
+    if c <= 0.040_45 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Gradient noise of Perlin's improved noise, roughly in `[-1, 1]`. Lattice gradients come
+/// from a hash of the lattice point instead of a permutation table, so the noise needs no
+/// setup and is the same in every run.
+pub fn perlin(p: Point3) -> f32 {
+    let cell = p.map(f32::floor);
+    let f = p - cell;
+    let (x, y, z) = (cell.x as i32, cell.y as i32, cell.z as i32);
+    let fade = f.map(|t| t * t * t * (t * (t * 6.0 - 15.0) + 10.0));
+
+    let corner = |dx: i32, dy: i32, dz: i32| {
+        let offset = f - Vec3::new(dx as f32, dy as f32, dz as f32);
+        gradient(lattice_hash(x + dx, y + dy, z + dz), offset)
+    };
+    let mix = |a: f32, b: f32, t: f32| a + t * (b - a);
+
+    let x00 = mix(corner(0, 0, 0), corner(1, 0, 0), fade.x);
+    let x10 = mix(corner(0, 1, 0), corner(1, 1, 0), fade.x);
+    let x01 = mix(corner(0, 0, 1), corner(1, 0, 1), fade.x);
+    let x11 = mix(corner(0, 1, 1), corner(1, 1, 1), fade.x);
+    mix(mix(x00, x10, fade.y), mix(x01, x11, fade.y), fade.z)
+}
+
+/// Perlin noise summed over `octaves` octaves, each at twice the frequency and half the
+/// amplitude of the one before, normalized back to roughly `[-1, 1]`
+pub fn fbm(p: Point3, octaves: u32) -> f32 {
+    let (mut sum, mut amplitude, mut total, mut frequency) = (0.0, 1.0, 0.0, 1.0);
+    for _ in 0..octaves.max(1) {
+        sum += amplitude * perlin(frequency * p);
+        total += amplitude;
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+    sum / total
+}
+
+/// Like `fbm`, but summing the absolute value of each octave, which gives sharp creases
+pub fn turbulence(p: Point3, octaves: u32) -> f32 {
+    let (mut sum, mut amplitude, mut total, mut frequency) = (0.0, 1.0, 0.0, 1.0);
+    for _ in 0..octaves.max(1) {
+        sum += amplitude * perlin(frequency * p).abs();
+        total += amplitude;
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+    sum / total
+}
+
+fn lattice_hash(x: i32, y: i32, z: i32) -> u32 {
+    let mut h = (x as u32).wrapping_mul(0x8da6_b343)
+        ^ (y as u32).wrapping_mul(0xd816_3841)
+        ^ (z as u32).wrapping_mul(0xcb1a_b31f);
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x7feb_352d);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x846c_a68b);
+    h ^ (h >> 16)
+}
+
+/// Dot product of `offset` with one of the twelve gradients toward the edges of a cube
+fn gradient(hash: u32, offset: Vec3) -> f32 {
+    let (x, y, z) = (offset.x, offset.y, offset.z);
+    match hash % 12 {
+        0 => x + y,
+        1 => -x + y,
+        2 => x - y,
+        3 => -x - y,
+        4 => x + z,
+        5 => -x + z,
+        6 => x - z,
+        7 => -x - z,
+        8 => y + z,
+        9 => -y + z,
+        10 => y - z,
+        _ => -y - z,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Texel at the center of a 1x1 image, read in `color_space`
+    fn texel(file: &str, image: image::DynamicImage, color_space: ColorSpace) -> Color {
+        let path = std::env::temp_dir().join(format!("traci_{}_{}", std::process::id(), file));
+        image.save(&path).unwrap();
+        let texture = ImageTexture::load(&path, WrapMode::Clamp, color_space);
+        std::fs::remove_file(&path).unwrap();
+        texture
+            .unwrap()
+            .value(Vec2::new(0.5, 0.5), Point3::new(0.0, 0.0, 0.0))
+    }
+
+    #[test]
+    fn ldr_images_decode_full_range() {
+        let rgb8 = image::RgbImage::from_pixel(1, 1, image::Rgb([255, 0, 51]));
+        let rgb16 = image::ImageBuffer::from_pixel(1, 1, image::Rgb([65535u16, 0, 13107]));
+        for (file, image) in [
+            ("texel8.png", image::DynamicImage::ImageRgb8(rgb8)),
+            ("texel16.png", image::DynamicImage::ImageRgb16(rgb16)),
+        ] {
+            let linear = texel(file, image.clone(), ColorSpace::Linear);
+            assert_eq!(linear, Color::new(1.0, 0.0, 0.2), "{}", file);
+
+            let srgb = texel(file, image, ColorSpace::Srgb);
+            assert_eq!((srgb.x, srgb.y), (1.0, 0.0), "{}", file);
+            assert!((srgb.z - 0.033105).abs() < 1.0e-5, "{} {}", file, srgb.z);
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+/// Rectangular block of pixels rendered as one unit of work, in image coordinates with
+/// the origin in the top left corner
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Tile {
+    pub fn pixels(&self) -> impl Iterator<Item = (u32, u32)> {
+        let (x0, y0, width, height) = (self.x, self.y, self.width, self.height);
+        (y0..y0 + height).flat_map(move |y| (x0..x0 + width).map(move |x| (x, y)))
+    }
+
+    pub fn len(&self) -> usize {
+        self.width as usize * self.height as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Order in which tiles are handed out to the worker threads
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TileOrder {
+    /// Row by row from the top left corner
+    Scanline,
+    /// Outward from the center of the image, which usually shows the subject first
+    Spiral,
+    /// Along a Hilbert curve, keeping consecutive tiles next to each other for better
+    /// cache locality
+    Hilbert,
+}
+
+impl FromStr for TileOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "scanline" => Ok(TileOrder::Scanline),
+            "spiral" => Ok(TileOrder::Spiral),
+            "hilbert" => Ok(TileOrder::Hilbert),
+            _ => Err(format!(
+                "unknown tile order `{}`, expected `scanline`, `spiral` or `hilbert`",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for TileOrder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TileOrder::Scanline => write!(f, "scanline"),
+            TileOrder::Spiral => write!(f, "spiral"),
+            TileOrder::Hilbert => write!(f, "hilbert"),
+        }
+    }
+}
+
+/// Split an image into tiles of at most `tile_size` by `tile_size` pixels, sorted in the
+/// given order
+pub fn tiles(width: u32, height: u32, tile_size: u32, order: TileOrder) -> Vec<Tile> {
+    let tile_size = tile_size.max(1);
+    let columns = width.div_ceil(tile_size);
+    let rows = height.div_ceil(tile_size);
+
+    let mut grid: Vec<(u32, u32)> = (0..rows)
+        .flat_map(|row| (0..columns).map(move |column| (column, row)))
+        .collect();
+
+    match order {
+        TileOrder::Scanline => {}
+        TileOrder::Spiral => {
+            let center_x = (columns as f32 - 1.0) / 2.0;
+            let center_y = (rows as f32 - 1.0) / 2.0;
+            let key = |&(column, row): &(u32, u32)| {
+                let dx = column as f32 - center_x;
+                let dy = row as f32 - center_y;
+                // Square rings around the center, each walked clockwise
+                (dx.abs().max(dy.abs()), dy.atan2(dx))
+            };
+            grid.sort_by(|a, b| {
+                let (ring_a, angle_a) = key(a);
+                let (ring_b, angle_b) = key(b);
+                ring_a
+                    .partial_cmp(&ring_b)
+                    .unwrap_or(Ordering::Equal)
+                    .then(angle_a.partial_cmp(&angle_b).unwrap_or(Ordering::Equal))
+            });
+        }
+        TileOrder::Hilbert => {
+            let side = columns.max(rows).next_power_of_two();
+            grid.sort_by_key(|&(column, row)| hilbert_index(side, column, row));
+        }
+    }
+
+    grid.into_iter()
+        .map(|(column, row)| {
+            let x = column * tile_size;
+            let y = row * tile_size;
+            Tile {
+                x,
+                y,
+                width: tile_size.min(width - x),
+                height: tile_size.min(height - y),
+            }
+        })
+        .collect()
+}
+
+/// Distance along the Hilbert curve filling a `side` by `side` grid, where `side` is a
+/// power of two
+fn hilbert_index(side: u32, mut x: u32, mut y: u32) -> u64 {
+    let mut index = 0;
+    let mut s = side / 2;
+    while s > 0 {
+        let rx = (x & s > 0) as u32;
+        let ry = (y & s > 0) as u32;
+        index += s as u64 * s as u64 * ((3 * rx) ^ ry) as u64;
+        // Rotate the quadrant so that the curve stays connected
+        if ry == 0 {
+            if rx == 1 {
+                x = side - 1 - x;
+                y = side - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    index
+}
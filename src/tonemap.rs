@@ -0,0 +1,245 @@
+//! Conversion of the linear high dynamic range film to display colors.
+//!
+//! Colors are scaled by the exposure, compressed into the displayable range by a tone
+//! mapping operator, encoded with the sRGB transfer curve and finally quantized, with
+//! optional dithering to hide banding in smooth gradients.
+
+use crate::vec3::*;
+
+use std::fmt;
+use std::str::FromStr;
+
+/// Operator that maps scene luminances onto the `[0, 1]` display range
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ToneMap {
+    /// Clip every channel at one
+    Clamp,
+    /// `L / (1 + L)` applied to the luminance, which never quite reaches white
+    Reinhard,
+    /// Reinhard with a white point, the luminance that maps to exactly one
+    ExtendedReinhard,
+    /// Fit of the ACES reference rendering and sRGB output transforms (Stephen Hill)
+    Aces,
+    /// AgX filmic curve, which desaturates highlights gracefully instead of skewing hues
+    Agx,
+}
+
+impl FromStr for ToneMap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "clamp" => Ok(ToneMap::Clamp),
+            "reinhard" => Ok(ToneMap::Reinhard),
+            "reinhard-extended" => Ok(ToneMap::ExtendedReinhard),
+            "aces" => Ok(ToneMap::Aces),
+            "agx" => Ok(ToneMap::Agx),
+            _ => Err(format!(
+                "unknown tone mapping operator `{}`, expected `clamp`, `reinhard`, `reinhard-extended`, `aces` or `agx`",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ToneMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ToneMap::Clamp => write!(f, "clamp"),
+            ToneMap::Reinhard => write!(f, "reinhard"),
+            ToneMap::ExtendedReinhard => write!(f, "reinhard-extended"),
+            ToneMap::Aces => write!(f, "aces"),
+            ToneMap::Agx => write!(f, "agx"),
+        }
+    }
+}
+
+/// Bits per channel of integer output images
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BitDepth {
+    Eight,
+    Sixteen,
+}
+
+impl BitDepth {
+    /// Largest value of a channel
+    pub fn max_value(self) -> f32 {
+        match self {
+            BitDepth::Eight => u8::MAX as f32,
+            BitDepth::Sixteen => u16::MAX as f32,
+        }
+    }
+}
+
+impl FromStr for BitDepth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "8" => Ok(BitDepth::Eight),
+            "16" => Ok(BitDepth::Sixteen),
+            _ => Err(format!(
+                "unsupported bit depth `{}`, expected `8` or `16`",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for BitDepth {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BitDepth::Eight => write!(f, "8"),
+            BitDepth::Sixteen => write!(f, "16"),
+        }
+    }
+}
+
+/// Steps applied to turn a linear film into an integer image
+#[derive(Copy, Clone, Debug)]
+pub struct OutputTransform {
+    /// Exposure adjustment in stops; every stop doubles the brightness
+    pub exposure: f32,
+    pub tone_map: ToneMap,
+    /// Luminance that becomes white under `ToneMap::ExtendedReinhard`
+    pub white_point: f32,
+    /// Add noise of up to one quantization step before rounding
+    pub dither: bool,
+    pub bit_depth: BitDepth,
+}
+
+impl Default for OutputTransform {
+    fn default() -> Self {
+        OutputTransform {
+            exposure: 0.0,
+            tone_map: ToneMap::Clamp,
+            white_point: 4.0,
+            dither: false,
+            bit_depth: BitDepth::Eight,
+        }
+    }
+}
+
+impl OutputTransform {
+    /// Display color of a linear color, encoded with the sRGB curve and in `[0, 1]`
+    pub fn apply(&self, color: Color) -> Color {
+        let exposed =
+            color.map(|c| if c.is_finite() { c.max(0.0) } else { 0.0 }) * self.exposure.exp2();
+        let mapped = match self.tone_map {
+            ToneMap::Clamp => exposed,
+            ToneMap::Reinhard => scale_luminance(exposed, |l| l / (1.0 + l)),
+            ToneMap::ExtendedReinhard => {
+                let white = self.white_point.max(1.0e-3);
+                scale_luminance(exposed, |l| l * (1.0 + l / (white * white)) / (1.0 + l))
+            }
+            ToneMap::Aces => aces(exposed),
+            ToneMap::Agx => agx(exposed),
+        };
+        mapped.map(|c| srgb_encode(c.clamp(0.0, 1.0)))
+    }
+
+    /// Integer value of one channel of the pixel at `(x, y)`, between zero and
+    /// `bit_depth.max_value()`
+    pub fn quantize(&self, value: f32, x: u32, y: u32, channel: u32) -> f32 {
+        let max = self.bit_depth.max_value();
+        let offset = if self.dither {
+            // Triangular noise from two uniform values, which makes the error independent
+            // of the signal
+            let a = dither_noise(x, y, 2 * channel);
+            let b = dither_noise(x, y, 2 * channel + 1);
+            a - b
+        } else {
+            0.0
+        };
+        (value * max + offset).round().clamp(0.0, max)
+    }
+}
+
+/// Scale a color so that its luminance becomes `curve(luminance)`, keeping its hue
+fn scale_luminance(color: Color, curve: impl Fn(f32) -> f32) -> Color {
+    let l = luminance(color);
+    if l <= 0.0 {
+        color
+    } else {
+        color * (curve(l) / l)
+    }
+}
+
+/// sRGB transfer curve: linear near black, then a 1/2.4 power
+fn srgb_encode(c: f32) -> f32 {
+    if c <= 0.003_130_8 {
+        12.92 * c
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn multiply(matrix: &[[f32; 3]; 3], color: Color) -> Color {
+    Color::new(
+        matrix[0][0] * color.x + matrix[0][1] * color.y + matrix[0][2] * color.z,
+        matrix[1][0] * color.x + matrix[1][1] * color.y + matrix[1][2] * color.z,
+        matrix[2][0] * color.x + matrix[2][1] * color.y + matrix[2][2] * color.z,
+    )
+}
+
+fn aces(color: Color) -> Color {
+    // sRGB to the ACES working space, with the exposure boost of the reference transform
+    const INPUT: [[f32; 3]; 3] = [
+        [0.597_19, 0.354_58, 0.048_23],
+        [0.076_00, 0.908_34, 0.015_66],
+        [0.028_40, 0.133_83, 0.837_77],
+    ];
+    const OUTPUT: [[f32; 3]; 3] = [
+        [1.604_75, -0.531_08, -0.073_67],
+        [-0.102_08, 1.108_13, -0.006_05],
+        [-0.003_27, -0.072_76, 1.076_02],
+    ];
+    let v = multiply(&INPUT, color);
+    let fitted = v.map(|v| {
+        (v * (v + 0.024_578_6) - 0.000_090_537) / (v * (0.983_729 * v + 0.432_951) + 0.238_081)
+    });
+    multiply(&OUTPUT, fitted)
+}
+
+fn agx(color: Color) -> Color {
+    // Inset into the AgX working space, log encoding over a fixed range of stops, then a
+    // polynomial fit of the AgX contrast curve (Wrensch's minimal AgX)
+    const INSET: [[f32; 3]; 3] = [
+        [0.842_479_1, 0.078_433_6, 0.079_223_75],
+        [0.042_328_24, 0.878_468_6, 0.079_166_13],
+        [0.042_375_65, 0.078_433_6, 0.879_143],
+    ];
+    const OUTSET: [[f32; 3]; 3] = [
+        [1.196_879, -0.098_020_88, -0.099_029_74],
+        [-0.052_896_85, 1.151_903_1, -0.098_961_18],
+        [-0.052_971_64, -0.098_043_45, 1.151_073_7],
+    ];
+    const MIN_EV: f32 = -12.473_931;
+    const MAX_EV: f32 = 4.026_069;
+
+    let encoded = multiply(&INSET, color).map(|c| {
+        let ev = c.max(1.0e-10).log2().clamp(MIN_EV, MAX_EV);
+        let x = (ev - MIN_EV) / (MAX_EV - MIN_EV);
+        let x2 = x * x;
+        let x4 = x2 * x2;
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+            - 0.00232
+    });
+    // The curve produces display values, which are linearized again so that the sRGB
+    // encoding applied afterwards is shared with the other operators
+    multiply(&OUTSET, encoded).map(|c| c.max(0.0).powf(2.2))
+}
+
+/// Uniform noise in `[0, 1)` that depends only on the pixel and channel, so dithered
+/// images are reproducible
+fn dither_noise(x: u32, y: u32, channel: u32) -> f32 {
+    let mut h = x.wrapping_mul(0x8da6_b343)
+        ^ y.wrapping_mul(0xd816_3841)
+        ^ channel.wrapping_mul(0xcb1a_b31f);
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x7feb_352d);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x846c_a68b);
+    h ^= h >> 16;
+    (h >> 8) as f32 / (1u32 << 24) as f32
+}
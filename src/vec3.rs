@@ -1,12 +1,15 @@
 extern crate approx;
 extern crate nalgebra as na;
 
+use crate::sampler::Sampler;
+
 use image::{Rgb, Rgba};
-use rand_distr::{Distribution, UnitBall, UnitDisc, UnitSphere};
+use std::f32::consts::{FRAC_PI_2, FRAC_PI_4, PI};
 
 // use glam::Vec3;
-use na::Vector3;
+use na::{Vector2, Vector3};
 
+pub type Vec2 = Vector2<f32>;
 pub type Vec3 = Vector3<f32>;
 pub type Color = Vector3<f32>;
 // pub type Point3 = na::Point3<f32>;
@@ -39,23 +42,30 @@ impl ColorVec for Vec3 {
     }
 }
 
+/// Perceived brightness of a linear sRGB color
+pub fn luminance(color: Color) -> f32 {
+    0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z
+}
+
 pub trait PointVec {}
 
 impl PointVec for Vec3 {}
 
-pub fn random_in_unit_sphere() -> Vec3 {
-    let [x, y, z] = UnitBall.sample(&mut rand::thread_rng());
-    Vec3::new(x, y, z)
+pub fn random_in_unit_sphere(sampler: &mut dyn Sampler) -> Vec3 {
+    let direction = random_on_unit_sphere(sampler);
+    direction * sampler.next_1d().cbrt()
 }
 
-pub fn random_on_unit_sphere() -> Vec3 {
-    let [x, y, z] = UnitSphere.sample(&mut rand::thread_rng());
-    Vec3::new(x, y, z)
+pub fn random_on_unit_sphere(sampler: &mut dyn Sampler) -> Vec3 {
+    let (u1, u2) = sampler.next_2d();
+    let z = 1.0 - 2.0 * u1;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * u2;
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
 }
 
-pub fn random_in_unit_hemisphere(normal: Vec3) -> Vec3 {
-    let [x, y, z] = UnitSphere.sample(&mut rand::thread_rng());
-    let in_unit_sphere = Vec3::new(x, y, z);
+pub fn random_in_unit_hemisphere(normal: Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+    let in_unit_sphere = random_on_unit_sphere(sampler);
     if in_unit_sphere.dot(&normal) > 0.0 {
         in_unit_sphere
     } else {
@@ -63,9 +73,20 @@ pub fn random_in_unit_hemisphere(normal: Vec3) -> Vec3 {
     }
 }
 
-pub fn random_in_unit_disc() -> Vec3 {
-    let [x, y] = UnitDisc.sample(&mut rand::thread_rng());
-    Vec3::new(x, y, 0.0)
+/// Point in the unit disc, using the concentric mapping from the unit square so that
+/// stratified samples stay stratified
+pub fn random_in_unit_disc(sampler: &mut dyn Sampler) -> Vec3 {
+    let (u1, u2) = sampler.next_2d();
+    let (a, b) = (2.0 * u1 - 1.0, 2.0 * u2 - 1.0);
+    if a == 0.0 && b == 0.0 {
+        return Vec3::new(0.0, 0.0, 0.0);
+    }
+    let (r, theta) = if a.abs() > b.abs() {
+        (a, FRAC_PI_4 * (b / a))
+    } else {
+        (b, FRAC_PI_2 - FRAC_PI_4 * (a / b))
+    };
+    Vec3::new(r * theta.cos(), r * theta.sin(), 0.0)
 }
 
 pub fn refract(direction: Vec3, surface_normal: Vec3, etai_over_etat: f32) -> Vec3 {
@@ -78,3 +99,23 @@ pub fn refract(direction: Vec3, surface_normal: Vec3, etai_over_etat: f32) -> Ve
 pub fn reflect(direction: Vec3, surface_normal: Vec3) -> Vec3 {
     direction - 2.0 * direction.dot(&surface_normal) * surface_normal
 }
+
+/// Cosine weighted direction around the unit `normal` for the uniform numbers `(u1, u2)`
+pub fn cosine_direction(normal: Vec3, (u1, u2): (f32, f32)) -> Vec3 {
+    let r = u1.sqrt();
+    let phi = 2.0 * PI * u2;
+    let (tangent, bitangent) = orthonormal_basis(normal);
+    r * phi.cos() * tangent + r * phi.sin() * bitangent + (1.0 - u1).max(0.0).sqrt() * normal
+}
+
+/// Two unit vectors perpendicular to `normal` and to each other
+pub fn orthonormal_basis(normal: Vec3) -> (Vec3, Vec3) {
+    let helper = if normal.x.abs() > 0.9 {
+        Vec3::new(0.0, 1.0, 0.0)
+    } else {
+        Vec3::new(1.0, 0.0, 0.0)
+    };
+    let tangent = normal.cross(&helper).normalize();
+    let bitangent = normal.cross(&tangent);
+    (tangent, bitangent)
+}